@@ -0,0 +1,112 @@
+use std::fs;
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rusted_chip8::chip8::Chip8;
+
+const INSTRUCTIONS: u64 = 100_000;
+
+/**
+Programs that never halt, so they can run for any number of instructions.
+`roms/` holds real games, the synthetic ones isolate specific instructions.
+ */
+fn programs() -> Vec<(String, Vec<u8>)> {
+    let mut programs = vec![
+        // V0 += 1, V1 ^= V0, jump back.
+        ("alu loop".to_string(), opcodes(&[0x7001, 0x8103, 0x1200])),
+        // I := 0 digit, draw it at (V0, V0) 5 rows high, V0 += 1, jump back.
+        ("draw loop".to_string(), opcodes(&[0xA000, 0xD005, 0x7001, 0x1202])),
+    ];
+
+    for entry in fs::read_dir("roms").expect("roms directory") {
+        let path = entry.expect("rom entry").path();
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        programs.push((name, fs::read(&path).expect("rom file")));
+    }
+
+    programs
+}
+
+fn opcodes(program: &[u16]) -> Vec<u8> {
+    program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect()
+}
+
+// How instructions are run: decoded every time, from the decode cache, or recompiled.
+#[derive(Clone, Copy)]
+enum Mode {
+    Decoded,
+    Cached,
+    #[cfg(feature = "jit")]
+    Recompiled,
+}
+
+const MODES: &[(&str, Mode)] = &[
+    ("decoded", Mode::Decoded),
+    ("cached", Mode::Cached),
+    #[cfg(feature = "jit")]
+    ("recompiled", Mode::Recompiled),
+];
+
+fn running(program: &[u8], mode: Mode) -> Chip8 {
+    let mut emulator = Chip8::new();
+    emulator.set_decode_cache(!matches!(mode, Mode::Decoded));
+    #[cfg(feature = "jit")]
+    emulator.set_jit(matches!(mode, Mode::Recompiled)).expect("recompiler");
+    emulator.load(program.to_vec());
+    emulator.start();
+    emulator
+}
+
+// Instructions per second for every program and every way of running them. The recompiler is
+// only used by `run_frame`, so the instructions all run in one long frame.
+fn bench_instructions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("instructions");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    for (name, program) in programs() {
+        for &(mode_name, mode) in MODES {
+            group.bench_with_input(BenchmarkId::new(&name, mode_name), &program, |b, program| {
+                b.iter_batched_ref(
+                    || running(program, mode),
+                    |emulator| emulator.run_frame(INSTRUCTIONS as u32),
+                    criterion::BatchSize::LargeInput,
+                );
+            });
+        }
+    }
+    group.finish();
+}
+
+// Sprites drawn per second, by sprite height.
+fn bench_sprites(c: &mut Criterion) {
+    const SPRITES: u64 = 10_000;
+    let mut group = c.benchmark_group("sprites");
+    group.throughput(Throughput::Elements(SPRITES));
+    for height in [1u16, 5, 15] {
+        let program = opcodes(&[0xD010 | height, 0x7003, 0x1200]);
+        group.bench_with_input(BenchmarkId::new("rows", height), &program, |b, program| {
+            b.iter_batched_ref(
+                || running(program, Mode::Cached),
+                |emulator| {
+                    for _ in 0..SPRITES * 3 {
+                        emulator.tick();
+                    }
+                },
+                criterion::BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn bench_snapshots(c: &mut Criterion) {
+    let (_, program) = programs().swap_remove(0);
+    let mut emulator = running(&program, Mode::Decoded);
+    emulator.run_frame(1000);
+
+    c.bench_function("snapshot", |b| b.iter(|| black_box(emulator.snapshot())));
+
+    let snapshot = emulator.snapshot();
+    c.bench_function("restore", |b| b.iter(|| emulator.restore(black_box(&snapshot))));
+}
+
+criterion_group!(benches, bench_instructions, bench_sprites, bench_snapshots);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rusted_chip8_fuzz::{run_frame, Engine, Run};
+
+// Runs every engine in lockstep and fails on the first frame after which their states differ.
+fuzz_target!(|run: Run| {
+    let mut emulators: Vec<_> = Engine::ALL.iter().map(|&engine| (engine, run.emulator(engine))).collect();
+    for (frame, keys) in run.frames().enumerate() {
+        let executed: Vec<u32> = emulators.iter_mut().map(|(_, emulator)| run_frame(emulator, keys)).collect();
+        let (reference, expected) = &emulators[0];
+        for ((engine, emulator), &count) in emulators.iter().zip(&executed).skip(1) {
+            assert_eq!(count, executed[0], "{:?} and {:?} ran different instruction counts in frame {}", engine, reference, frame);
+            assert!(emulator.snapshot() == expected.snapshot(), "{:?} diverged from {:?} in frame {}", engine, reference, frame);
+        }
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rusted_chip8_fuzz::{run_frame, Engine, Run};
+
+// Any ROM must run on any platform, whatever the keys pressed, without panicking.
+fuzz_target!(|run: Run| {
+    let mut emulator = run.emulator(Engine::Cached);
+    for keys in run.frames() {
+        run_frame(&mut emulator, keys);
+    }
+});
@@ -0,0 +1,107 @@
+/*!
+Inputs and engines shared by the fuzz targets.
+ */
+use arbitrary::{Arbitrary, Unstructured};
+use rusted_chip8::chip8::{Chip8, Font, Platform};
+
+const PLATFORMS: [Platform; 11] = [
+    Platform::OriginalChip8,
+    Platform::HybridVip,
+    Platform::ModernChip8,
+    Platform::Chip8X,
+    Platform::Chip48,
+    Platform::SuperChip1,
+    Platform::SuperChip,
+    Platform::MegaChip8,
+    Platform::XoChip,
+    Platform::HiResChip8,
+    Platform::Chip8E,
+];
+
+// Long enough for timers and FX0A to matter, short enough to keep executions fast.
+const MAX_FRAMES: usize = 32;
+const INSTRUCTIONS_PER_FRAME: u32 = 64;
+
+/**
+A ROM, the platform it runs on, the seed of CXNN and the keys held during each frame, one bit
+per key. The program runs for as many frames as there are key states.
+ */
+#[derive(Debug)]
+pub struct Run {
+    platform: u8,
+    seed: u64,
+    keys: Vec<u16>,
+    rom: Vec<u8>,
+}
+
+// The ROM is the raw end of the input, so mutations of the input are mutations of the program.
+impl<'a> Arbitrary<'a> for Run {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Run> {
+        let platform = u.arbitrary()?;
+        let seed = u.arbitrary()?;
+        let frames = u.int_in_range(1..=MAX_FRAMES)?;
+        let keys = (0..frames).map(|_| u.arbitrary()).collect::<arbitrary::Result<_>>()?;
+        let rom = u.bytes(u.len())?.to_vec();
+        Ok(Run { platform, seed, keys, rom })
+    }
+}
+
+impl Run {
+    pub fn platform(&self) -> Platform {
+        PLATFORMS[self.platform as usize % PLATFORMS.len()]
+    }
+
+    // The ROM loaded in an emulator set up for its platform like the frontend does.
+    pub fn emulator(&self, engine: Engine) -> Chip8 {
+        let platform = self.platform();
+        let mut emulator = Chip8::new();
+        emulator.set_platform(platform);
+        engine.configure(&mut emulator);
+        emulator.set_quirks(platform.quirks());
+        emulator.set_font(Font::builtin(platform.font_style()));
+        emulator.seed_rng(self.seed);
+        emulator.load(self.rom.clone());
+        emulator.start();
+        emulator
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = u16> + '_ {
+        self.keys.iter().copied()
+    }
+}
+
+// Holds the keys set in `keys`, releases the others and runs a frame.
+pub fn run_frame(emulator: &mut Chip8, keys: u16) -> u32 {
+    for key in 0..16 {
+        if keys & (1 << key) != 0 {
+            emulator.press_key(key);
+        } else {
+            emulator.release_key(key);
+        }
+    }
+    emulator.run_frame(INSTRUCTIONS_PER_FRAME)
+}
+
+// The ways `Chip8` can run a program, which must all give the same results.
+#[derive(Clone, Copy, Debug)]
+pub enum Engine {
+    Decoded,
+    Cached,
+    #[cfg(feature = "jit")]
+    Recompiled,
+}
+
+impl Engine {
+    pub const ALL: &'static [Engine] = &[
+        Engine::Decoded,
+        Engine::Cached,
+        #[cfg(feature = "jit")]
+        Engine::Recompiled,
+    ];
+
+    fn configure(&self, emulator: &mut Chip8) {
+        emulator.set_decode_cache(!matches!(self, Engine::Decoded));
+        #[cfg(feature = "jit")]
+        emulator.set_jit(matches!(self, Engine::Recompiled)).expect("recompiler");
+    }
+}
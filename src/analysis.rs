@@ -0,0 +1,567 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::chip8::{InstructionSet, Platform};
+
+/**
+What the analysis found a byte of the ROM to be. Bytes no code path reaches are `Unknown`:
+they may be data, or code only reached through a computed jump.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteKind {
+    Unknown,
+    Code,
+    // Drawn by DXYN after ANNN pointed I at it.
+    Sprite,
+    // Read or written by FX33, FX55 or FX65 after ANNN pointed I at it.
+    Data,
+}
+
+// How an edge of the control-flow graph leaves a block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Edge {
+    // Falls through to the next instruction, or continues after a call returns.
+    Next(u16),
+    // A skip instruction skipped the next instruction.
+    Skip(u16),
+    Jump(u16),
+    Call(u16),
+}
+
+impl Edge {
+    pub fn target(&self) -> u16 {
+        match self {
+            Edge::Next(target) | Edge::Skip(target) | Edge::Jump(target) | Edge::Call(target) => *target,
+        }
+    }
+}
+
+/**
+A straight run of instructions entered only at `start`. `end` is the address past its last
+instruction. Blocks ending in a return, a halt or a computed jump have no successors for them.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Block {
+    pub start: u16,
+    pub end: u16,
+    pub successors: Vec<Edge>,
+}
+
+impl Block {
+    // Size in bytes. Blocks at the top of XO-CHIP's memory end past the wrap to 0.
+    pub fn size(&self) -> u16 {
+        self.end.wrapping_sub(self.start)
+    }
+}
+
+// Where an instruction sends the program, as far as it can be told without running it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Flow {
+    Next,
+    // Continues with the next instruction or the one after it.
+    Skip,
+    Jump(u16),
+    // Continues at the target, then at the next instruction once it returns.
+    Call(u16),
+    Return,
+    // Jumps somewhere in `base..=base + 255` depending on a register, like BNNN.
+    Computed(u16),
+    // Stops, or executes an opcode the interpreter doesn't know.
+    Halt,
+}
+
+/**
+Static control-flow analysis of a ROM. Starting from the entry point of the platform, it follows
+jumps, calls, skips and returns to find the reachable code, splits it into basic blocks, and
+finds the subroutines and the sprites and data tables ANNN points I at before DXYN, FX33, FX55
+or FX65 use it.
+
+Computed jumps (BNNN, and CHIP-8E's FX1B) are handled conservatively: their targets depend on a
+register, so they are recorded but not followed, and code only they reach is left `Unknown`
+rather than guessed. Calls are assumed to return. I is only tracked along each path from the
+ANNN that sets it, and the first path reaching an instruction decides what I holds there.
+ */
+pub struct Analysis {
+    image: Vec<u8>,
+    entry: u16,
+    instruction_set: InstructionSet,
+    kinds: Vec<ByteKind>,
+    blocks: BTreeMap<u16, Block>,
+    subroutines: BTreeSet<u16>,
+    jump_targets: BTreeSet<u16>,
+    // Start and length of every sprite drawn, and of every data table used.
+    sprites: BTreeMap<u16, usize>,
+    data: BTreeMap<u16, usize>,
+    // Addresses of computed jumps and the lowest address each one can reach.
+    computed_jumps: BTreeMap<u16, u16>,
+}
+
+impl Analysis {
+    // Analyses `program` as loaded for `platform`, or at `program_address` like `Chip8::set_program_address`.
+    pub fn new(program: &[u8], platform: Platform, program_address: Option<u16>) -> Analysis {
+        let mut image = vec![0; platform.memory_size()];
+        let origin = program_address.unwrap_or(platform.load_address()) as usize % image.len();
+        let size = program.len().min(image.len() - origin);
+        image[origin..origin + size].copy_from_slice(&program[..size]);
+
+        let mut analysis = Analysis {
+            kinds: vec![ByteKind::Unknown; image.len()],
+            image,
+            entry: program_address.unwrap_or(platform.start_address()),
+            instruction_set: platform.instruction_set(),
+            blocks: BTreeMap::new(),
+            subroutines: BTreeSet::new(),
+            jump_targets: BTreeSet::new(),
+            sprites: BTreeMap::new(),
+            data: BTreeMap::new(),
+            computed_jumps: BTreeMap::new(),
+        };
+        let leaders = analysis.explore();
+        analysis.build_blocks(leaders);
+        analysis
+    }
+
+    pub fn entry(&self) -> u16 {
+        self.entry
+    }
+
+    pub fn kind(&self, address: u16) -> ByteKind {
+        self.kinds.get(address as usize).copied().unwrap_or(ByteKind::Unknown)
+    }
+
+    // Whether an instruction starts at `address`.
+    pub fn is_instruction(&self, address: u16) -> bool {
+        self.blocks.range(..=address).next_back().is_some_and(|(_, block)| {
+            address.wrapping_sub(block.start) < block.size() && self.instructions(block).any(|instruction| instruction == address)
+        })
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    pub fn block(&self, start: u16) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    // Addresses called with 2NNN.
+    pub fn subroutines(&self) -> &BTreeSet<u16> {
+        &self.subroutines
+    }
+
+    // Start address and length of every sprite drawn by DXYN.
+    pub fn sprites(&self) -> &BTreeMap<u16, usize> {
+        &self.sprites
+    }
+
+    // Start address and length of every table read or written by FX33, FX55 or FX65.
+    pub fn data(&self) -> &BTreeMap<u16, usize> {
+        &self.data
+    }
+
+    // Addresses of computed jumps, with the lowest address each one can reach.
+    pub fn computed_jumps(&self) -> &BTreeMap<u16, u16> {
+        &self.computed_jumps
+    }
+
+    pub fn opcode_at(&self, address: u16) -> u16 {
+        let byte = |address: usize| self.image.get(address % self.image.len()).copied().unwrap_or(0);
+        ((byte(address as usize) as u16) << 8) | byte(address as usize + 1) as u16
+    }
+
+    /**
+    Names for the addresses instructions refer to, for disassemblers: the entry point, subroutines,
+    jump targets, sprites and data tables.
+     */
+    pub fn labels(&self) -> BTreeMap<u16, String> {
+        let mut labels = BTreeMap::new();
+        for address in self.data.keys() {
+            labels.insert(*address, format!("data_{:03X}", address));
+        }
+        for address in self.sprites.keys() {
+            labels.insert(*address, format!("sprite_{:03X}", address));
+        }
+        for address in &self.jump_targets {
+            labels.insert(*address, format!("label_{:03X}", address));
+        }
+        for address in &self.subroutines {
+            labels.insert(*address, format!("sub_{:03X}", address));
+        }
+        labels.insert(self.entry, "main".to_string());
+        labels
+    }
+
+    /**
+    Where execution continues once the instruction at `pc` has run, without stopping inside
+    what it calls: the return address for a call, or `None` for any other instruction, which a
+    debugger steps over by executing it once.
+     */
+    pub fn step_over(&self, pc: u16) -> Option<u16> {
+        match self.flow(pc) {
+            Flow::Call(_) => Some(pc.wrapping_add(2)),
+            _ => None,
+        }
+    }
+
+    // The control-flow graph in graphviz DOT format, with a node per block.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box fontname=monospace];\n");
+        let labels = self.labels();
+        for block in self.blocks.values() {
+            let name = labels.get(&block.start).map(|label| format!("{}\\n", label)).unwrap_or_default();
+            let _ = writeln!(dot, "    b{:03X} [label=\"{}{:03X}-{:03X}\"];", block.start, name, block.start, block.end.wrapping_sub(1));
+        }
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let style = match edge {
+                    Edge::Next(_) => "",
+                    Edge::Skip(_) => " [label=skip]",
+                    Edge::Jump(_) => " [label=jump]",
+                    Edge::Call(_) => " [label=call style=dashed]",
+                };
+                let _ = writeln!(dot, "    b{:03X} -> b{:03X}{};", block.start, edge.target(), style);
+            }
+            if let Some(base) = self.instructions(block).last().and_then(|address| self.computed_jumps.get(&address)) {
+                let _ = writeln!(dot, "    b{:03X} -> computed_{:03X} [style=dotted];", block.start, base);
+                let _ = writeln!(dot, "    computed_{:03X} [label=\"{:03X}+V\" shape=ellipse];", base, base);
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn size_at(&self, address: u16) -> u16 {
+        self.instruction_set.instruction_size(self.opcode_at(address))
+    }
+
+    // Addresses of the instructions of `block`.
+    pub fn instructions<'a>(&'a self, block: &'a Block) -> impl Iterator<Item = u16> + 'a {
+        let mut address = block.start;
+        std::iter::from_fn(move || {
+            if address.wrapping_sub(block.start) >= block.size() {
+                return None;
+            }
+            let instruction = address;
+            address = address.wrapping_add(self.size_at(address));
+            Some(instruction)
+        })
+    }
+
+    // Follows every path from the entry point, marking code and what I points at.
+    // Returns the addresses that start a block.
+    fn explore(&mut self) -> BTreeSet<u16> {
+        let mut leaders = BTreeSet::from([self.entry]);
+        let mut visited = vec![false; self.image.len()];
+        // Addresses to visit, with the value of I when it is known.
+        let mut pending = vec![(self.entry, None)];
+
+        while let Some((address, mut i)) = pending.pop() {
+            let mut address = address;
+            loop {
+                if address as usize + 1 >= self.image.len() || visited[address as usize] {
+                    break;
+                }
+                visited[address as usize] = true;
+                let size = self.size_at(address);
+                for offset in 0..size {
+                    if let Some(kind) = self.kinds.get_mut(address as usize + offset as usize) {
+                        *kind = ByteKind::Code;
+                    }
+                }
+
+                let opcode = self.opcode_at(address);
+                i = self.track_i(address, opcode, i);
+                let next = address.wrapping_add(size);
+                match self.flow(address) {
+                    Flow::Next => {
+                        address = next;
+                        continue;
+                    }
+                    Flow::Skip => {
+                        let skipped = next.wrapping_add(self.size_at(next));
+                        leaders.extend([next, skipped]);
+                        pending.push((skipped, i));
+                        pending.push((next, i));
+                    }
+                    Flow::Jump(target) => {
+                        self.jump_targets.insert(target);
+                        leaders.insert(target);
+                        pending.push((target, i));
+                    }
+                    Flow::Call(target) => {
+                        self.subroutines.insert(target);
+                        leaders.extend([target, next]);
+                        // The subroutine may change I.
+                        pending.push((next, None));
+                        pending.push((target, i));
+                    }
+                    Flow::Computed(base) => {
+                        self.computed_jumps.insert(address, base);
+                    }
+                    Flow::Return | Flow::Halt => {}
+                }
+                break;
+            }
+        }
+
+        // Code wins over sprites and data sharing its bytes.
+        for (tables, kind) in [(&self.sprites, ByteKind::Sprite), (&self.data, ByteKind::Data)] {
+            for (start, length) in tables {
+                for address in *start as usize..*start as usize + length {
+                    let kind_at = &mut self.kinds[address % self.image.len()];
+                    if *kind_at == ByteKind::Unknown {
+                        *kind_at = kind;
+                    }
+                }
+            }
+        }
+        leaders
+    }
+
+    // Value of I after `opcode` at `address`, recording the sprites and tables it is used for.
+    fn track_i(&mut self, address: u16, opcode: u16, i: Option<u16>) -> Option<u16> {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let record = |tables: &mut BTreeMap<u16, usize>, length: usize| {
+            if let Some(i) = i {
+                let entry = tables.entry(i).or_insert(0);
+                *entry = (*entry).max(length);
+            }
+        };
+        match opcode & 0xF000 {
+            0xA000 => return Some(opcode & 0x0FFF),
+            // SUPER-CHIP draws a 16x16 sprite of 32 bytes for DXY0.
+            0xD000 => match opcode & 0xF {
+                0 => record(&mut self.sprites, 32),
+                n => record(&mut self.sprites, n as usize),
+            },
+            0xF000 => match opcode & 0xFF {
+                0x00 if self.size_at(address) == 4 => return Some(self.opcode_at(address.wrapping_add(2))),
+                0x33 => record(&mut self.data, 3),
+                // I may move past the table depending on the quirks, so it is forgotten.
+                0x55 | 0x65 => {
+                    record(&mut self.data, x + 1);
+                    return None;
+                }
+                0x1E | 0x29 | 0x30 => return None,
+                _ => {}
+            },
+            _ => {}
+        }
+        i
+    }
+
+    fn flow(&self, address: u16) -> Flow {
+        let opcode = self.opcode_at(address);
+        let nnn = opcode & 0x0FFF;
+        let set = self.instruction_set;
+
+        if set == InstructionSet::Chip8E {
+            match opcode {
+                0x00ED => return Flow::Halt,
+                0x0188 => return Flow::Skip,
+                0x00F2 | 0x0151 => return Flow::Next,
+                _ => {}
+            }
+            match opcode & 0xFF00 {
+                0xBB00 => return Flow::Jump(address.wrapping_sub(opcode & 0xFF)),
+                0xBF00 => return Flow::Jump(address.wrapping_add(opcode & 0xFF)),
+                _ => {}
+            }
+            if opcode & 0xF000 == 0x5000 {
+                // 5XY1 skips, 5XY2 and 5XY3 store and load registers.
+                return if opcode & 0xF == 1 { Flow::Skip } else { Flow::Next };
+            }
+            if opcode & 0xF0FF == 0xF01B {
+                return Flow::Computed(address.wrapping_add(2));
+            }
+        }
+        if set == InstructionSet::Chip8X {
+            if opcode == 0x02A0 || opcode & 0xF00F == 0x5001 || opcode & 0xF000 == 0xB000 {
+                return Flow::Next;
+            }
+            if opcode & 0xF0FF == 0xE0F2 || opcode & 0xF0FF == 0xE0F5 {
+                return Flow::Skip;
+            }
+        }
+        if set == InstructionSet::HiResChip8 && opcode == 0x0230 {
+            return Flow::Next;
+        }
+        if matches!(set, InstructionSet::SuperChip | InstructionSet::XoChip) && opcode == 0x00FF {
+            return Flow::Next;
+        }
+
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Flow::Next,
+                0x00EE => Flow::Return,
+                // Machine code routines, and zeroed memory, are not followed.
+                _ => Flow::Halt,
+            },
+            0x1000 => Flow::Jump(nnn),
+            0x2000 => Flow::Call(nnn),
+            0x3000 | 0x4000 | 0x5000 | 0x9000 => Flow::Skip,
+            0xB000 => Flow::Computed(nnn),
+            0xE000 if matches!(opcode & 0xFF, 0x9E | 0xA1) => Flow::Skip,
+            _ => Flow::Next,
+        }
+    }
+
+    // Splits the code found from `leaders` into blocks and links them.
+    fn build_blocks(&mut self, leaders: BTreeSet<u16>) {
+        for &start in &leaders {
+            if self.kind(start) != ByteKind::Code {
+                continue;
+            }
+            let mut address = start;
+            let successors = loop {
+                let next = address.wrapping_add(self.size_at(address));
+                let flow = self.flow(address);
+                match flow {
+                    Flow::Next if leaders.contains(&next) || self.kind(next) != ByteKind::Code => {
+                        address = next;
+                        break if self.kind(next) == ByteKind::Code { vec![Edge::Next(next)] } else { vec![] };
+                    }
+                    Flow::Next => address = next,
+                    Flow::Skip => {
+                        address = next;
+                        break vec![Edge::Next(next), Edge::Skip(next.wrapping_add(self.size_at(next)))];
+                    }
+                    Flow::Jump(target) => {
+                        address = next;
+                        break vec![Edge::Jump(target)];
+                    }
+                    Flow::Call(target) => {
+                        address = next;
+                        break vec![Edge::Call(target), Edge::Next(next)];
+                    }
+                    Flow::Computed(_) | Flow::Return | Flow::Halt => {
+                        address = next;
+                        break vec![];
+                    }
+                }
+            };
+            self.blocks.insert(start, Block { start, end: address, successors });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyse(program: &[u16]) -> Analysis {
+        let bytes: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        Analysis::new(&bytes, Platform::ModernChip8, None)
+    }
+
+    #[test]
+    fn code_after_an_unconditional_jump_should_stay_unknown() {
+        let analysis = analyse(&[0x6001, 0x1206, 0xF090, 0x00E0, 0x1206]);
+
+        assert_eq!(analysis.kind(0x200), ByteKind::Code);
+        assert_eq!(analysis.kind(0x204), ByteKind::Unknown);
+        assert_eq!(analysis.kind(0x206), ByteKind::Code);
+        assert!(analysis.is_instruction(0x206));
+        assert!(!analysis.is_instruction(0x207));
+    }
+
+    #[test]
+    fn skips_should_split_blocks_with_both_successors() {
+        let analysis = analyse(&[0x3000, 0x6001, 0x6102, 0x1206]);
+
+        assert_eq!(analysis.block(0x200).unwrap().successors, vec![Edge::Next(0x202), Edge::Skip(0x204)]);
+        assert_eq!(analysis.block(0x202).unwrap().successors, vec![Edge::Next(0x204)]);
+        // The jump target starts a block of its own.
+        assert_eq!(analysis.block(0x204), Some(&Block { start: 0x204, end: 0x206, successors: vec![Edge::Next(0x206)] }));
+        assert_eq!(analysis.block(0x206), Some(&Block { start: 0x206, end: 0x208, successors: vec![Edge::Jump(0x206)] }));
+    }
+
+    #[test]
+    fn calls_should_mark_subroutines_and_continue_after_them() {
+        let analysis = analyse(&[0x2206, 0x1202, 0x0000, 0x6001, 0x00EE]);
+
+        assert_eq!(analysis.subroutines(), &BTreeSet::from([0x206]));
+        assert_eq!(analysis.block(0x200).unwrap().successors, vec![Edge::Call(0x206), Edge::Next(0x202)]);
+        assert_eq!(analysis.block(0x206).unwrap().successors, vec![]);
+        assert_eq!(analysis.kind(0x204), ByteKind::Unknown);
+        assert_eq!(analysis.step_over(0x200), Some(0x202));
+        assert_eq!(analysis.step_over(0x206), None);
+    }
+
+    #[test]
+    fn annn_followed_by_dxyn_should_mark_a_sprite() {
+        let analysis = analyse(&[0xA20A, 0xD015, 0xA20F, 0xF165, 0x1208, 0xF090, 0x9090, 0xF012, 0x3400]);
+
+        assert_eq!(analysis.sprites(), &BTreeMap::from([(0x20A, 5)]));
+        assert_eq!(analysis.data(), &BTreeMap::from([(0x20F, 2)]));
+        assert_eq!(analysis.kind(0x209), ByteKind::Code);
+        assert_eq!(analysis.kind(0x20A), ByteKind::Sprite);
+        assert_eq!(analysis.kind(0x20E), ByteKind::Sprite);
+        assert_eq!(analysis.kind(0x210), ByteKind::Data);
+        assert_eq!(analysis.kind(0x211), ByteKind::Unknown);
+    }
+
+    #[test]
+    fn computed_jumps_should_not_be_followed() {
+        let analysis = analyse(&[0x6002, 0xB206, 0x0000, 0x1208, 0x00E0]);
+
+        assert_eq!(analysis.computed_jumps(), &BTreeMap::from([(0x202, 0x206)]));
+        assert_eq!(analysis.kind(0x206), ByteKind::Unknown);
+        assert_eq!(analysis.block(0x200).unwrap().successors, vec![]);
+    }
+
+    #[test]
+    fn labels_should_name_entry_subroutines_targets_and_sprites() {
+        let analysis = analyse(&[0x220A, 0xA20E, 0xD001, 0x1208, 0x1200, 0x00EE, 0xFF00]);
+
+        assert_eq!(analysis.labels(), BTreeMap::from([
+            (0x200, "main".to_string()),
+            (0x208, "label_208".to_string()),
+            (0x20A, "sub_20A".to_string()),
+            (0x20E, "sprite_20E".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn dot_export_should_list_blocks_and_edges() {
+        let dot = analyse(&[0x3000, 0x2206, 0x1200, 0x00EE]).to_dot();
+
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("b200 [label=\"main\\n200-201\"];"));
+        assert!(dot.contains("b200 -> b204 [label=skip];"));
+        assert!(dot.contains("b202 -> b206 [label=call style=dashed];"));
+        assert!(dot.contains("b204 -> b200 [label=jump];"));
+    }
+
+    #[test]
+    fn blocks_should_wrap_at_the_top_of_xo_chip_memory() {
+        let analysis = Analysis::new(&[0x00, 0xE0, 0x12, 0x04], Platform::XoChip, Some(0xFFFC));
+
+        assert_eq!(analysis.block(0xFFFC).unwrap().end, 0x0000);
+        assert!(analysis.is_instruction(0xFFFE));
+        assert_eq!(analysis.instructions(analysis.block(0xFFFC).unwrap()).collect::<Vec<_>>(), vec![0xFFFC, 0xFFFE]);
+        assert!(analysis.to_dot().contains("bFFFC [label=\"main\\nFFFC-FFFF\"];"));
+    }
+
+    #[test]
+    fn skips_should_step_over_f000_nnnn_as_one_instruction_on_xo_chip_only() {
+        let bytes = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x00, 0x12, 0x06];
+
+        let xo_chip = Analysis::new(&bytes, Platform::XoChip, None);
+        assert_eq!(xo_chip.block(0x200).unwrap().successors, vec![Edge::Next(0x202), Edge::Skip(0x206)]);
+        assert!(!xo_chip.is_instruction(0x204));
+
+        let chip8 = Analysis::new(&bytes, Platform::ModernChip8, None);
+        assert_eq!(chip8.block(0x200).unwrap().successors, vec![Edge::Next(0x202), Edge::Skip(0x204)]);
+        assert!(chip8.is_instruction(0x204));
+    }
+
+    #[test]
+    fn chip8e_relative_branches_should_be_followed() {
+        let bytes = [0xBF, 0x04, 0x00, 0x00, 0x60, 0x01, 0xBB, 0x02];
+        let analysis = Analysis::new(&bytes, Platform::Chip8E, None);
+
+        assert_eq!(analysis.block(0x200).unwrap().successors, vec![Edge::Jump(0x204)]);
+        assert_eq!(analysis.kind(0x202), ByteKind::Unknown);
+        assert_eq!(analysis.block(0x204).unwrap().successors, vec![Edge::Jump(0x204)]);
+    }
+}
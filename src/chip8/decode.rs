@@ -0,0 +1,288 @@
+use super::{Chip8, Fault, InstructionSet, ProgramCounter};
+
+// Fields of an opcode, extracted once when it is decoded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Operands {
+    pub opcode: u16,
+    pub x: usize,
+    pub y: usize,
+    pub n: u8,
+    pub nn: u8,
+    pub nnn: u16,
+}
+
+type Handler = fn(&mut Chip8, Operands) -> ProgramCounter;
+
+/**
+An opcode resolved to the handler that executes it, so running it again only takes an
+indirect call. Decoding does not depend on quirks or machine state, only on the opcode and the
+instruction set of the platform.
+ */
+#[derive(Clone, Copy)]
+pub(crate) struct Instruction {
+    handler: Handler,
+    operands: Operands,
+    size: u16,
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16, set: InstructionSet) -> Instruction {
+        let operands = Operands {
+            opcode,
+            x: ((opcode & 0x0F00) >> 8) as usize,
+            y: ((opcode & 0x00F0) >> 4) as usize,
+            n: (opcode & 0x000F) as u8,
+            nn: (opcode & 0x00FF) as u8,
+            nnn: opcode & 0x0FFF,
+        };
+
+        // Variants only list the opcodes they add or replace, the rest decode as on CHIP-8.
+        let handler = match set {
+            InstructionSet::Chip8 => None,
+            InstructionSet::HiResChip8 => hires_chip8_handler(opcode),
+            InstructionSet::Chip8X => chip8x_handler(opcode),
+            InstructionSet::Chip8E => chip8e_handler(opcode),
+            InstructionSet::SuperChip => superchip_handler(opcode),
+            InstructionSet::XoChip => xochip_handler(opcode),
+        };
+
+        Instruction { handler: handler.unwrap_or_else(|| chip8_handler(opcode)), operands, size: set.instruction_size(opcode) }
+    }
+
+    pub fn opcode(&self) -> u16 {
+        self.operands.opcode
+    }
+
+    // Size in bytes, see `InstructionSet::instruction_size`.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn execute(&self, chip8: &mut Chip8) -> ProgramCounter {
+        (self.handler)(chip8, self.operands)
+    }
+}
+
+fn chip8_handler(opcode: u16) -> Handler {
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => |c, _| c.opcode_clear_screen(),
+            0x00EE => |c, _| c.return_from_subroutine(),
+            _ => unknown,
+        },
+        0x1000 => |c, o| c.opcode_jmp(o.nnn),
+        0x2000 => |c, o| c.opcode_call_subroutine(o.nnn),
+        0x3000 => |c, o| c.opcode_skip_if_vx_equals_nn(o.x, o.nn),
+        0x4000 => |c, o| c.opcode_skip_if_vx_diffs_nn(o.x, o.nn),
+        0x5000 => |c, o| c.opcode_skip_if_vx_equals_vy(o.x, o.y),
+        0x6000 => |c, o| c.opcode_set_vx_to_nn(o.x, o.nn),
+        0x7000 => |c, o| c.opcode_adds_nn_to_vx(o.x, o.nn),
+        0x8000 => match opcode & 0x000F {
+            0x0..=0x7 | 0xE => |c, o| c.opcode_set_vx_to_vy(o.opcode, o.x, o.y),
+            _ => unknown,
+        },
+        0x9000 => |c, o| c.opcode_skips_if_vx_diffs_vy(o.x, o.y),
+        0xA000 => |c, o| c.opcode_set_i_to_nnn(o.nnn),
+        0xB000 => |c, o| c.opcode_jmp_nnn_plus_v0(o.nnn),
+        0xC000 => |c, o| c.opcode_set_vx_random(o.x, o.nn),
+        0xD000 => |c, o| c.opcode_draw(o.x, o.y, o.n),
+        0xE000 => match opcode & 0xFF {
+            0x9E => |c, o| c.opcode_skip_key_pressed_in_vx(o.x),
+            0xA1 => |c, o| c.opcode_skip_key_not_pressed_in_vx(o.x),
+            _ => unknown,
+        },
+        _ => match opcode & 0xFF {
+            0x07 => |c, o| c.opcode_save_delay_to_vx(o.x),
+            0x0A => |c, o| c.opcode_wait_key(o.x),
+            0x15 => |c, o| c.opcode_save_vx_to_delay(o.x),
+            0x18 => |c, o| c.opcode_save_vx_to_sound_timer(o.x),
+            0x1E => |c, o| c.opcode_adds_vx_to_i(o.x),
+            0x29 => |c, o| c.opcode_set_i_with_vx(o.x),
+            0x33 => |c, o| c.opcode_save_bin_vx(o.x),
+            0x55 => |c, o| c.opcode_dump_v_to_memory(o.x),
+            0x65 => |c, o| c.opcode_fill_v_with_memory(o.x),
+            _ => unknown,
+        },
+    }
+}
+
+// The VIP hi-res interpreter clears its 64x64 screen with a routine of its own.
+fn hires_chip8_handler(opcode: u16) -> Option<Handler> {
+    let handler: Handler = match opcode {
+        0x0230 => |c, _| c.opcode_clear_screen(),
+        _ => return None,
+    };
+    Some(handler)
+}
+
+// SUPER-CHIP switches to its high resolution mode and points I at its big font.
+fn superchip_handler(opcode: u16) -> Option<Handler> {
+    let handler: Handler = match opcode {
+        0x00FF => |c, _| c.set_schip_graphic_mode(),
+        _ if opcode & 0xF0FF == 0xF030 => |c, o| c.opcode_set_i_with_big_vx(o.x),
+        _ => return None,
+    };
+    Some(handler)
+}
+
+// XO-CHIP loads I with the 16-bit address following F000.
+fn xochip_handler(opcode: u16) -> Option<Handler> {
+    match opcode {
+        0xF000 => Some(|c, _| c.opcode_set_i_long()),
+        _ => superchip_handler(opcode),
+    }
+}
+
+// CHIP-8X gives BXYN to the colour board, so BNNN is gone.
+fn chip8x_handler(opcode: u16) -> Option<Handler> {
+    let handler: Handler = match opcode & 0xF000 {
+        0x0000 if opcode == 0x02A0 => |c, _| c.opcode_cycle_background(),
+        0x5000 if opcode & 0xF == 1 => |c, o| c.opcode_add_vy_to_vx_by_nibble(o.x, o.y),
+        0xB000 => |c, o| c.opcode_set_foreground(o.x, o.y, o.n),
+        0xE000 => match opcode & 0xFF {
+            0xF2 => |c, o| c.opcode_skip_second_key_pressed_in_vx(o.x),
+            0xF5 => |c, o| c.opcode_skip_second_key_not_pressed_in_vx(o.x),
+            _ => return None,
+        },
+        0xF000 => match opcode & 0xFF {
+            0xF8 => |c, o| c.opcode_output_vx(o.x),
+            0xFB => |c, o| c.opcode_input_to_vx(o.x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(handler)
+}
+
+// CHIP-8E gives the B prefix to relative branches, so BNNN is gone.
+fn chip8e_handler(opcode: u16) -> Option<Handler> {
+    let handler: Handler = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00ED => |c, _| c.opcode_stop(),
+            0x00F2 => |_, _| ProgramCounter::Next,
+            0x0151 => |c, _| c.opcode_wait_delay(),
+            0x0188 => |_, _| ProgramCounter::Skip,
+            _ => return None,
+        },
+        0x5000 => match opcode & 0xF {
+            0x1 => |c, o| c.opcode_skip_if_vx_greater_than_vy(o.x, o.y),
+            0x2 => |c, o| c.opcode_store_vx_to_vy(o.x, o.y),
+            0x3 => |c, o| c.opcode_load_vx_to_vy(o.x, o.y),
+            _ => return None,
+        },
+        0xB000 => match opcode & 0x0F00 {
+            0x0B00 => |c, o| c.opcode_branch_back(o.nn),
+            0x0F00 => |c, o| c.opcode_branch_forward(o.nn),
+            _ => unknown,
+        },
+        0xF000 => match opcode & 0xFF {
+            0x03 => |c, o| c.opcode_output_vx(o.x),
+            0x1B => |c, o| c.opcode_skip_vx_bytes(o.x),
+            0x4F => |c, o| c.opcode_wait_vx_frames(o.x),
+            0xE3 => |c, o| c.opcode_input_to_vx(o.x),
+            0xE7 => |c, o| c.opcode_input_to_vx(o.x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(handler)
+}
+
+fn unknown(chip8: &mut Chip8, operands: Operands) -> ProgramCounter {
+    chip8.raise(Fault::UnknownOpcode { pc: chip8.pc, opcode: operands.opcode })
+}
+
+/**
+Decoded instructions keyed by the address they were fetched from.
+Entries must be invalidated whenever memory they were decoded from changes, which for a write
+to `address` means the instructions starting at `address` and at `address - 1`.
+ */
+pub(crate) struct DecodeCache {
+    entries: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache { entries: Vec::new() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    // Enables the cache for a memory of `memory_size` bytes, or disables it with 0.
+    pub fn resize(&mut self, memory_size: usize) {
+        self.entries = vec![None; memory_size];
+    }
+
+    pub fn get(&self, address: u16) -> Option<Instruction> {
+        self.entries.get(address as usize).copied().flatten()
+    }
+
+    pub fn insert(&mut self, address: u16, instruction: Instruction) {
+        if let Some(entry) = self.entries.get_mut(address as usize) {
+            *entry = Some(instruction);
+        }
+    }
+
+    pub fn invalidate(&mut self, address: u16) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let last = self.entries.len() - 1;
+        let address = address as usize & last;
+        self.entries[address] = None;
+        self.entries[address.checked_sub(1).unwrap_or(last)] = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_modifying_code_should_invalidate_cached_instructions() {
+        let program: [u16; 11] = [
+            0x120A, // 200: jump to 20A
+            0x6061, // 202: V0 := 0x61
+            0x6177, // 204: V1 := 0x77
+            0xA20A, // 206: I := 20A
+            0xF155, // 208: store V0..V1 at 20A, turning it into 6177
+            0x6155, // 20A: V1 := 0x55
+            0x3E01, // 20C: skip if VE == 1
+            0x1212, // 20E: jump to 212
+            0x1210, // 210: done
+            0x6E01, // 212: VE := 1
+            0x1202, // 214: jump to 202
+        ];
+        let mut emu = Chip8::new();
+        emu.set_decode_cache(true);
+        emu.load(program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect());
+        emu.start();
+
+        emu.run_frame(20);
+
+        assert_eq!(emu.pc, 0x210);
+        assert_eq!(emu.v[1], 0x77);
+    }
+
+    #[test]
+    fn invalidate_should_drop_instructions_overlapping_the_address() {
+        let mut cache = DecodeCache::new();
+        cache.resize(0x1000);
+        for address in [0xFFF, 0x000, 0x001, 0x002] {
+            cache.insert(address, Instruction::decode(0x00E0, InstructionSet::Chip8));
+        }
+
+        cache.invalidate(0x000);
+
+        assert!(cache.get(0xFFF).is_none());
+        assert!(cache.get(0x000).is_none());
+        assert!(cache.get(0x001).is_some());
+        assert!(cache.get(0x002).is_some());
+    }
+}
@@ -1,56 +1,137 @@
-use macroquad::color::{BLACK, WHITE};
+use std::ops::Range;
+use macroquad::color::{Color, BLACK, BLUE, GREEN, RED, VIOLET, WHITE, YELLOW};
 use macroquad::prelude::draw_rectangle;
-use macroquad::window::clear_background;
 
+#[derive(Clone, PartialEq)]
 pub struct Display {
     scale: f32,
+    width: usize,
+    height: usize,
     screen: Vec<u8>,
+    colours: Option<Colours>,
 }
 
-const NATIVE_SCREEN_WIDTH: usize = 64;
-const NATIVE_SCREEN_HEIGHT: usize = 32;
+pub(crate) const NATIVE_SCREEN_WIDTH: usize = 64;
+pub(crate) const NATIVE_SCREEN_HEIGHT: usize = 32;
+
+// Height of the rendered screen in window pixels, whatever the display geometry.
+const RENDER_HEIGHT: f32 = 320.0;
+
+// Colours of the VP-590 colour board, by the 3-bit code CHIP-8X programs use.
+const COLOUR_CODES: [Color; 8] = [BLACK, RED, BLUE, VIOLET, GREEN, YELLOW, Color::new(0.0, 1.0, 1.0, 1.0), WHITE];
+// 02A0 steps through these background colours, starting with blue.
+const BACKGROUND_CYCLE: [usize; 4] = [2, 0, 4, 1];
+// Foreground colours are set for 8 pixel wide columns.
+const COLOUR_ZONE_WIDTH: usize = 8;
+
+/**
+CHIP-8X colour board state: the position in the background cycle and the foreground colour code
+of every 8 pixel wide column of every pixel row. Zones start red.
+ */
+#[derive(Clone, PartialEq)]
+struct Colours {
+    background: usize,
+    foreground: Vec<u8>,
+}
 
 impl Display {
-    pub fn new() -> Display {
+    pub fn new(width: usize, height: usize) -> Display {
         Display {
-            scale: 10.0,
-            screen: vec![0; NATIVE_SCREEN_WIDTH * NATIVE_SCREEN_HEIGHT],
+            scale: RENDER_HEIGHT / height as f32,
+            width,
+            height,
+            screen: vec![0; width * height],
+            colours: None,
         }
     }
+
+    // A display driven by the CHIP-8X colour board.
+    pub fn with_colours(width: usize, height: usize) -> Display {
+        let zones = width.div_ceil(COLOUR_ZONE_WIDTH) * height;
+        Display {
+            colours: Some(Colours { background: 0, foreground: vec![1; zones] }),
+            ..Display::new(width, height)
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     pub fn clear(&mut self) {
-        clear_background(BLACK);
-        self.screen = vec![0; NATIVE_SCREEN_WIDTH * NATIVE_SCREEN_HEIGHT];
+        self.screen = vec![0; self.width * self.height];
     }
 
     pub fn draw(&mut self, x: u8, y: u8) -> bool {
         // Correct x and y
-        let corrected_x = x % (NATIVE_SCREEN_WIDTH as u8 + 1);
-        let corrected_y = y % (NATIVE_SCREEN_HEIGHT as u8 + 1);
+        let corrected_x = x as usize % self.width;
+        let corrected_y = y as usize % self.height;
 
-        let pixel_coordinate: usize = (corrected_y as usize * (NATIVE_SCREEN_WIDTH - 1)) + corrected_x as usize;
+        let pixel_coordinate: usize = (corrected_y * self.width) + corrected_x;
         self.screen[pixel_coordinate] ^= 1;
-        let active: u8 = self.screen[pixel_coordinate as usize] ^ 1;
+        let active: u8 = self.screen[pixel_coordinate] ^ 1;
 
-        return if active == 1 { true } else { false };
+        active == 1
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.screen[y * self.width + x] == 1
+    }
+
+    // Moves to the next background colour. Does nothing without a colour board.
+    pub fn cycle_background(&mut self) {
+        if let Some(colours) = &mut self.colours {
+            colours.background = (colours.background + 1) % BACKGROUND_CYCLE.len();
+        }
+    }
+
+    // Sets the foreground colour code of the given 8 pixel wide columns of the given pixel rows.
+    // Zones past the edges are ignored, and so is everything without a colour board.
+    pub fn set_foreground(&mut self, columns: Range<usize>, rows: Range<usize>, code: u8) {
+        let zones_per_row = self.width.div_ceil(COLOUR_ZONE_WIDTH);
+        let height = self.height;
+        if let Some(colours) = &mut self.colours {
+            for row in rows.start.min(height)..rows.end.min(height) {
+                for column in columns.start.min(zones_per_row)..columns.end.min(zones_per_row) {
+                    colours.foreground[row * zones_per_row + column] = code & 0x7;
+                }
+            }
+        }
+    }
+
+    // Colour code of the pixel at (x, y) when lit, and of the background, if there is a colour board.
+    pub fn colour_codes(&self, x: usize, y: usize) -> Option<(u8, u8)> {
+        self.colours.as_ref().map(|colours| {
+            let zone = y * self.width.div_ceil(COLOUR_ZONE_WIDTH) + x / COLOUR_ZONE_WIDTH;
+            (colours.foreground[zone], BACKGROUND_CYCLE[colours.background] as u8)
+        })
     }
 
     pub fn render(&self) {
         let mut y = 0;
-        while y < NATIVE_SCREEN_HEIGHT {
+        while y < self.height {
             let mut x = 0;
-            while x < NATIVE_SCREEN_WIDTH {
-                let pixel_coordinate = (y * (NATIVE_SCREEN_WIDTH - 1)) + x;
-                let active = self.screen[pixel_coordinate as usize];
+            while x < self.width {
+                let pixel_coordinate = (y * self.width) + x;
+                let active = self.screen[pixel_coordinate];
+                let (foreground, background) = match self.colour_codes(x, y) {
+                    Some((foreground, background)) => (COLOUR_CODES[foreground as usize], COLOUR_CODES[background as usize]),
+                    None => (WHITE, BLACK),
+                };
                 draw_rectangle(
                     x as f32 * self.scale,
                     y as f32 * self.scale,
                     self.scale,
                     self.scale,
-                    if active == 1 { WHITE } else { BLACK },
+                    if active == 1 { foreground } else { background },
                 );
                 x += 1;
             }
             y += 1;
         }
     }
-}
\ No newline at end of file
+}
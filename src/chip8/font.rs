@@ -0,0 +1,203 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const SMALL_FONT_SIZE: usize = 16 * 5;
+pub const BIG_FONT_SIZE: usize = 16 * 10;
+pub const DEFAULT_FONT_ADDRESS: u16 = 0x000;
+
+/**
+Hexadecimal digit sprites: 4x5 pixel glyphs used by FX29, and 8x10 pixel glyphs used by
+FX30. They are stored in memory one after the other, small glyphs first.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Font {
+    small: [u8; SMALL_FONT_SIZE],
+    big: [u8; BIG_FONT_SIZE],
+}
+
+// Small fonts of well-known interpreters.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FontStyle {
+    Vip,
+    Dream6800,
+    Eti660,
+    SuperChip,
+}
+
+impl FontStyle {
+    pub fn from_id(id: &str) -> Option<FontStyle> {
+        match id {
+            "vip" => Some(FontStyle::Vip),
+            "dream6800" => Some(FontStyle::Dream6800),
+            "eti660" => Some(FontStyle::Eti660),
+            "schip" => Some(FontStyle::SuperChip),
+            _ => None,
+        }
+    }
+}
+
+impl Font {
+    // A built-in small font, paired with the SCHIP big font.
+    pub fn builtin(style: FontStyle) -> Font {
+        let small = match style {
+            FontStyle::Vip => VIP_FONT,
+            FontStyle::Dream6800 => DREAM_6800_FONT,
+            FontStyle::Eti660 => ETI_660_FONT,
+            FontStyle::SuperChip => SCHIP_FONT,
+        };
+        Font { small, big: SCHIP_BIG_FONT }
+    }
+
+    /**
+    Reads a font from raw bytes: 80 bytes of small glyphs, optionally followed by 160 bytes of
+    big glyphs. Without big glyphs the SCHIP ones are used.
+     */
+    pub fn from_bytes(bytes: &[u8]) -> Option<Font> {
+        let mut font = Font::builtin(FontStyle::SuperChip);
+        match bytes.len() {
+            SMALL_FONT_SIZE => font.small.copy_from_slice(bytes),
+            len if len == SMALL_FONT_SIZE + BIG_FONT_SIZE => {
+                font.small.copy_from_slice(&bytes[..SMALL_FONT_SIZE]);
+                font.big.copy_from_slice(&bytes[SMALL_FONT_SIZE..]);
+            }
+            _ => return None,
+        }
+        Some(font)
+    }
+
+    pub fn load_file(path: &Path) -> io::Result<Font> {
+        let bytes = fs::read(path)?;
+        Font::from_bytes(&bytes).ok_or_else(|| {
+            let message = format!("font files must be {} or {} bytes long", SMALL_FONT_SIZE, SMALL_FONT_SIZE + BIG_FONT_SIZE);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })
+    }
+
+    pub fn small(&self) -> &[u8] {
+        &self.small
+    }
+
+    pub fn big(&self) -> &[u8] {
+        &self.big
+    }
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Font::builtin(FontStyle::SuperChip)
+    }
+}
+
+const VIP_FONT: [u8; SMALL_FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+const DREAM_6800_FONT: [u8; SMALL_FONT_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+const ETI_660_FONT: [u8; SMALL_FONT_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+const SCHIP_FONT: [u8; SMALL_FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// SCHIP 1.1 only has digits 0-9. A-F are the glyphs XO-CHIP interpreters added.
+const SCHIP_BIG_FONT: [u8; BIG_FONT_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes_should_accept_small_or_small_and_big_fonts() {
+        let small_only = Font::from_bytes(&[0x11; SMALL_FONT_SIZE]).unwrap();
+        assert_eq!(small_only.small(), &[0x11; SMALL_FONT_SIZE]);
+        assert_eq!(small_only.big(), &SCHIP_BIG_FONT);
+
+        let both = Font::from_bytes(&[0x22; SMALL_FONT_SIZE + BIG_FONT_SIZE]).unwrap();
+        assert_eq!(both.big(), &[0x22; BIG_FONT_SIZE]);
+
+        assert_eq!(Font::from_bytes(&[0; 81]), None);
+    }
+}
@@ -0,0 +1,536 @@
+use std::fmt;
+use std::mem::{self, ManuallyDrop};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlags, Value};
+use cranelift_codegen::isa::OwnedTargetIsa;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use super::{Chip8, ProgramCounter, Quirks, V_SIZE};
+use crate::chip8::timing::VIP_INTERPRETER_CYCLES_PER_FRAME;
+
+// Longest run of instructions translated into one block, which bounds the search on invalidation.
+const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+const MAX_BLOCK_SIZE: usize = MAX_BLOCK_INSTRUCTIONS * 2;
+// Shorter runs are cheaper to interpret than to enter native code for.
+const MIN_BLOCK_INSTRUCTIONS: usize = 2;
+// Native code of invalidated blocks is only freed with the whole module, once this many were compiled.
+const MAX_COMPILED_BLOCKS: usize = 4096;
+
+// Registers a translated block reads and writes, laid out for the native code.
+#[repr(C)]
+struct Registers {
+    v: [u8; V_SIZE],
+    address_register: u16,
+}
+
+const ADDRESS_REGISTER_OFFSET: i32 = V_SIZE as i32;
+
+type BlockFn = unsafe extern "C" fn(*mut Registers);
+
+// Why the recompiler could not be created, e.g. an unsupported host architecture.
+#[derive(Debug)]
+pub struct JitError(String);
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot create the recompiler: {}", self.0)
+    }
+}
+
+/**
+A straight run of instructions starting at an address. Runs too short to be worth entering
+native code are kept without code, so the interpreter is used without looking at them again.
+ */
+struct Block {
+    size: u16,
+    instructions: u32,
+    vip_cycles: u32,
+    code: Option<BlockFn>,
+}
+
+/**
+Basic-block recompiler.
+Runs of instructions that only compute on V and I (6XNN, 7XNN, 8XYN, ANNN) are translated with
+Cranelift into one native function each. Anything else, including every instruction that reads
+or writes memory, the timers, the keypad or the display, or changes control flow, ends the block
+and is left to the interpreter. Translation depends on the shift and logic quirks, so blocks are
+dropped when the quirks change.
+ */
+pub(crate) struct Jit {
+    isa: OwnedTargetIsa,
+    module: ManuallyDrop<JITModule>,
+    context: Context,
+    builder_context: FunctionBuilderContext,
+    // Blocks keyed by their start address. Blocks can overlap when code jumps inside one.
+    blocks: Vec<Option<Block>>,
+    // Number of blocks covering each byte, so writes outside of translated code cost nothing.
+    coverage: Vec<u16>,
+    compiled: usize,
+    quirks: Quirks,
+}
+
+impl Jit {
+    pub fn new(memory_size: usize) -> Result<Jit, JitError> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").map_err(|e| JitError(e.to_string()))?;
+        let isa = cranelift_native::builder()
+            .map_err(|e| JitError(e.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| JitError(e.to_string()))?;
+        let module = JITModule::new(JITBuilder::with_isa(isa.clone(), default_libcall_names()));
+
+        Ok(Jit {
+            isa,
+            context: module.make_context(),
+            module: ManuallyDrop::new(module),
+            builder_context: FunctionBuilderContext::new(),
+            blocks: (0..memory_size).map(|_| None).collect(),
+            coverage: vec![0; memory_size],
+            compiled: 0,
+            quirks: Quirks::default(),
+        })
+    }
+
+    // Drops every block and follows a memory of `memory_size` bytes.
+    pub fn resize(&mut self, memory_size: usize) {
+        self.clear();
+        self.blocks = (0..memory_size).map(|_| None).collect();
+        self.coverage = vec![0; memory_size];
+    }
+
+    // Drops every block, e.g. after the whole memory was replaced.
+    pub fn clear(&mut self) {
+        self.blocks.iter_mut().for_each(|block| *block = None);
+        self.coverage.fill(0);
+        if self.compiled > 0 {
+            let module = JITModule::new(JITBuilder::with_isa(self.isa.clone(), default_libcall_names()));
+            let old = mem::replace(&mut *self.module, module);
+            // Safe since no block refers to the old code anymore.
+            unsafe { old.free_memory() };
+            self.compiled = 0;
+        }
+    }
+
+    // Drops the blocks translated from the byte at `address`.
+    pub fn invalidate(&mut self, address: u16) {
+        let address = address as usize;
+        if self.coverage.get(address).copied().unwrap_or(0) == 0 {
+            return;
+        }
+        // Blocks never wrap around the end of memory, so they start at most a block size before.
+        for start in address.saturating_sub(MAX_BLOCK_SIZE - 1)..=address {
+            let covers = matches!(&self.blocks[start], Some(block) if start + block.size as usize > address);
+            if covers {
+                let block = self.blocks[start].take().unwrap();
+                self.coverage[start..start + block.size as usize].iter_mut().for_each(|count| *count -= 1);
+            }
+        }
+    }
+
+    fn get(&self, address: u16) -> Option<&Block> {
+        self.blocks.get(address as usize)?.as_ref()
+    }
+
+    fn insert(&mut self, address: u16, opcodes: &[u16], vip_cycles: u32, quirks: Quirks) {
+        let code = if opcodes.len() >= MIN_BLOCK_INSTRUCTIONS { self.compile(opcodes, quirks) } else { None };
+        let start = address as usize;
+        let size = opcodes.len().max(1) * 2;
+        self.coverage[start..start + size].iter_mut().for_each(|count| *count += 1);
+        self.blocks[start] = Some(Block {
+            size: size as u16,
+            instructions: opcodes.len() as u32,
+            vip_cycles,
+            code,
+        });
+    }
+
+    // Translates `opcodes` into a native function. Failing to compile leaves the block to the interpreter.
+    fn compile(&mut self, opcodes: &[u16], quirks: Quirks) -> Option<BlockFn> {
+        if self.compiled >= MAX_COMPILED_BLOCKS {
+            self.clear();
+        }
+
+        let pointer = self.module.target_config().pointer_type();
+        self.context.func.signature.params.push(AbiParam::new(pointer));
+        let mut builder = FunctionBuilder::new(&mut self.context.func, &mut self.builder_context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let registers = builder.block_params(entry)[0];
+        let mut translator = Translator {
+            builder: &mut builder,
+            registers,
+            v: [None; V_SIZE],
+            address_register: None,
+            dirty_v: [false; V_SIZE],
+            dirty_address_register: false,
+        };
+        for &opcode in opcodes {
+            translator.translate(opcode, quirks);
+        }
+        translator.write_back();
+        builder.ins().return_(&[]);
+        builder.finalize();
+
+        let id = self.module.declare_anonymous_function(&self.context.func.signature).ok()
+            .filter(|&id| self.module.define_function(id, &mut self.context).is_ok())
+            .filter(|_| self.module.finalize_definitions().is_ok());
+        self.module.clear_context(&mut self.context);
+        let id = id?;
+        self.compiled += 1;
+
+        // Safe since the function was built with the `BlockFn` signature.
+        Some(unsafe { mem::transmute::<*const u8, BlockFn>(self.module.get_finalized_function(id)) })
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        // Safe since the blocks, the only references to the code, are dropped with the module.
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+// Whether the recompiler handles `opcode`, see `Translator::translate`.
+fn is_translatable(opcode: u16) -> bool {
+    match opcode & 0xF000 {
+        0x6000 | 0x7000 | 0xA000 => true,
+        0x8000 => matches!(opcode & 0x000F, 0x0..=0x7 | 0xE),
+        _ => false,
+    }
+}
+
+// Emits the instructions of a block, keeping registers in SSA values until the block ends.
+struct Translator<'a, 'b> {
+    builder: &'a mut FunctionBuilder<'b>,
+    registers: Value,
+    v: [Option<Value>; V_SIZE],
+    address_register: Option<Value>,
+    dirty_v: [bool; V_SIZE],
+    dirty_address_register: bool,
+}
+
+impl Translator<'_, '_> {
+    // Mirrors the interpreter's handlers for the same opcodes, see `is_translatable`.
+    fn translate(&mut self, opcode: u16, quirks: Quirks) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let nn = (opcode & 0x00FF) as i64;
+
+        match opcode & 0xF000 {
+            0x6000 => {
+                let value = self.builder.ins().iconst(types::I8, nn);
+                self.set_v(x, value);
+            }
+            0x7000 => {
+                let vx = self.v(x);
+                let value = self.builder.ins().iadd_imm(vx, nn);
+                self.set_v(x, value);
+            }
+            0xA000 => {
+                let value = self.builder.ins().iconst(types::I16, (opcode & 0x0FFF) as i64);
+                self.address_register = Some(value);
+                self.dirty_address_register = true;
+            }
+            _ => self.translate_alu(opcode & 0x000F, x, y, quirks),
+        }
+    }
+
+    // 8XYN, with VF written after VX like in `opcode_set_vx_to_vy`.
+    fn translate_alu(&mut self, operation: u16, x: usize, y: usize, quirks: Quirks) {
+        let vx = self.v(x);
+        let vy = self.v(y);
+        let shifted = if quirks.shift { vx } else { vy };
+        let logic = operation != 0 && operation <= 3;
+
+        let (result, flag) = match operation {
+            0x0 => (vy, None),
+            0x1 => (self.builder.ins().bor(vx, vy), None),
+            0x2 => (self.builder.ins().band(vx, vy), None),
+            0x3 => (self.builder.ins().bxor(vx, vy), None),
+            0x4 => {
+                let sum = self.builder.ins().iadd(vx, vy);
+                (sum, Some(self.builder.ins().icmp(IntCC::UnsignedLessThan, sum, vx)))
+            }
+            0x5 => (self.builder.ins().isub(vx, vy), Some(self.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, vx, vy))),
+            0x6 => (self.builder.ins().ushr_imm(shifted, 1), Some(self.builder.ins().band_imm(shifted, 1))),
+            0x7 => (self.builder.ins().isub(vy, vx), Some(self.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, vy, vx))),
+            _ => (self.builder.ins().ishl_imm(shifted, 1), Some(self.builder.ins().ushr_imm(shifted, 7))),
+        };
+        let flag = if logic && quirks.logic { Some(self.builder.ins().iconst(types::I8, 0)) } else { flag };
+
+        self.set_v(x, result);
+        if let Some(flag) = flag {
+            self.set_v(0xF, flag);
+        }
+    }
+
+    fn v(&mut self, index: usize) -> Value {
+        if let Some(value) = self.v[index] {
+            return value;
+        }
+        let value = self.builder.ins().load(types::I8, MemFlags::trusted(), self.registers, index as i32);
+        self.v[index] = Some(value);
+        value
+    }
+
+    fn set_v(&mut self, index: usize, value: Value) {
+        self.v[index] = Some(value);
+        self.dirty_v[index] = true;
+    }
+
+    fn write_back(&mut self) {
+        for index in 0..V_SIZE {
+            if let (true, Some(value)) = (self.dirty_v[index], self.v[index]) {
+                self.builder.ins().store(MemFlags::trusted(), value, self.registers, index as i32);
+            }
+        }
+        if let (true, Some(value)) = (self.dirty_address_register, self.address_register) {
+            self.builder.ins().store(MemFlags::trusted(), value, self.registers, ADDRESS_REGISTER_OFFSET);
+        }
+    }
+}
+
+impl Chip8 {
+    /**
+    Enables or disables the recompiler, which `run_frame` then uses for the blocks it can
+    translate. Like with the decode cache, translated code is dropped when the program writes
+    over it, and instruction fetches are no longer reported to memory observers.
+     */
+    pub fn set_jit(&mut self, enabled: bool) -> Result<(), JitError> {
+        self.jit = if enabled { Some(Jit::new(self.memory.size())?) } else { None };
+        Ok(())
+    }
+
+    pub fn is_jit_enabled(&self) -> bool {
+        self.jit.is_some()
+    }
+
+    /**
+    Runs the translated block at PC, translating it first if needed. The block only runs when
+    the interpreter would have run all of its instructions this frame: at most `instructions`,
+    or as many as the remaining machine cycles allow with the vip_timing quirk.
+    Returns the number of instructions executed, or None to let the interpreter run PC.
+     */
+    pub(crate) fn run_block(&mut self, instructions: u32) -> Option<u32> {
+        // Blocks skip the checks of the linter, the counts of the profile and breakpoints.
+        if self.linter.is_some() || self.profile.is_some() || !self.breakpoints.is_empty() {
+            return None;
+        }
+        let pc = self.pc;
+        let quirks = self.quirks;
+        let jit = self.jit.as_mut()?;
+        if jit.quirks != quirks {
+            jit.clear();
+            jit.quirks = quirks;
+        }
+        // An instruction on the last byte wraps around memory, which blocks never do.
+        if pc as usize + 1 >= self.memory.size() {
+            return None;
+        }
+
+        if self.jit.as_ref()?.get(pc).is_none() {
+            let opcodes = self.translatable_run(pc);
+            let vip_cycles = opcodes.iter().map(|&opcode| self.vip_cycles(opcode, ProgramCounter::Next)).sum();
+            self.jit.as_mut()?.insert(pc, &opcodes, vip_cycles, quirks);
+        }
+
+        let block = self.jit.as_ref()?.get(pc)?;
+        let code = block.code?;
+        let fits = if quirks.vip_timing {
+            self.frame_cycles + block.vip_cycles <= VIP_INTERPRETER_CYCLES_PER_FRAME
+        } else {
+            block.instructions <= instructions
+        };
+        if !fits {
+            return None;
+        }
+        let (size, executed, vip_cycles) = (block.size, block.instructions, block.vip_cycles);
+
+        let mut registers = Registers { v: self.v, address_register: self.address_register };
+        // Safe since the code only accesses the registers it is given.
+        unsafe { code(&mut registers) };
+        self.v = registers.v;
+        self.address_register = registers.address_register;
+        self.pc = pc.wrapping_add(size);
+        if quirks.vip_timing {
+            self.frame_cycles += vip_cycles;
+        }
+        Some(executed)
+    }
+
+    // Opcodes from `address` up to the first one the recompiler does not handle, within memory.
+    fn translatable_run(&self, address: u16) -> Vec<u16> {
+        let end = (address as usize + MAX_BLOCK_SIZE).min(self.memory.size());
+        (address as usize..end - 1)
+            .step_by(2)
+            .map(|address| self.opcode_at(address as u16))
+            .take_while(|&opcode| is_translatable(opcode))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use crate::chip8::Platform;
+
+    const PLATFORMS: [Platform; 4] = [Platform::OriginalChip8, Platform::ModernChip8, Platform::SuperChip, Platform::XoChip];
+    const DATA: u16 = 0x800;
+
+    /**
+    A random program made of units that stay valid when run in any order: jumps only go to the
+    start of a unit, skips only skip a single ALU instruction, and memory is only written in a
+    data area or over the NN byte of 6XNN and 7XNN, which makes self-modifying code.
+     */
+    fn random_program(rng: &mut StdRng) -> Vec<u8> {
+        let alu = |rng: &mut StdRng| -> u16 {
+            let x = rng.gen_range(0..16) << 8;
+            let y = rng.gen_range(0..16) << 4;
+            match rng.gen_range(0..3) {
+                0 => 0x6000 | x | rng.gen_range(0..=0xFF),
+                1 => 0x7000 | x | rng.gen_range(0..=0xFF),
+                _ => 0x8000 | x | y | [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE][rng.gen_range(0..9)],
+            }
+        };
+        let mut units: Vec<Vec<u16>> = (0..48).map(|_| {
+            let x = rng.gen_range(0..16) << 8;
+            match rng.gen_range(0..10) {
+                0..=4 => (0..rng.gen_range(1..8)).map(|_| alu(rng)).collect(),
+                5 => vec![[0x3000, 0x4000][rng.gen_range(0..2)] | x | rng.gen_range(0..4), alu(rng)],
+                6 => vec![0xA000 | DATA | rng.gen_range(0..0x80), [0xF033, 0xF055, 0xF065][rng.gen_range(0..3)] | x],
+                7 => vec![0xA000 | rng.gen_range(0x200..0x300), 0xD000 | x | rng.gen_range(0..0x100)],
+                8 => vec![0xF015 | x, 0xF007 | rng.gen_range(0..16) << 8],
+                // Writes V0 over an NN byte, whose address is filled in below.
+                _ => vec![0xA000, 0xF055],
+            }
+        }).collect();
+
+        // Every unit ends with a jump.
+        let starts: Vec<u16> = units.iter().scan(0x200, |address, unit| {
+            let start = *address;
+            *address += 2 * (unit.len() as u16 + 1);
+            Some(start)
+        }).collect();
+        let immediates: Vec<u16> = units.iter().zip(&starts)
+            .flat_map(|(unit, &start)| unit.iter().enumerate().map(move |(i, &opcode)| (start + 2 * i as u16, opcode)))
+            .filter(|&(_, opcode)| matches!(opcode & 0xF000, 0x6000 | 0x7000))
+            .map(|(address, _)| address + 1)
+            .chain([DATA])
+            .collect();
+
+        let mut program = Vec::new();
+        for (i, unit) in units.iter_mut().enumerate() {
+            if unit[..] == [0xA000, 0xF055] {
+                unit[0] |= immediates[rng.gen_range(0..immediates.len())];
+            }
+            // Units mostly continue with the next one.
+            let target = if rng.gen_bool(0.8) { starts[(i + 1) % starts.len()] } else { starts[rng.gen_range(0..starts.len())] };
+            unit.push(0x1000 | target);
+            program.extend(unit.iter().flat_map(|opcode| opcode.to_be_bytes()));
+        }
+        program
+    }
+
+    fn a_chip8(program: &[u8], platform: Platform, jit: bool) -> Chip8 {
+        let mut emu = Chip8::new();
+        emu.set_quirks(platform.quirks());
+        emu.set_jit(jit).unwrap();
+        emu.load(program.to_vec());
+        emu.start();
+        emu
+    }
+
+    #[test]
+    fn recompiled_code_should_behave_like_the_interpreter() {
+        let mut rng = StdRng::seed_from_u64(0xC8);
+        for _ in 0..25 {
+            let program = random_program(&mut rng);
+            for platform in PLATFORMS {
+                let mut interpreted = a_chip8(&program, platform, false);
+                let mut recompiled = a_chip8(&program, platform, true);
+
+                for frame in 0..30 {
+                    assert_eq!(recompiled.run_frame(100), interpreted.run_frame(100), "frame {} on {:?}", frame, platform);
+                    assert!(recompiled.snapshot() == interpreted.snapshot(), "frame {} on {:?}", frame, platform);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn writes_should_invalidate_translated_blocks() {
+        let program: [u16; 6] = [
+            0x6001, // 200: V0 := 1
+            0x7102, // 202: V1 += 2
+            0xA203, // 204: I := 203, the NN byte of 7102
+            0x6005, // 206: V0 := 5
+            0xF055, // 208: store V0 at 203, turning 7102 into 7105
+            0x1200, // 20A: jump to 200
+        ];
+        let program: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut emu = a_chip8(&program, Platform::ModernChip8, true);
+
+        emu.run_frame(6);
+        assert_eq!(emu.v[1], 2);
+
+        emu.run_frame(6);
+        assert_eq!(emu.v[1], 7);
+    }
+
+    #[test]
+    fn writes_past_the_end_of_memory_should_invalidate_the_blocks_they_wrap_onto() {
+        let program: [u16; 20] = [
+            0x2220, // 200: call 220
+            0xAFFF, // 202: I := FFF
+            0x60FF, // 204: V0 := FF
+            0xF01E, // 206: I += V0
+            0xF01E, // 208: I += V0
+            0x6023, // 20A: V0 := 23
+            0xF01E, // 20C: I += V0, making I 1220, which wraps to 220
+            0x6062, // 20E: V0 := 62
+            0x6107, // 210: V1 := 07
+            0xF155, // 212: store 6207 at 220, turning 6201 into 6207
+            0x2220, // 214: call 220
+            0x1216, // 216: jump to 216
+            0x0000, 0x0000, 0x0000, 0x0000,
+            0x6201, // 220: V2 := 1
+            0x7301, // 222: V3 += 1
+            0x7401, // 224: V4 += 1
+            0x00EE, // 226: return
+        ];
+        let program: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut interpreted = a_chip8(&program, Platform::ModernChip8, false);
+        let mut recompiled = a_chip8(&program, Platform::ModernChip8, true);
+
+        assert_eq!(recompiled.run_frame(20), interpreted.run_frame(20));
+        assert!(recompiled.snapshot() == interpreted.snapshot());
+        assert_eq!(recompiled.v[2], 7);
+    }
+
+    // Found by fuzzing: a block was inserted for the last byte of memory, past its end.
+    #[test]
+    fn instructions_wrapping_around_memory_should_be_interpreted() {
+        let mut interpreted = a_chip8(&[0xBF, 0xFF], Platform::ModernChip8, false);
+        let mut recompiled = a_chip8(&[0xBF, 0xFF], Platform::ModernChip8, true);
+
+        assert_eq!(recompiled.run_frame(2), interpreted.run_frame(2));
+        assert!(recompiled.snapshot() == interpreted.snapshot());
+    }
+
+    #[test]
+    fn blocks_should_not_run_past_the_instructions_of_the_frame() {
+        let program: Vec<u8> = [0x6001u16, 0x7001, 0x7001, 0x7001, 0x1200].iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut emu = a_chip8(&program, Platform::ModernChip8, true);
+
+        assert_eq!(emu.run_frame(3), 3);
+        assert_eq!(emu.v[0], 3);
+        assert_eq!(emu.pc, 0x206);
+    }
+}
@@ -1,22 +1,109 @@
+/**
+State of the 16 keys of the hex keypad.
+Besides whether a key is held, presses and releases are latched until the end of the frame,
+so instructions can react to edges even when a key goes down and up within a single frame.
+ */
 pub struct Keypad {
     key_states: [u8; 16],
+    just_pressed: u16,
+    just_released: u16,
 }
 
 impl Keypad {
     pub fn new() -> Keypad {
         Keypad {
             key_states: [0; 16],
+            just_pressed: 0,
+            just_released: 0,
         }
     }
     pub fn press(&mut self, key_index: usize) {
+        if self.key_states[key_index] == 0 {
+            self.just_pressed |= 1 << key_index;
+        }
         self.key_states[key_index] = 1;
     }
 
     pub fn release(&mut self, key_index: usize) {
+        if self.key_states[key_index] == 1 {
+            self.just_released |= 1 << key_index;
+        }
         self.key_states[key_index] = 0;
     }
 
     pub fn status(&self, key_index: usize) -> u8 {
-        return self.key_states[key_index];
+        self.key_states[key_index]
+    }
+
+    // Whether the key went down during the current frame.
+    pub fn just_pressed(&self, key_index: usize) -> bool {
+        self.just_pressed & (1 << key_index) != 0
+    }
+
+    // Whether the key went up during the current frame.
+    pub fn just_released(&self, key_index: usize) -> bool {
+        self.just_released & (1 << key_index) != 0
+    }
+
+    // Forgets the presses and releases of the frame that ended.
+    pub fn end_frame(&mut self) {
+        self.just_pressed = 0;
+        self.just_released = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Event {
+        Press(usize),
+        Release(usize),
+    }
+
+    // Applies each frame's events and returns the keys seen as just pressed/released in every frame.
+    fn run_script(script: &[&[Event]]) -> Vec<(Vec<usize>, Vec<usize>)> {
+        let mut keypad = Keypad::new();
+        let mut edges = Vec::new();
+        for frame in script {
+            for event in frame.iter() {
+                match event {
+                    Event::Press(key) => keypad.press(*key),
+                    Event::Release(key) => keypad.release(*key),
+                }
+            }
+            let pressed = (0..16).filter(|key| keypad.just_pressed(*key)).collect();
+            let released = (0..16).filter(|key| keypad.just_released(*key)).collect();
+            edges.push((pressed, released));
+            keypad.end_frame();
+        }
+        edges
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn held_key_should_only_be_just_pressed_in_its_first_frame() {
+        let edges = run_script(&[
+            &[Event::Press(5)],
+            &[Event::Press(5)],
+            &[Event::Release(5)],
+            &[Event::Release(5)],
+        ]);
+
+        assert_eq!(edges, vec![
+            (vec![5], vec![]),
+            (vec![], vec![]),
+            (vec![], vec![5]),
+            (vec![], vec![]),
+        ]);
+    }
+
+    #[test]
+    fn press_and_release_within_one_frame_should_report_both_edges() {
+        let edges = run_script(&[
+            &[Event::Press(0xA), Event::Release(0xA)],
+            &[],
+        ]);
+
+        assert_eq!(edges, vec![(vec![0xA], vec![0xA]), (vec![], vec![])]);
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use super::{Chip8, InstructionSet, ProgramCounter};
+use crate::chip8::decode::Instruction;
+
+/**
+Likely bugs noticed while a program runs. None of them stops it: real interpreters happily run
+through all of these, and some programs even mean to.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Lint {
+    // Read or executed a byte that was never loaded nor written since the last reset.
+    UninitialisedRead { address: u16 },
+    // Jumped, called or returned into the font.
+    JumpIntoFont { target: u16 },
+    // Executed bytes that were drawn as a sprite earlier.
+    ExecutedSpriteData { address: u16 },
+    // Executed bytes that FX65 loaded into registers earlier.
+    ExecutedData { address: u16 },
+    // Halted, by jumping to itself or stopping, with return addresses left on the stack.
+    StackImbalance { depth: usize },
+    // DXYN read a sprite past the end of memory, wrapping around to its start.
+    SpritePastEndOfMemory { address: u16, height: u8 },
+    // Executed an instruction at an odd address.
+    OddPc,
+}
+
+// A lint raised by the instruction `opcode` at `pc`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Diagnostic {
+    pub pc: u16,
+    pub opcode: u16,
+    pub lint: Lint,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lint::UninitialisedRead { address } => write!(f, "read of uninitialised memory at {:03X}", address),
+            Lint::JumpIntoFont { target } => write!(f, "jump into the font at {:03X}", target),
+            Lint::ExecutedSpriteData { address } => write!(f, "executing sprite data at {:03X}", address),
+            Lint::ExecutedData { address } => write!(f, "executing data loaded by FX65 at {:03X}", address),
+            Lint::StackImbalance { depth } => write!(f, "halted with {} return addresses on the stack", depth),
+            Lint::SpritePastEndOfMemory { address, height } => {
+                write!(f, "{} byte sprite at {:03X} runs past the end of memory", height, address)
+            }
+            Lint::OddPc => write!(f, "instruction at an odd address"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03X} {:04X}: {}", self.pc, self.opcode, self.lint)
+    }
+}
+
+// What the program did with each byte of memory.
+const INITIALISED: u8 = 1;
+const SPRITE: u8 = 2;
+const DATA: u8 = 4;
+
+/**
+Per-byte history of memory, and the diagnostics already sent, so each one is only reported once
+per reset instead of on every iteration of a loop.
+ */
+pub(crate) struct Linter {
+    memory: Vec<u8>,
+    reported: HashSet<Diagnostic>,
+    sender: Sender<Diagnostic>,
+}
+
+impl Linter {
+    // Bytes loaded before linting started are unknown, so they are all taken as initialised.
+    fn new(memory_size: usize, sender: Sender<Diagnostic>) -> Linter {
+        Linter {
+            memory: vec![INITIALISED; memory_size],
+            reported: HashSet::new(),
+            sender,
+        }
+    }
+
+    // Forgets the history of memory, e.g. once it is refilled on reset.
+    pub fn reset(&mut self, memory_size: usize) {
+        self.memory = vec![0; memory_size];
+        self.reported.clear();
+    }
+
+    // Takes every byte as initialised, e.g. after restoring a snapshot.
+    pub fn assume_initialised(&mut self, memory_size: usize) {
+        self.memory = vec![INITIALISED; memory_size];
+    }
+
+    // The byte at `address` was loaded or written: it holds a new value, whatever the old one was used for.
+    pub fn mark_written(&mut self, address: u16) {
+        if let Some(flags) = self.memory.get_mut(address as usize) {
+            *flags = INITIALISED;
+        }
+    }
+
+    fn flags(&self, address: u16) -> u8 {
+        self.memory.get(address as usize).copied().unwrap_or(INITIALISED)
+    }
+
+    fn mark(&mut self, address: u16, flag: u8) {
+        if let Some(flags) = self.memory.get_mut(address as usize) {
+            *flags |= flag;
+        }
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic) {
+        // Nobody may be listening anymore, which is fine.
+        if self.reported.insert(diagnostic) {
+            let _ = self.sender.send(diagnostic);
+        }
+    }
+}
+
+impl Chip8 {
+    /**
+    Starts checking the running program for likely bugs, see `Lint`. Diagnostics are sent to the
+    returned channel, each one once until the next reset. Enable it before loading a program,
+    since bytes loaded earlier can't be told apart from uninitialised ones and are trusted.
+    While linting, the recompiler is bypassed so that every instruction is checked.
+     */
+    pub fn enable_lints(&mut self) -> Receiver<Diagnostic> {
+        let (sender, receiver) = channel();
+        self.linter = Some(Linter::new(self.memory.size(), sender));
+        receiver
+    }
+
+    pub fn disable_lints(&mut self) {
+        self.linter = None;
+    }
+
+    // Checks `instruction`, fetched from PC, before it executes, while I still holds the address it reads.
+    pub(crate) fn lint_before(&mut self, instruction: Instruction) {
+        let Some(mut linter) = self.linter.take() else { return };
+        let pc = self.pc;
+        let opcode = instruction.opcode();
+        let report = |linter: &mut Linter, lint| linter.report(Diagnostic { pc, opcode, lint });
+
+        if pc & 1 == 1 {
+            report(&mut linter, Lint::OddPc);
+        }
+        for offset in 0..instruction.size() {
+            let address = self.memory.wrap(pc as usize + offset as usize);
+            let flags = linter.flags(address);
+            if flags & INITIALISED == 0 {
+                report(&mut linter, Lint::UninitialisedRead { address });
+            }
+            if flags & SPRITE != 0 {
+                report(&mut linter, Lint::ExecutedSpriteData { address });
+            } else if flags & DATA != 0 {
+                report(&mut linter, Lint::ExecutedData { address });
+            }
+        }
+
+        let (length, flag) = self.data_read(opcode);
+        let start = self.address_register;
+        if opcode & 0xF000 == 0xD000 && start as usize + length > self.memory.size() {
+            report(&mut linter, Lint::SpritePastEndOfMemory { address: start, height: length as u8 });
+        }
+        for offset in 0..length {
+            let address = self.memory.wrap(start as usize + offset);
+            if linter.flags(address) & INITIALISED == 0 {
+                report(&mut linter, Lint::UninitialisedRead { address });
+            }
+            linter.mark(address, flag);
+        }
+
+        self.linter = Some(linter);
+    }
+
+    // Checks where `instruction`, executed at `pc`, sent the program.
+    pub(crate) fn lint_after(&mut self, pc: u16, instruction: Instruction, next: ProgramCounter) {
+        let Some(mut linter) = self.linter.take() else { return };
+        let opcode = instruction.opcode();
+
+        let halted = match next {
+            ProgramCounter::Jump(target) => opcode & 0xF000 == 0x1000 && target == pc,
+            ProgramCounter::Wait => opcode == 0x00ED && self.platform.instruction_set() == InstructionSet::Chip8E,
+            _ => false,
+        };
+        if halted && !self.stack.entries().is_empty() {
+            linter.report(Diagnostic { pc, opcode, lint: Lint::StackImbalance { depth: self.stack.entries().len() } });
+        }
+        if let ProgramCounter::Jump(target) = next {
+            if self.font_range().contains(&(target as usize)) {
+                linter.report(Diagnostic { pc, opcode, lint: Lint::JumpIntoFont { target } });
+            }
+        }
+
+        self.linter = Some(linter);
+    }
+
+    // Number of bytes `opcode` reads from I as data, and what they are used for.
+    fn data_read(&self, opcode: u16) -> (usize, u8) {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let set = self.platform.instruction_set();
+        match opcode & 0xF000 {
+            // SUPER-CHIP draws a 16x16 sprite of 32 bytes for DXY0.
+            0xD000 if opcode & 0xF == 0 && matches!(set, InstructionSet::SuperChip | InstructionSet::XoChip) => (32, SPRITE),
+            0xD000 => ((opcode & 0x000F) as usize, SPRITE),
+            0xF000 if opcode & 0xFF == 0x65 => (x + 1, DATA),
+            0x5000 if opcode & 0xF == 3 && set == InstructionSet::Chip8E => {
+                ((y + 1).saturating_sub(x), DATA)
+            }
+            _ => (0, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Platform;
+
+    // Runs `program` with lints enabled and returns the diagnostics raised.
+    fn lint(program: &[u16], instructions: u32) -> Vec<Diagnostic> {
+        let mut emu = Chip8::new();
+        let diagnostics = emu.enable_lints();
+        emu.load(program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect());
+        emu.start();
+        emu.run_frame(instructions);
+        diagnostics.try_iter().collect()
+    }
+
+    fn lints(program: &[u16], instructions: u32) -> Vec<Lint> {
+        lint(program, instructions).into_iter().map(|diagnostic| diagnostic.lint).collect()
+    }
+
+    #[test]
+    fn a_clean_program_should_raise_nothing() {
+        // Draws the 0 glyph and loops forever.
+        assert_eq!(lints(&[0x6000, 0xF029, 0xD005, 0x1206], 10), vec![]);
+    }
+
+    #[test]
+    fn reading_memory_never_written_should_be_reported_once() {
+        let diagnostics = lint(&[0xA300, 0xF065, 0xA300, 0x1202], 10);
+
+        assert_eq!(diagnostics, vec![Diagnostic { pc: 0x202, opcode: 0xF065, lint: Lint::UninitialisedRead { address: 0x300 } }]);
+    }
+
+    #[test]
+    fn reading_memory_written_by_the_program_should_be_fine() {
+        assert_eq!(lints(&[0xA300, 0xF055, 0xA300, 0xF065, 0x1208], 10), vec![]);
+    }
+
+    #[test]
+    fn running_off_the_program_should_report_executing_uninitialised_memory() {
+        assert_eq!(lints(&[0x6001], 2), vec![Lint::UninitialisedRead { address: 0x202 }, Lint::UninitialisedRead { address: 0x203 }]);
+    }
+
+    #[test]
+    fn jumping_into_the_font_should_be_reported() {
+        let diagnostics = lint(&[0x1000], 1);
+
+        assert_eq!(diagnostics[0], Diagnostic { pc: 0x200, opcode: 0x1000, lint: Lint::JumpIntoFont { target: 0x000 } });
+    }
+
+    #[test]
+    fn executing_sprite_and_loaded_data_should_be_reported() {
+        // Draws the byte at 208 and loads the one at 20A, then runs into them.
+        let program = [0xA208, 0xD001, 0xA20A, 0xF065, 0x6001, 0x6002];
+        let diagnostics = lints(&program, 6);
+
+        assert_eq!(diagnostics, vec![
+            Lint::ExecutedSpriteData { address: 0x208 },
+            Lint::ExecutedData { address: 0x20A },
+        ]);
+    }
+
+    #[test]
+    fn executing_code_written_over_sprite_data_should_be_fine() {
+        // Draws the byte at 20C, then writes 6001 over it with FX55 and runs into it.
+        let program = [0xA20C, 0xD001, 0x6060, 0x6101, 0xF155, 0x6203, 0x1234, 0x120E];
+
+        assert_eq!(lints(&program, 8), vec![]);
+    }
+
+    #[test]
+    fn halting_inside_a_subroutine_should_report_the_stack_imbalance() {
+        assert_eq!(lints(&[0x2204, 0x0000, 0x1204], 4), vec![Lint::StackImbalance { depth: 1 }]);
+    }
+
+    #[test]
+    fn sprites_past_the_end_of_memory_should_be_reported() {
+        let mut emu = Chip8::new();
+        let diagnostics = emu.enable_lints();
+        emu.load(vec![0xAF, 0xFC, 0xD0, 0x08]);
+        emu.write_memory(0xFFC, 0);
+        emu.write_memory(0xFFD, 0);
+        emu.write_memory(0xFFE, 0);
+        emu.write_memory(0xFFF, 0);
+        emu.start();
+
+        emu.run_frame(2);
+
+        assert_eq!(diagnostics.try_iter().map(|diagnostic| diagnostic.lint).collect::<Vec<_>>(), vec![
+            Lint::SpritePastEndOfMemory { address: 0xFFC, height: 8 },
+        ]);
+    }
+
+    #[test]
+    fn super_chip_16x16_sprites_past_the_end_of_memory_should_be_reported() {
+        let mut emu = Chip8::new();
+        emu.set_platform(Platform::SuperChip);
+        let diagnostics = emu.enable_lints();
+        emu.load(vec![0xAF, 0xF0, 0xD0, 0x00]);
+        for address in 0xFF0..0x1000 {
+            emu.write_memory(address, 0);
+        }
+        emu.start();
+
+        emu.run_frame(2);
+
+        assert_eq!(diagnostics.try_iter().map(|diagnostic| diagnostic.lint).collect::<Vec<_>>(), vec![
+            Lint::SpritePastEndOfMemory { address: 0xFF0, height: 32 },
+        ]);
+    }
+
+    #[test]
+    fn odd_pc_should_be_reported() {
+        // 203 holds 1203, which jumps to itself.
+        assert_eq!(lints(&[0x1203, 0x0012, 0x0300], 3), vec![Lint::OddPc]);
+    }
+}
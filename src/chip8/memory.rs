@@ -0,0 +1,216 @@
+use std::cell::RefCell;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+pub const DEFAULT_MEMORY_SIZE: usize = 0x1000;
+
+// A memory access reported to observers. Addresses are already wrapped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemoryAccess {
+    Read { address: u16, value: u8 },
+    Write { address: u16, previous: u8, value: u8 },
+}
+
+pub type MemoryObserver = Box<dyn FnMut(MemoryAccess)>;
+
+/**
+What memory holds before anything is loaded into it. Real machines powered up with garbage in
+RAM, which a few programs read by accident, so filling it with something other than zeroes helps
+to expose reads of uninitialised memory. Random fills are seeded, so every reset gets the same bytes.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemoryFill {
+    Pattern(u8),
+    Random(u64),
+}
+
+impl Default for MemoryFill {
+    fn default() -> Self {
+        MemoryFill::Pattern(0)
+    }
+}
+
+impl MemoryFill {
+    // Parses `zero`, `random`, `random:<seed>` or a hex byte to repeat, e.g. from the command line.
+    pub fn from_id(id: &str) -> Option<MemoryFill> {
+        match id {
+            "zero" => Some(MemoryFill::Pattern(0)),
+            "random" => Some(MemoryFill::Random(0)),
+            _ => match id.strip_prefix("random:") {
+                Some(seed) => seed.parse().ok().map(MemoryFill::Random),
+                None => u8::from_str_radix(id.trim_start_matches("0x"), 16).ok().map(MemoryFill::Pattern),
+            },
+        }
+    }
+}
+
+// Handle returned when attaching an observer, used to detach it again.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ObserverId(usize);
+
+/**
+The address space of the interpreter.
+Its size is fixed and a power of two, so addresses past the end wrap around: 4KB memories
+use 12-bit addresses and 64KB memories 16-bit ones.
+Reads and writes made through `read_u8`, `read_u16` and `write` are reported to the attached
+observers, which is how debuggers and watchpoints follow what a program does. `as_slice` and
+`load` bypass them.
+ */
+pub struct Memory {
+    data: Vec<u8>,
+    observers: RefCell<Vec<Option<MemoryObserver>>>,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Memory {
+        assert!(size.is_power_of_two() && size <= 0x10000, "Invalid memory size {:#X}", size);
+        Memory {
+            data: vec![0; size],
+            observers: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn wrap(&self, address: usize) -> u16 {
+        (address & (self.data.len() - 1)) as u16
+    }
+
+    pub fn read_u8(&self, address: u16) -> u8 {
+        let address = self.wrap(address as usize);
+        let value = self.data[address as usize];
+        self.notify(MemoryAccess::Read { address, value });
+        value
+    }
+
+    // Reads a big-endian word. The second byte wraps around independently.
+    pub fn read_u16(&self, address: u16) -> u16 {
+        let h = self.read_u8(address);
+        let l = self.read_u8(self.wrap(address as usize + 1));
+        ((h as u16) << 8) | l as u16
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let address = self.wrap(address as usize);
+        let previous = self.data[address as usize];
+        self.data[address as usize] = value;
+        self.notify(MemoryAccess::Write { address, previous, value });
+    }
+
+    // Copies `bytes` starting at `address`, wrapping around. Observers are not notified.
+    pub fn load(&mut self, address: u16, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            let address = self.wrap(address as usize + offset);
+            self.data[address as usize] = *byte;
+        }
+    }
+
+    // Overwrites the whole memory as `fill` says. Observers stay attached.
+    pub fn fill(&mut self, fill: MemoryFill) {
+        match fill {
+            MemoryFill::Pattern(byte) => self.data.fill(byte),
+            MemoryFill::Random(seed) => StdRng::seed_from_u64(seed).fill_bytes(&mut self.data),
+        }
+    }
+
+    pub fn attach_observer(&mut self, observer: MemoryObserver) -> ObserverId {
+        let mut observers = self.observers.borrow_mut();
+        observers.push(Some(observer));
+        ObserverId(observers.len() - 1)
+    }
+
+    pub fn detach_observer(&mut self, id: ObserverId) {
+        if let Some(observer) = self.observers.borrow_mut().get_mut(id.0) {
+            *observer = None;
+        }
+    }
+
+    fn notify(&self, access: MemoryAccess) {
+        for observer in self.observers.borrow_mut().iter_mut().flatten() {
+            observer(access);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn addresses_wrap_around_the_memory_size() {
+        let mut memory = Memory::new(0x1000);
+
+        memory.write(0x1005, 0xAB);
+
+        assert_eq!(memory.read_u8(0x0005), 0xAB);
+        assert_eq!(memory.read_u8(0xF005), 0xAB);
+    }
+
+    #[test]
+    fn read_u16_is_big_endian_and_wraps_between_bytes() {
+        let mut memory = Memory::new(0x1000);
+        memory.load(0xFFF, &[0x12, 0x34]);
+
+        assert_eq!(memory.read_u8(0x000), 0x34);
+        assert_eq!(memory.read_u16(0xFFF), 0x1234);
+    }
+
+    #[test]
+    fn sixty_four_kilobyte_memory_uses_16_bit_addresses() {
+        let mut memory = Memory::new(0x10000);
+
+        memory.write(0xF000, 0x42);
+
+        assert_eq!(memory.read_u8(0xF000), 0x42);
+        assert_eq!(memory.read_u8(0x0000), 0x00);
+    }
+
+    #[test]
+    fn random_fill_should_repeat_for_the_same_seed() {
+        let mut memory = Memory::new(0x1000);
+        let mut other = Memory::new(0x1000);
+
+        memory.fill(MemoryFill::Random(7));
+        other.fill(MemoryFill::Random(7));
+        assert_eq!(memory.as_slice(), other.as_slice());
+        assert!(memory.as_slice().iter().any(|byte| *byte != memory.as_slice()[0]));
+
+        other.fill(MemoryFill::Random(8));
+        assert_ne!(memory.as_slice(), other.as_slice());
+    }
+
+    #[test]
+    fn fills_should_parse_from_ids() {
+        assert_eq!(MemoryFill::from_id("zero"), Some(MemoryFill::Pattern(0)));
+        assert_eq!(MemoryFill::from_id("0xA5"), Some(MemoryFill::Pattern(0xA5)));
+        assert_eq!(MemoryFill::from_id("random"), Some(MemoryFill::Random(0)));
+        assert_eq!(MemoryFill::from_id("random:42"), Some(MemoryFill::Random(42)));
+        assert_eq!(MemoryFill::from_id("random:x"), None);
+        assert_eq!(MemoryFill::from_id("garbage"), None);
+    }
+
+    #[test]
+    fn observers_see_reads_and_writes_until_detached() {
+        let mut memory = Memory::new(0x1000);
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let recorded = accesses.clone();
+        let id = memory.attach_observer(Box::new(move |access| recorded.borrow_mut().push(access)));
+
+        memory.write(0x300, 7);
+        memory.read_u8(0x1300);
+        memory.detach_observer(id);
+        memory.write(0x300, 8);
+
+        assert_eq!(*accesses.borrow(), vec![
+            MemoryAccess::Write { address: 0x300, previous: 0, value: 7 },
+            MemoryAccess::Read { address: 0x300, value: 7 },
+        ]);
+    }
+}
@@ -1,96 +1,410 @@
-use rand::rngs::ThreadRng;
-use crate::chip8::display::Display;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use crate::chip8::decode::{DecodeCache, Instruction};
+use crate::chip8::display::{Display, NATIVE_SCREEN_HEIGHT, NATIVE_SCREEN_WIDTH};
 use crate::chip8::keypad::Keypad;
+use std::ops::Range;
+use crate::chip8::font::{BIG_FONT_SIZE, SMALL_FONT_SIZE};
+use crate::chip8::memory::DEFAULT_MEMORY_SIZE;
+use crate::chip8::stack::{Stack, DEFAULT_STACK_DEPTH};
+use crate::chip8::timing::VIP_INTERPRETER_CYCLES_PER_FRAME;
+pub use crate::chip8::font::{Font, FontStyle, DEFAULT_FONT_ADDRESS};
+pub use crate::chip8::memory::{Memory, MemoryAccess, MemoryFill, MemoryObserver, ObserverId};
+pub use crate::chip8::platform::{InstructionSet, Platform};
+pub use crate::chip8::quirks::Quirks;
+pub use crate::chip8::registers::Register;
+pub use crate::chip8::snapshot::Snapshot;
+#[cfg(feature = "jit")]
+pub use crate::chip8::jit::JitError;
+pub use crate::chip8::lint::{Diagnostic, Lint};
+pub use crate::chip8::profile::{Profile, SubroutineProfile};
 
 mod opcodes;
+mod decode;
 mod display;
+mod font;
+#[cfg(feature = "jit")]
+mod jit;
 mod keypad;
+mod lint;
+mod memory;
+mod platform;
+mod profile;
+mod quirks;
+#[cfg(test)]
+mod reference;
+mod registers;
+mod snapshot;
+mod stack;
+mod timing;
+mod variants;
 
 
 
-const STACK_SIZE: usize = 16;
-const MEMORY_SIZE: usize = 4096;
 const V_SIZE: usize = 16;
-const PROGRAM_START_LOCATION: usize = 0x200;
-const PROGRAM_SIZE: usize = MEMORY_SIZE - PROGRAM_START_LOCATION;
+
+/**
+Where the program counter goes once an instruction has executed.
+Every opcode handler returns one, so control flow is decided in a single place.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProgramCounter {
+    // Continue with the instruction that follows.
+    Next,
+    // Skip the instruction that follows, whatever its size.
+    Skip,
+    // Continue at the given address.
+    Jump(u16),
+    // Execute the same instruction again, e.g. while waiting for a key.
+    Wait,
+}
+
+impl ProgramCounter {
+    pub fn skip_if(condition: bool) -> ProgramCounter {
+        if condition { ProgramCounter::Skip } else { ProgramCounter::Next }
+    }
+}
+
+// Errors that halt the machine. `pc` is the address of the faulting instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fault {
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    UnknownOpcode { pc: u16, opcode: u16 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow { pc } => write!(f, "stack overflow at {:03X}", pc),
+            Fault::StackUnderflow { pc } => write!(f, "stack underflow at {:03X}", pc),
+            Fault::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode {:04X} at {:03X}", opcode, pc),
+        }
+    }
+}
+
+// Debuggers setting breakpoints. Each owns its own, so one removing a breakpoint leaves another's at the same address.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum BreakpointOwner {
+    Gdb,
+    Dap,
+}
+
+// A program address past the end of the platform memory.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AddressOutOfRange {
+    pub address: u16,
+    pub memory_size: usize,
+}
+
+impl fmt::Display for AddressOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "program address {:03X} is past the end of the {} bytes of memory", self.address, self.memory_size)
+    }
+}
 
 pub struct Chip8 {
+    platform: Platform,
     keypad: Keypad,
+    // CHIP-8X's second keypad.
+    second_keypad: Keypad,
     display: Display,
-    memory: Vec<u8>,
+    memory: Memory,
+    memory_fill: MemoryFill,
+    // Where programs are loaded and started, instead of the platform addresses.
+    program_address: Option<u16>,
     v: [u8; V_SIZE],
     address_register: u16,
     pc: u16,
     stack: Stack,
-    stack_pointer: u8,
 
     play: bool,
-    skip_increment_pc: bool,
+    fault: Option<Fault>,
+    // Key pressed during FX0A, stored in VX once it is released.
+    awaited_key: Option<usize>,
+    // FX4F set the delay timer and waits for it to run out.
+    waiting_delay: bool,
+    // With the vblank quirk, a sprite was drawn this frame, so the next DXYN waits for the next one.
+    drawn_this_frame: bool,
+    waiting_vblank: bool,
+    // Machine cycles spent in the current frame with the vip_timing quirk.
+    frame_cycles: u32,
 
     timer_delay: u8,
     timer_sound: u8,
-    rng: ThreadRng,
+    rng: StdRng,
+    quirks: Quirks,
+    font: Font,
+    font_address: u16,
+    decode_cache: DecodeCache,
+    #[cfg(feature = "jit")]
+    jit: Option<jit::Jit>,
+    linter: Option<lint::Linter>,
+    profile: Option<Profile>,
+    // Who set a breakpoint at each address. Addresses nobody holds a breakpoint at anymore are removed.
+    breakpoints: BTreeMap<u16, BTreeSet<BreakpointOwner>>,
+    // run_frame paused before the instruction at PC because of a breakpoint, so it runs on resume.
+    paused_at_breakpoint: bool,
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
 }
 
 impl Chip8 {
     pub fn new() -> Chip8 {
-        Chip8 {
+        let mut chip8 = Chip8 {
+            platform: Platform::default(),
             keypad: Keypad::new(),
-            display: Display::new(),
-            memory: vec![0; PROGRAM_START_LOCATION],
+            second_keypad: Keypad::new(),
+            display: Display::new(NATIVE_SCREEN_WIDTH, NATIVE_SCREEN_HEIGHT),
+            memory: Memory::new(DEFAULT_MEMORY_SIZE),
+            memory_fill: MemoryFill::default(),
+            program_address: None,
             v: [0; 16],
             address_register: 0,
-            pc: PROGRAM_START_LOCATION as u16,
-            stack: Stack::new(),
-            stack_pointer: 0,
+            pc: Platform::default().start_address(),
+            stack: Stack::new(DEFAULT_STACK_DEPTH),
             play: false,
-            skip_increment_pc: false,
+            fault: None,
+            awaited_key: None,
+            waiting_delay: false,
+            drawn_this_frame: false,
+            waiting_vblank: false,
+            frame_cycles: 0,
             timer_delay: 0,
             timer_sound: 0,
-            rng: rand::thread_rng(),
-        }
+            rng: StdRng::from_entropy(),
+            quirks: Quirks::default(),
+            font: Font::default(),
+            font_address: DEFAULT_FONT_ADDRESS,
+            decode_cache: DecodeCache::new(),
+            #[cfg(feature = "jit")]
+            jit: None,
+            linter: None,
+            profile: None,
+            breakpoints: BTreeMap::new(),
+            paused_at_breakpoint: false,
+        };
+        chip8.load_font();
+        chip8
     }
 
     pub fn reset(&mut self) {
-        self.memory = vec![0; 80];
+        self.memory.fill(self.memory_fill);
+        self.forget_decoded_code();
+        if let Some(linter) = &mut self.linter {
+            linter.reset(self.memory.size());
+        }
+        self.restart_profile();
         self.v = [0; 16];
         self.address_register = 0;
-        self.pc = PROGRAM_START_LOCATION as u16;
-        self.stack = Stack::new();
-        self.stack_pointer = 0;
-        // TODO reset keypad
-        // TODO reset something_left_to_draw flag
-        // TODO reset clear_screen flag.
+        self.pc = self.start_address();
+        self.stack = Stack::new(self.stack.depth());
+        self.fault = None;
+        self.paused_at_breakpoint = false;
+        self.awaited_key = None;
+        self.waiting_delay = false;
+        self.drawn_this_frame = false;
+        self.waiting_vblank = false;
+        self.frame_cycles = 0;
+        self.timer_delay = 0;
+        self.timer_sound = 0;
+        self.keypad = Keypad::new();
+        self.second_keypad = Keypad::new();
+        self.display.clear();
         self.load_font();
     }
 
     fn load_font(&mut self) {
-        // Initialize the fonts
-        let fonts: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-        self.memory[..80].copy_from_slice(&fonts);
-        self.memory.extend_from_slice(&vec![0u8; 0x200 - 80])
+        let address = self.font_address;
+        self.memory.load(address, self.font.small());
+        self.memory.load(address.wrapping_add(SMALL_FONT_SIZE as u16), self.font.big());
+        self.mark_loaded(address, SMALL_FONT_SIZE + BIG_FONT_SIZE);
+        self.forget_decoded_code();
     }
 
+    // Resets the machine and loads `program` at the load address.
     pub fn load(&mut self, program: Vec<u8>) {
         self.reset();
-        self.memory.extend_from_slice(&program);
+        let address = self.load_address();
+        let size = program.len().min(self.memory.size() - address as usize);
+        self.memory.load(address, &program[..size]);
+        self.mark_loaded(address, size);
+        self.forget_decoded_code();
+    }
+
+    // Tells the linter that `size` bytes from `address` were loaded, so reading them is fine.
+    fn mark_loaded(&mut self, address: u16, size: usize) {
+        if let Some(linter) = &mut self.linter {
+            for offset in 0..size {
+                linter.mark_written(self.memory.wrap(address as usize + offset));
+            }
+        }
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /**
+    Switches to the memory layout, display geometry and instruction set of `platform`. Memory is
+    replaced with a freshly filled one of the platform size, the stack is emptied and PC goes to
+    the start address. Quirks and font are left alone, since ROM metadata may override the
+    platform defaults.
+     */
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.set_memory_size(platform.memory_size());
+        self.set_stack_depth(platform.stack_depth());
+        let (width, height) = platform.display_size();
+        self.display = match platform {
+            Platform::Chip8X => Display::with_colours(width, height),
+            _ => Display::new(width, height),
+        };
+        self.pc = self.start_address();
+    }
+
+    /**
+    Loads and starts programs at `address` instead of the platform addresses, e.g. at 0x600 for
+    ETI-660 programs, or at 0x2C0 for hi-res programs stripped of their 0x200-0x2BF prologue.
+    `None` goes back to the platform addresses. Takes effect on the next reset. Addresses past
+    the end of the memory of the current platform are refused, so set the platform first. Changing
+    to a platform with less memory than the address needs goes back to the platform addresses.
+     */
+    pub fn set_program_address(&mut self, address: Option<u16>) -> Result<(), AddressOutOfRange> {
+        if let Some(address) = address.filter(|address| *address as usize >= self.memory.size()) {
+            return Err(AddressOutOfRange { address, memory_size: self.memory.size() });
+        }
+        self.program_address = address;
+        Ok(())
+    }
+
+    pub fn program_address(&self) -> Option<u16> {
+        self.program_address
+    }
+
+    // Where `load` stores programs.
+    pub fn load_address(&self) -> u16 {
+        self.program_address.unwrap_or(self.platform.load_address())
+    }
+
+    // Where PC goes on reset.
+    pub fn start_address(&self) -> u16 {
+        self.program_address.unwrap_or(self.platform.start_address())
+    }
+
+    // What memory holds before the font and program are loaded. Takes effect on the next reset.
+    pub fn set_memory_fill(&mut self, fill: MemoryFill) {
+        self.memory_fill = fill;
+    }
+
+    // Width and height of the screen in pixels.
+    pub fn display_size(&self) -> (usize, usize) {
+        (self.display.width(), self.display.height())
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.memory.as_slice()
+    }
+
+    pub fn attach_memory_observer(&mut self, observer: MemoryObserver) -> ObserverId {
+        self.memory.attach_observer(observer)
+    }
+
+    pub fn detach_memory_observer(&mut self, id: ObserverId) {
+        self.memory.detach_observer(id);
+    }
+
+    /**
+    Enables or disables caching decoded instructions by address. Cached instructions are
+    invalidated when the program writes over them, so self-modifying code keeps working, but
+    instruction fetches are no longer reported to memory observers.
+     */
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache.resize(if enabled { self.memory.size() } else { 0 });
+    }
+
+    // Replaces the memory with a freshly filled one of `size` bytes, e.g. 64KB for XO-CHIP. Observers are dropped.
+    pub fn set_memory_size(&mut self, size: usize) {
+        if self.program_address.is_some_and(|address| address as usize >= size) {
+            self.program_address = None;
+        }
+        self.memory = Memory::new(size);
+        self.memory.fill(self.memory_fill);
+        if let Some(linter) = &mut self.linter {
+            linter.reset(size);
+        }
+        self.set_decode_cache(self.decode_cache.is_enabled());
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.resize(size);
+        }
+        self.load_font();
+    }
+
+    pub fn write_memory(&mut self, address: usize, value: u8) {
+        self.store(self.memory.wrap(address), value);
+    }
+
+    // Return addresses currently on the stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        self.stack.entries()
+    }
+
+    pub fn write_stack(&mut self, index: usize, value: u16) {
+        self.stack.write(index, value);
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.stack.depth()
+    }
+
+    // Replaces the stack with an empty one holding up to `depth` return addresses.
+    pub fn set_stack_depth(&mut self, depth: usize) {
+        self.stack = Stack::new(depth);
+    }
+
+    // The fault that halted the program, if any. It is cleared by `reset`.
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    // Replaces the font, which is written to memory right away.
+    pub fn set_font(&mut self, font: Font) {
+        self.font = font;
+        self.load_font();
+    }
+
+    // Moves the font, e.g. to 0x50 like some interpreters. The old copy is left in memory until reset.
+    pub fn set_font_address(&mut self, address: u16) {
+        self.font_address = address;
+        self.load_font();
+    }
+
+    // Memory taken by the small glyphs followed by the big ones.
+    pub fn font_range(&self) -> Range<usize> {
+        let start = self.font_address as usize;
+        start..start + SMALL_FONT_SIZE + BIG_FONT_SIZE
+    }
+
+    // Makes CXNN draw the same numbers on every run, e.g. to compare two emulators.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        // Cycles counted under other quirks must not starve the next frame.
+        self.frame_cycles = 0;
     }
 
     pub fn start(&mut self) {
@@ -105,23 +419,173 @@ impl Chip8 {
         self.play = false;
     }
 
-    pub fn run(&mut self) {
-        if self.play {
+    pub fn is_playing(&self) -> bool {
+        self.play
+    }
+
+    /**
+    Stops the program before it executes the instruction at `address`: `run_frame` pauses and
+    ends the frame there, like it does on a fault, and resuming executes that instruction.
+    Breakpoints are kept across resets. While any is set, the recompiler is bypassed so that
+    none is run past. The breakpoint stays until every owner that set it removed it. Returns false
+    if `owner` already had one there.
+     */
+    pub fn add_breakpoint(&mut self, address: u16, owner: BreakpointOwner) -> bool {
+        self.breakpoints.entry(address).or_default().insert(owner)
+    }
+
+    // Returns false if `owner` had none there.
+    pub fn remove_breakpoint(&mut self, address: u16, owner: BreakpointOwner) -> bool {
+        let Some(owners) = self.breakpoints.get_mut(&address) else { return false };
+        let removed = owners.remove(&owner);
+        if owners.is_empty() {
+            self.breakpoints.remove(&address);
+        }
+        removed
+    }
+
+    // Removes every breakpoint `owner` set.
+    pub fn clear_breakpoints(&mut self, owner: BreakpointOwner) {
+        self.breakpoints.retain(|_, owners| {
+            owners.remove(&owner);
+            !owners.is_empty()
+        });
+    }
+
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains_key(&address)
+    }
+
+    // Addresses with a breakpoint, whoever set it.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    // Whether run_frame paused at a breakpoint and nothing ran since.
+    pub fn is_paused_at_breakpoint(&self) -> bool {
+        self.paused_at_breakpoint && !self.play
+    }
+
+    /**
+    Runs one 60Hz frame: executes `instructions` opcodes, then updates the timers once and
+    forgets the key presses and releases of the frame. With the vip_timing quirk, `instructions`
+    is ignored and opcodes run until the machine cycles a COSMAC VIP has per frame are spent.
+    The frame ends early when DXYN waits for the vertical blank, the program stops, a fault is raised
+    or a breakpoint is reached, which pauses the emulator.
+    Returns the number of instructions executed, which is 0 while the emulator is not playing.
+     */
+    pub fn run_frame(&mut self, instructions: u32) -> u32 {
+        self.run_frame_with(instructions, None)
+    }
+
+    /**
+    Runs one frame like `run_frame`, but checks `stop` before every instruction, and pauses and
+    ends the frame there as soon as it returns true, like a breakpoint. Debuggers step with it,
+    so stepping goes through the same loop, timers and keypad as running. The recompiler is
+    bypassed so that every instruction is checked.
+     */
+    pub fn run_frame_until(&mut self, instructions: u32, mut stop: impl FnMut(&Chip8) -> bool) -> u32 {
+        self.run_frame_with(instructions, Some(&mut stop))
+    }
+
+    fn run_frame_with(&mut self, instructions: u32, mut stop: Option<&mut dyn FnMut(&Chip8) -> bool>) -> u32 {
+        if !self.play {
+            return 0;
+        }
+
+        let mut executed = 0;
+        while self.play && self.fault.is_none() && !self.waiting_vblank {
+            let frame_done = if self.quirks.vip_timing {
+                self.frame_cycles >= VIP_INTERPRETER_CYCLES_PER_FRAME
+            } else {
+                executed >= instructions
+            };
+            if frame_done {
+                break;
+            }
+            if !self.paused_at_breakpoint && self.breakpoints.contains_key(&self.pc) {
+                self.paused_at_breakpoint = true;
+                self.play = false;
+                break;
+            }
+            if let Some(stop) = &mut stop {
+                if stop(self) {
+                    self.play = false;
+                    break;
+                }
+            } else {
+                #[cfg(feature = "jit")]
+                if let Some(count) = self.run_block(instructions.saturating_sub(executed)) {
+                    executed += count;
+                    continue;
+                }
+            }
+
             self.tick();
+            executed += 1;
         }
 
+        // Cycles spent past the end of the frame are taken from the next one. Waiting for the
+        // vertical blank uses up the rest of the frame.
+        self.frame_cycles = if self.waiting_vblank { 0 } else { self.frame_cycles.saturating_sub(VIP_INTERPRETER_CYCLES_PER_FRAME) };
+        self.drawn_this_frame = false;
+        self.waiting_vblank = false;
+        self.update_timers();
+        self.keypad.end_frame();
+        self.second_keypad.end_frame();
+
+        executed
+    }
+
+    pub fn press_key(&mut self, key_index: usize) {
+        self.keypad.press(key_index);
+    }
+
+    pub fn release_key(&mut self, key_index: usize) {
+        self.keypad.release(key_index);
+    }
+
+    // Keys of CHIP-8X's second keypad, read by EXF2 and EXF5.
+    pub fn press_second_key(&mut self, key_index: usize) {
+        self.second_keypad.press(key_index);
+    }
+
+    pub fn release_second_key(&mut self, key_index: usize) {
+        self.second_keypad.release(key_index);
+    }
+
+    pub fn render(&self) {
         self.display.render();
     }
 
-    pub fn tick(&mut self) {
-        let h = self.memory[self.pc as usize];
-        let l = self.memory[self.pc as usize + 1];
-        let opcode: u16 = ((h as u16) << 8) + l as u16;
+    // Whether the pixel at (x, y) of the screen is lit, for frontends that draw it themselves.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.display.pixel(x, y)
+    }
 
-        println!("PC: {:x} OPCODE:({:x}/{:x}) => {:x}", self.pc, h, l, opcode);
+    // Executes a single instruction. Timers are not touched, see `update_timers`.
+    // Nothing is executed once a fault has been raised.
+    pub fn tick(&mut self) {
+        if self.fault.is_some() {
+            return;
+        }
 
-        self.execute_operation(opcode);
+        self.paused_at_breakpoint = false;
+        let pc = self.pc;
+        let instruction = self.fetch(pc);
+        self.lint_before(instruction);
+        let next = self.execute(instruction);
+        self.lint_after(pc, instruction, next);
+        self.profile_after(pc, instruction);
+        // Only counted with VIP timing, which ends frames by cycles. Instructions stepped one by
+        // one outside frames still add up, so the count saturates.
+        if self.quirks.vip_timing {
+            self.frame_cycles = self.frame_cycles.saturating_add(self.vip_cycles(instruction.opcode(), next));
+        }
+    }
 
+    // Decrements delay and sound timers. Must be called at 60Hz.
+    pub fn update_timers(&mut self) {
         if self.timer_delay > 0 {
             self.timer_delay -= 1;
         }
@@ -131,97 +595,179 @@ impl Chip8 {
         }
     }
 
-    fn execute_operation(&mut self, opcode: u16) {
-        let address = opcode & 0x0FFF;
-        let x: usize = ((opcode & 0x0F00) >> 8) as usize & 0b00001111;
-        let y: usize = ((opcode & 0x00F0) >> 4) as usize & 0b00001111;
-        let nibble: u8 = (opcode & 0x000F) as u8;
-        let nn: u8 = (opcode & 0x00FF) as u8;
-
-        match opcode & 0xF000 {
-            0x0000 => {
-                match opcode {
-                    0x00E0 => self.opcode_clear_screen(),
-                    0x00EE => self.return_from_subroutine(),
-                    0x00FF => self.set_schip_graphic_mode(),
-                    _ => panic!("Unknown Opcode {}", opcode)
-                }
-            }
-            0x1000 => self.opcode_jmp(address),
-            0x2000 => self.opcode_call_subroutine(address),
-            0x3000 => self.opcode_skip_if_vx_equals_nn(x, nn),
-            0x4000 => self.opcode_skip_if_vx_diffs_nn(x, nn),
-            0x5000 => self.opcode_skip_if_vx_equals_vy(x, y),
-            0x6000 => self.opcode_set_vx_to_nn(x, nn),
-            0x7000 => self.opcode_adds_nn_to_vx(x, nn),
-            0x8000 => self.opcode_set_vx_to_vy(opcode, x, y),
-            0x9000 => self.opcode_skips_if_vx_diffs_vy(x, y),
-            0xA000 => self.opcode_set_i_to_nnn(address),
-            0xB000 => self.opcode_jmp_nnn_plus_v0(address),
-            0xC000 => self.opcode_set_vx_random(x, nn),
-            0xD000 => self.opcode_draw(x, y, nibble),
-            0xE000 => {
-                match opcode & 0xFF {
-                    0x9E => self.opcode_skip_key_pressed_in_vx(x),
-                    0xA1 => self.opcode_skip_key_not_pressed_in_vx(x),
-                    _ => panic!("Unknown Opcode {}", opcode)
-                }
-            }
-            0xF000 => {
-                match opcode & 0xFF {
-                    0x07 => self.opcode_save_delay_to_vx(x),
-                    0x0A => self.opcode_wait_key(x),
-                    0x15 => self.opcode_save_vx_to_delay(x),
-                    0x18 => self.opcode_save_vx_to_sound_timer(x),
-                    0x1E => self.opcode_adds_vx_to_i(x),
-                    0x29 => self.opcode_set_i_with_vx(x),
-                    0x33 => self.opcode_save_bin_vx(x),
-                    0x55 => self.opcode_dump_v_to_memory(x),
-                    0x65 => self.opcode_fill_v_with_memory(x),
-                    _ => panic!("Unknown Opcode {}", opcode)
-                }
+    // Executes `instruction`, which is assumed to be stored at PC, and moves PC to the next one.
+    // Returns where PC went.
+    fn execute(&mut self, instruction: Instruction) -> ProgramCounter {
+        let next = instruction.execute(self);
+        self.pc = match next {
+            ProgramCounter::Next => self.pc.wrapping_add(instruction.size()),
+            ProgramCounter::Skip => {
+                let following = self.pc.wrapping_add(instruction.size());
+                following.wrapping_add(self.platform.instruction_set().instruction_size(self.opcode_at(following)))
             }
-            _ => println!("Unknown Opcode {}", opcode),
+            ProgramCounter::Jump(address) => address,
+            ProgramCounter::Wait => self.pc,
+        };
+        next
+    }
+
+    // Decodes the instruction at `address`, or takes it from the decode cache when enabled.
+    fn fetch(&mut self, address: u16) -> Instruction {
+        if let Some(instruction) = self.decode_cache.get(address) {
+            return instruction;
         }
-        self.increment_pc();
+
+        let instruction = Instruction::decode(self.opcode_at(address), self.platform.instruction_set());
+        self.decode_cache.insert(address, instruction);
+        instruction
     }
 
-    fn increment_pc(&mut self) {
-        if self.skip_increment_pc {
-            self.skip_increment_pc = false;
-            return;
+    // Writes a byte on behalf of the program, dropping cached instructions decoded from it.
+    fn store(&mut self, address: u16, value: u8) {
+        let address = self.memory.wrap(address as usize);
+        self.memory.write(address, value);
+        self.decode_cache.invalidate(address);
+        if let Some(linter) = &mut self.linter {
+            linter.mark_written(address);
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.invalidate(address);
         }
+    }
 
-        self.pc += 2;
+    // Drops every instruction decoded or translated from memory, after it was loaded behind their back.
+    fn forget_decoded_code(&mut self) {
+        self.decode_cache.clear();
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.clear();
+        }
+    }
+
+    // Halts the program on the faulting instruction, so PC still points at it.
+    fn raise(&mut self, fault: Fault) -> ProgramCounter {
+        self.fault = Some(fault);
+        self.play = false;
+        ProgramCounter::Wait
     }
-}
 
-struct Stack {
-    data: [u16; STACK_SIZE],
-    top: usize,
+    fn opcode_at(&self, address: u16) -> u16 {
+        self.memory.read_u16(address)
+    }
 }
 
-impl Stack {
-    pub fn new() -> Self {
-        Stack {
-            data: [0; STACK_SIZE],
-            top: 0,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_address_should_override_where_programs_load_and_start() {
+        let mut emu = Chip8::new();
+        emu.set_platform(Platform::HiResChip8);
+        emu.set_program_address(Some(0x600)).unwrap();
+
+        emu.load(vec![0x60, 0x2A]);
+        emu.start();
+        emu.run_frame(1);
+
+        assert_eq!((emu.v[0], emu.pc), (0x2A, 0x602));
+
+        emu.set_program_address(None).unwrap();
+        emu.reset();
+        assert_eq!(emu.pc, 0x2C0);
     }
 
-    pub fn push(&mut self, value: u16) {
-        if self.top < STACK_SIZE {
-            self.data[self.top] = value;
-            self.top += 1
-        }
+    #[test]
+    fn program_addresses_past_the_end_of_memory_should_be_refused() {
+        let rom = crate::rom_loader::parse_hex_listing(b"1000: 00E0").unwrap();
+        let mut emu = Chip8::new();
+
+        assert_eq!(
+            emu.set_program_address(rom.start_address),
+            Err(AddressOutOfRange { address: 0x1000, memory_size: 0x1000 })
+        );
+        assert_eq!(emu.program_address(), None);
+
+        emu.set_platform(Platform::XoChip);
+        emu.set_program_address(rom.start_address).unwrap();
+        emu.load(rom.program.clone());
+        assert_eq!(emu.pc, 0x1000);
+        assert_eq!(&emu.memory()[0x1000..0x1002], &[0x00, 0xE0]);
+
+        // The address no longer fits once the memory shrinks.
+        emu.set_platform(Platform::ModernChip8);
+        assert_eq!(emu.program_address(), None);
+        emu.load(rom.program);
+        assert_eq!(emu.pc, 0x200);
+        assert_eq!(&emu.memory()[0x200..0x202], &[0x00, 0xE0]);
     }
 
-    pub fn pop(&mut self) -> u16 {
-        if self.top > 0 {
-            self.top -= 1;
-            return self.data[self.top];
-        }
+    #[test]
+    fn memory_fill_should_only_cover_what_is_not_loaded() {
+        let mut emu = Chip8::new();
+        emu.set_memory_fill(MemoryFill::Pattern(0xA5));
 
-        return 0;
+        emu.load(vec![0x12, 0x00]);
+
+        let font = emu.font_range().start;
+        assert_eq!(&emu.memory()[font..font + SMALL_FONT_SIZE], Font::default().small());
+        assert_eq!(&emu.memory()[0x200..0x203], &[0x12, 0x00, 0xA5]);
+        assert_eq!(emu.memory()[0xFFF], 0xA5);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn breakpoints_should_pause_before_the_instruction_and_let_it_run_on_resume() {
+        let mut emu = Chip8::new();
+        #[cfg(feature = "jit")]
+        emu.set_jit(true).unwrap();
+        // Counts V0 up forever.
+        emu.load(vec![0x70, 0x01, 0x61, 0x00, 0x12, 0x00]);
+        emu.add_breakpoint(0x202, BreakpointOwner::Gdb);
+        emu.start();
+
+        assert_eq!(emu.run_frame(100), 1);
+        assert!(emu.is_paused_at_breakpoint());
+        assert_eq!((emu.pc, emu.v[0]), (0x202, 1));
+
+        emu.start();
+        assert_eq!(emu.run_frame(100), 3);
+        assert_eq!((emu.pc, emu.v[0]), (0x202, 2));
+
+        emu.remove_breakpoint(0x202, BreakpointOwner::Gdb);
+        emu.start();
+        assert_eq!(emu.run_frame(100), 100);
+        assert!(!emu.is_paused_at_breakpoint());
+    }
+
+    #[test]
+    fn breakpoints_should_stay_until_every_owner_removed_them() {
+        let mut emu = Chip8::new();
+
+        assert!(emu.add_breakpoint(0x202, BreakpointOwner::Dap));
+        assert!(emu.add_breakpoint(0x202, BreakpointOwner::Gdb));
+        assert!(!emu.add_breakpoint(0x202, BreakpointOwner::Gdb));
+        assert!(emu.add_breakpoint(0x204, BreakpointOwner::Dap));
+
+        assert!(emu.remove_breakpoint(0x202, BreakpointOwner::Dap));
+        assert!(!emu.remove_breakpoint(0x202, BreakpointOwner::Dap));
+        assert!(emu.has_breakpoint(0x202));
+
+        emu.clear_breakpoints(BreakpointOwner::Gdb);
+        assert_eq!(emu.breakpoints().collect::<Vec<_>>(), vec![0x204]);
+    }
+
+    #[test]
+    fn run_frame_until_should_pause_before_the_first_instruction_it_stops_at() {
+        let mut emu = Chip8::new();
+        #[cfg(feature = "jit")]
+        emu.set_jit(true).unwrap();
+        // Counts V0 up forever.
+        emu.load(vec![0x70, 0x01, 0x61, 0x00, 0x12, 0x00]);
+        emu.start();
+
+        assert_eq!(emu.run_frame_until(100, |emu| emu.v[0] == 3), 7);
+        assert!(!emu.is_playing());
+        assert_eq!((emu.pc, emu.v[0]), (0x202, 3));
+    }
+}
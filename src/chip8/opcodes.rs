@@ -1,107 +1,127 @@
-use super::Chip8;
+use super::{Chip8, Fault, ProgramCounter};
 use rand::Rng;
+use crate::chip8::font::SMALL_FONT_SIZE;
 use crate::chip8::{V_SIZE};
 
 impl Chip8 {
-    pub fn opcode_clear_screen(&mut self) {
+    pub fn opcode_clear_screen(&mut self) -> ProgramCounter {
         self.display.clear();
+        ProgramCounter::Next
     }
 
-    pub fn return_from_subroutine(&mut self) {
-        self.pc = self.stack.pop();
-        self.skip_increment_pc = true;
+    pub fn return_from_subroutine(&mut self) -> ProgramCounter {
+        match self.stack.pop() {
+            Some(address) => ProgramCounter::Jump(address),
+            None => self.raise(Fault::StackUnderflow { pc: self.pc }),
+        }
     }
 
-    pub fn set_schip_graphic_mode(&mut self) {}
-
-    pub fn opcode_jmp(&mut self, address: u16) {
-        self.pc = address;
-        self.skip_increment_pc = true;
+    pub fn set_schip_graphic_mode(&mut self) -> ProgramCounter {
+        ProgramCounter::Next
     }
 
-    pub fn opcode_call_subroutine(&mut self, address: u16) {
-        self.stack.push(self.pc + 2);
-        self.pc = address;
-        self.skip_increment_pc = true;
+    pub fn opcode_jmp(&mut self, address: u16) -> ProgramCounter {
+        ProgramCounter::Jump(address)
     }
-    pub fn opcode_skip_if_vx_equals_nn(&mut self, x: usize, nn: u8) {
-        if self.v[x] == nn {
-            self.increment_pc();
+
+    pub fn opcode_call_subroutine(&mut self, address: u16) -> ProgramCounter {
+        if !self.stack.push(self.pc.wrapping_add(2)) {
+            return self.raise(Fault::StackOverflow { pc: self.pc });
         }
+        ProgramCounter::Jump(address)
     }
-    pub fn opcode_skip_if_vx_diffs_nn(&mut self, x: usize, nn: u8) {
-        if self.v[x] != nn {
-            self.increment_pc();
-        }
+    pub fn opcode_skip_if_vx_equals_nn(&mut self, x: usize, nn: u8) -> ProgramCounter {
+        ProgramCounter::skip_if(self.v[x] == nn)
     }
-    pub fn opcode_skip_if_vx_equals_vy(&mut self, x: usize, y: usize) {
-        if self.v[x] == self.v[y] {
-            self.increment_pc();
-        }
+    pub fn opcode_skip_if_vx_diffs_nn(&mut self, x: usize, nn: u8) -> ProgramCounter {
+        ProgramCounter::skip_if(self.v[x] != nn)
+    }
+    pub fn opcode_skip_if_vx_equals_vy(&mut self, x: usize, y: usize) -> ProgramCounter {
+        ProgramCounter::skip_if(self.v[x] == self.v[y])
     }
-    pub fn opcode_set_vx_to_nn(&mut self, x: usize, nn: u8) {
+    pub fn opcode_set_vx_to_nn(&mut self, x: usize, nn: u8) -> ProgramCounter {
         self.v[x] = nn;
+        ProgramCounter::Next
     }
-    pub fn opcode_adds_nn_to_vx(&mut self, x: usize, nn: u8) {
+    pub fn opcode_adds_nn_to_vx(&mut self, x: usize, nn: u8) -> ProgramCounter {
         self.v[x] = self.v[x].wrapping_add(nn);
-    }
-    pub fn opcode_set_vx_to_vy(&mut self, opcode: u16, x: usize, y: usize) {
-        match opcode & 0x0F {
-            0 => self.v[x] = self.v[y],
-            1 => self.v[x] |= self.v[y],
-            2 => self.v[x] &= self.v[y],
-            3 => self.v[x] ^= self.v[y],
+        ProgramCounter::Next
+    }
+    // 8XYN	Register to register operations.
+    // As on the original interpreter, the VF flag is written after the result, so it prevails
+    // when X is F, and the operands are read before either is written, so VF can be an operand.
+    pub fn opcode_set_vx_to_vy(&mut self, opcode: u16, x: usize, y: usize) -> ProgramCounter {
+        let vx = self.v[x];
+        let vy = self.v[y];
+        // Shifts take VY as the operand, unless the shift quirk shifts VX in place.
+        let shifted = if self.quirks.shift { vx } else { vy };
+
+        let (result, flag) = match opcode & 0x0F {
+            0 => (vy, None),
+            1 => (vx | vy, self.logic_flag()),
+            2 => (vx & vy, self.logic_flag()),
+            3 => (vx ^ vy, self.logic_flag()),
             4 => {
                 // 8XY4	Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there isn't.
-                let sum: u16 = self.v[y] as u16 + self.v[x] as u16;
-                if sum > 0xFF {
-                    self.v[0xF] = 1; // Carry
-                } else {
-                    self.v[0xF] = 0; // No carry
-                }
-                self.v[x] = self.v[x].wrapping_add(self.v[y]);
+                let (sum, carry) = vx.overflowing_add(vy);
+                (sum, Some(carry as u8))
             }
             5 => {
                 // 8XY5	VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there isn't.
-                //self.v[0xF] = if self.v[y] > self.v[x] {0} else {1};
-                self.v[0xF] = if self.v[x] >= self.v[y] { 1 } else { 0 };
-                self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                let (difference, borrow) = vx.overflowing_sub(vy);
+                (difference, Some(!borrow as u8))
             }
             6 => {
-                // 8XY6	Shifts VX right by one. VF is set to the value of the least significant bit of VX before the shift.[2]
-                self.v[0xF] = self.v[x] & 0x01;
-                self.v[x] >>= 1;
+                // 8XY6	Shifts right by one. VF is set to the value of the least significant bit before the shift.
+                (shifted >> 1, Some(shifted & 0x01))
             }
             7 => {
                 // 8XY7	Sets VX to (VY minus VX). VF is set to 0 when there's a borrow, and 1 when there isn't.
-                self.v[0xF] = if self.v[y] >= self.v[x] { 1 } else { 0 };
-                self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                let (difference, borrow) = vy.overflowing_sub(vx);
+                (difference, Some(!borrow as u8))
             }
             0xE => {
-                self.v[0xF] = (self.v[x] >> 7) & 0x1;
-                self.v[x] <<= 1;
+                // 8XYE	Shifts left by one. VF is set to the value of the most significant bit before the shift.
+                (shifted << 1, Some(shifted >> 7))
             }
-            _ => { panic!("unhandled opcode") }
+            // Other operations are decoded as unknown opcodes.
+            _ => unreachable!(),
+        };
+
+        self.v[x] = result;
+        if let Some(flag) = flag {
+            self.v[0xF] = flag;
         }
+        ProgramCounter::Next
+    }
+
+    // The original interpreter leaves VF at 0 after 8XY1/8XY2/8XY3.
+    fn logic_flag(&self) -> Option<u8> {
+        if self.quirks.logic { Some(0) } else { None }
     }
 
     // Skips the next instruction if VX doesn't equal VY.
-    pub fn opcode_skips_if_vx_diffs_vy(&mut self, x: usize, y: usize) {
-        if self.v[x] != self.v[y] {
-            self.increment_pc();
-            self.skip_increment_pc = true;
-        }
+    pub fn opcode_skips_if_vx_diffs_vy(&mut self, x: usize, y: usize) -> ProgramCounter {
+        ProgramCounter::skip_if(self.v[x] != self.v[y])
     }
-    pub fn opcode_set_i_to_nnn(&mut self, nnn: u16) {
+    // F000 NNNN	XO-CHIP: sets I to the 16-bit address stored right after the opcode.
+    pub fn opcode_set_i_long(&mut self) -> ProgramCounter {
+        self.address_register = self.opcode_at(self.pc.wrapping_add(2));
+        ProgramCounter::Next
+    }
+    pub fn opcode_set_i_to_nnn(&mut self, nnn: u16) -> ProgramCounter {
         self.address_register = nnn;
+        ProgramCounter::Next
     }
-    pub fn opcode_jmp_nnn_plus_v0(&mut self, nnn: u16) {
-        self.pc = nnn + (self.v[0] as u16);
-        self.skip_increment_pc = true;
+    // BNNN	Jumps to NNN plus V0. With the jump quirk it is BXNN, jumping to XNN plus VX.
+    pub fn opcode_jmp_nnn_plus_v0(&mut self, nnn: u16) -> ProgramCounter {
+        let offset_register = if self.quirks.jump { (nnn >> 8) as usize } else { 0 };
+        ProgramCounter::Jump(nnn.wrapping_add(self.v[offset_register] as u16))
     }
-    pub fn opcode_set_vx_random(&mut self, x: usize, nn: u8) {
+    pub fn opcode_set_vx_random(&mut self, x: usize, nn: u8) -> ProgramCounter {
         let random_number: u8 = self.rng.gen_range(0..=255);
         self.v[x] = random_number & nn;
+        ProgramCounter::Next
     }
 
     // Draws a sprite at coordinate (VX,VY) that has a width of 8 pixels and a height of N pixels.
@@ -111,8 +131,18 @@ impl Chip8 {
     // and to 0 if that doesn't happen.
     // All drawing is XOR drawing (i.e. it toggles the screen pixels)
     // 0xDXYN
-    pub fn opcode_draw(&mut self, x: usize, y: usize, n: u8) {
+    // With the vblank quirk only one sprite is drawn per frame, later ones wait for the next frame.
+    pub fn opcode_draw(&mut self, x: usize, y: usize, n: u8) -> ProgramCounter {
+        if self.quirks.vblank {
+            if self.drawn_this_frame {
+                self.waiting_vblank = true;
+                return ProgramCounter::Wait;
+            }
+            self.drawn_this_frame = true;
+        }
+
         self.v[0xF] = if self.draw_sprite(self.v[x], self.v[y], self.address_register, n) { 1 } else { 0 };
+        ProgramCounter::Next
     }
 
 
@@ -137,172 +167,347 @@ impl Chip8 {
     Will draw a big 0 on the display at (2, 3).
      */
     pub fn draw_sprite(&mut self, x: u8, y: u8, address: u16, height: u8) -> bool {
+        // The sprite always starts on screen. Without the wrap quirk, the parts past the edges are clipped.
+        let (screen_width, screen_height) = (self.display.width(), self.display.height());
+        let x = x as usize % screen_width;
+        let y = y as usize % screen_height;
         let mut collision = false;
 
-        let mut y_line = 0;
-        while y_line < height {
-            let pixel_memory_address = (address + y_line as u16) as usize;
-            let mut pixel = self.memory[pixel_memory_address];
-            let mut x_line: i8 = 7;
-            while x_line >= 0 {
-                if (pixel & 1) == 1 {
-                    if self.display.draw(x + x_line as u8, y + y_line) {
-                        collision = true;
-                    }
+        for row in 0..height as usize {
+            if !self.quirks.wrap && y + row >= screen_height {
+                break;
+            }
+            let pixels = self.memory.read_u8(address.wrapping_add(row as u16));
+            for column in 0..8 {
+                if !self.quirks.wrap && x + column >= screen_width {
+                    break;
+                }
+                if pixels & (0x80 >> column) != 0 && self.display.draw((x + column) as u8, (y + row) as u8) {
+                    collision = true;
                 }
-                pixel >>= 1;
-                x_line -= 1;
             }
-            y_line += 1;
         }
 
-        return collision;
+        collision
     }
-    pub fn opcode_skip_key_pressed_in_vx(&mut self, x: usize) {
-        if self.keypad.status(self.v[x] as usize) == 1 {
-            self.increment_pc();
-            self.skip_increment_pc = false;
-        }
+    // EX9E and EXA1 only look at the low nibble of VX, since there are 16 keys.
+    pub fn opcode_skip_key_pressed_in_vx(&mut self, x: usize) -> ProgramCounter {
+        ProgramCounter::skip_if(self.keypad.status((self.v[x] & 0xF) as usize) == 1)
     }
-    pub fn opcode_skip_key_not_pressed_in_vx(&mut self, x: usize) {
-        if self.keypad.status(self.v[x] as usize) == 0 {
-            self.increment_pc();
-            self.skip_increment_pc = false;
-        }
+    pub fn opcode_skip_key_not_pressed_in_vx(&mut self, x: usize) -> ProgramCounter {
+        ProgramCounter::skip_if(self.keypad.status((self.v[x] & 0xF) as usize) == 0)
     }
-    pub fn opcode_save_delay_to_vx(&mut self, x: usize) {
+    pub fn opcode_save_delay_to_vx(&mut self, x: usize) -> ProgramCounter {
         self.v[x] = self.timer_delay;
-    }
-    pub fn opcode_wait_key(&mut self, x: usize) {
-        let mut keypress = false;
-        for i in 0..=(V_SIZE - 1) {
-            if self.keypad.status(i) != 0 {
-                self.v[x] = i as u8;
-                keypress = true;
-                break;
+        ProgramCounter::Next
+    }
+    // FX0A	Waits for a key to be pressed and released, then stores it in VX. Keys already held when
+    // the wait starts are ignored. Timers keep running meanwhile.
+    pub fn opcode_wait_key(&mut self, x: usize) -> ProgramCounter {
+        match self.awaited_key {
+            None => {
+                self.awaited_key = (0..V_SIZE).find(|key| self.keypad.just_pressed(*key));
+                ProgramCounter::Wait
             }
-        }
-
-        if !keypress {
-            //self.pc -= 2; // force try again
-            self.skip_increment_pc = true;
+            Some(key) if self.keypad.just_released(key) => {
+                self.v[x] = key as u8;
+                self.awaited_key = None;
+                ProgramCounter::Next
+            }
+            Some(_) => ProgramCounter::Wait,
         }
     }
-    pub fn opcode_save_vx_to_delay(&mut self, x: usize) {
+    pub fn opcode_save_vx_to_delay(&mut self, x: usize) -> ProgramCounter {
         self.timer_delay = self.v[x];
+        ProgramCounter::Next
     }
-    pub fn opcode_save_vx_to_sound_timer(&mut self, x: usize) {
+    pub fn opcode_save_vx_to_sound_timer(&mut self, x: usize) -> ProgramCounter {
         self.timer_sound = self.v[x];
+        ProgramCounter::Next
+    }
+    // FX1E	Adds VX to I. With the index overflow quirk, VF is set to 1 when I goes past 0xFFF, and to 0 when it doesn't.
+    pub fn opcode_adds_vx_to_i(&mut self, x: usize) -> ProgramCounter {
+        let sum = self.address_register as u32 + self.v[x] as u32;
+        self.address_register = sum as u16;
+        if self.quirks.index_overflow {
+            self.v[0xF] = if sum > 0xFFF { 1 } else { 0 };
+        }
+        ProgramCounter::Next
     }
-    pub fn opcode_adds_vx_to_i(&mut self, x: usize) {
-        self.address_register = self.address_register.wrapping_add(self.v[x] as u16);
-        self.v[0xF] = if (self.address_register + self.v[x] as u16) > 0xFFF { 1 } else { 0 };
+
+    // I is set the address for the hexadecimal character sprite referred to by the low nibble of VX 5 chars high
+    pub fn opcode_set_i_with_vx(&mut self, x: usize) -> ProgramCounter {
+        let glyph = (self.v[x] & 0xF) as u16;
+        self.address_register = self.font_address.wrapping_add(glyph * 5);
+        ProgramCounter::Next
     }
 
-    // I is set the address for the hexadecimal character sprite referred to by the register VX 5 chars high
-    pub fn opcode_set_i_with_vx(&mut self, x: usize) {
-        self.address_register = (self.v[x] * 5) as u16;
+    // FX30	SCHIP: I is set to the 10 bytes high sprite of the digit in the low nibble of VX.
+    pub fn opcode_set_i_with_big_vx(&mut self, x: usize) -> ProgramCounter {
+        let glyph = (self.v[x] & 0xF) as u16;
+        self.address_register = self.font_address.wrapping_add(SMALL_FONT_SIZE as u16 + glyph * 10);
+        ProgramCounter::Next
     }
 
     // FX33	Stores the Binary-coded decimal representation of VX, with the most significant of three digits at the address in I, the middle digit at I plus 1, and the least significant digit at I plus 2. (In other words, take the decimal representation of VX, place the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.)
-    pub fn opcode_save_bin_vx(&mut self, x: usize) {
-        self.memory[self.address_register as usize] = self.v[x] / 100;
-        self.memory[self.address_register as usize + 1] = (self.v[x] / 10) % 10;
-        self.memory[self.address_register as usize + 2] = (self.v[x] % 100) % 10;
+    pub fn opcode_save_bin_vx(&mut self, x: usize) -> ProgramCounter {
+        let i = self.address_register;
+        self.store(i, self.v[x] / 100);
+        self.store(i.wrapping_add(1), (self.v[x] / 10) % 10);
+        self.store(i.wrapping_add(2), (self.v[x] % 100) % 10);
+        ProgramCounter::Next
+    }
+
+    // FX55	Stores V0 to VX (including VX) in memory starting at address I.
+    pub fn opcode_dump_v_to_memory(&mut self, x: usize) -> ProgramCounter {
+        for i in 0..=x {
+            self.store(self.address_register.wrapping_add(i as u16), self.v[i]);
+        }
+        self.increment_i_after_memory_transfer(x);
+        ProgramCounter::Next
     }
 
-    pub fn opcode_dump_v_to_memory(&mut self, x: usize) {
-        let mut i: usize = 0;
-        while i < x {
-            self.memory[self.address_register as usize + i] = self.v[i];
-            i += 1;
+    // FX65	Fills V0 to VX (including VX) with values from memory starting at address I.
+    pub fn opcode_fill_v_with_memory(&mut self, x: usize) -> ProgramCounter {
+        for i in 0..=x {
+            self.v[i] = self.memory.read_u8(self.address_register.wrapping_add(i as u16));
         }
-        self.address_register += x as u16 + 1;
+        self.increment_i_after_memory_transfer(x);
+        ProgramCounter::Next
     }
 
-    // FX65	Fills V0 to VX with values from memory starting at address I.[4]
-    pub fn opcode_fill_v_with_memory(&mut self, x: usize) {
-        let mut i: usize = 0;
-        while i < x {
-            self.v[i] = (self.address_register + i as u16) as u8;
-            i += 1;
+    // The original interpreter leaves I pointing past the last transferred byte. Later ones differ.
+    fn increment_i_after_memory_transfer(&mut self, x: usize) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
         }
-        self.address_register += x as u16 + 1;
+
+        let increment = if self.quirks.memory_increment_by_x { x } else { x + 1 };
+        self.address_register = self.address_register.wrapping_add(increment as u16);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chip8::{Font, FontStyle, Platform, Quirks};
 
     fn a_chip8() -> Chip8 {
         Chip8::new()
     }
 
+    // A Chip8 with `program` stored at 0x200, so instructions can be executed through `step`.
+    fn a_chip8_with_program(program: &[u16]) -> Chip8 {
+        let mut emu = a_chip8();
+        let program: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        emu.memory.load(0x200, &program);
+        emu
+    }
+
+    // Like `a_chip8_with_program`, running on `platform`.
+    fn a_chip8_on(platform: Platform, program: &[u16]) -> Chip8 {
+        let mut emu = a_chip8();
+        emu.set_platform(platform);
+        let program: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        emu.memory.load(0x200, &program);
+        emu
+    }
+
+    fn step(emu: &mut Chip8) {
+        emu.tick();
+    }
+
     #[test]
     fn test_0x3xnn_should_skip_next_instruction_when_vx_equals_nn() {
-        let mut emu = a_chip8();
-        emu.pc = 0x200;
+        let mut emu = a_chip8_with_program(&[0x3001, 0x7000]);
         emu.v[0] = 1;
-        let nn = 1;
 
-        emu.opcode_skip_if_vx_equals_nn(0, nn);
-        assert_eq!(emu.pc, 0x202);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x204);
     }
 
     #[test]
     fn test_0x3xnn_should_not_skip_instruction_when_vx_differs_nn() {
-        let mut emu = a_chip8();
-        emu.pc = 0x200;
+        let mut emu = a_chip8_with_program(&[0x3001, 0x7000]);
         emu.v[0] = 0;
-        let nn = 1;
 
-        emu.opcode_skip_if_vx_equals_nn(0, nn);
-        assert_eq!(emu.pc, 0x200);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x202);
     }
 
     #[test]
     fn test_0x4xnn_should_skip_next_instruction_if_vx_differs_nn() {
-        let mut emu = a_chip8();
-        emu.pc = 0x200;
+        let mut emu = a_chip8_with_program(&[0x4001, 0x7000]);
         emu.v[0] = 0;
-        let nn = 1;
 
-        emu.opcode_skip_if_vx_diffs_nn(0, nn);
-        assert_eq!(emu.pc, 0x202);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x204);
     }
 
     #[test]
     fn test_0x4xnn_should_not_skip_next_instruction_if_vx_equals_nn() {
-        let mut emu = a_chip8();
-        emu.pc = 0x200;
+        let mut emu = a_chip8_with_program(&[0x4001, 0x7000]);
         emu.v[0] = 1;
-        let nn = 1;
 
-        emu.opcode_skip_if_vx_diffs_nn(0, nn);
-        assert_eq!(emu.pc, 0x200);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x202);
     }
 
     #[test]
     fn test_0x5xy0_should_skip_next_instruction_if_vx_equals_vy() {
-        let mut emu = a_chip8();
-        emu.pc = 0x200;
+        let mut emu = a_chip8_with_program(&[0x5010, 0x7000]);
         emu.v[0] = 1;
         emu.v[1] = 1;
 
-        emu.opcode_skip_if_vx_equals_vy(0, 1);
-        assert_eq!(emu.pc, 0x202);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x204);
     }
 
     #[test]
     fn test_0x5xy0_should_not_skip_next_instruction_if_vx_differs_vy() {
-        let mut emu = a_chip8();
-        emu.pc = 0x200;
+        let mut emu = a_chip8_with_program(&[0x5010, 0x7000]);
         emu.v[0] = 0;
         emu.v[1] = 1;
 
-        emu.opcode_skip_if_vx_equals_vy(0, 1);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x202);
+    }
+
+    #[test]
+    fn test_1nnn_jumps_to_nnn() {
+        let mut emu = a_chip8_with_program(&[0x1345]);
+
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x345);
+    }
+
+    #[test]
+    fn test_2nnn_calls_subroutine_and_00ee_returns_after_the_call() {
+        let mut emu = a_chip8_with_program(&[0x2206, 0x7000, 0x7000, 0x00EE]);
+
+        step(&mut emu);
+        assert_eq!(emu.pc, 0x206);
+        assert_eq!(emu.stack(), &[0x202]);
+
+        step(&mut emu);
+        assert_eq!(emu.pc, 0x202);
+        assert!(emu.stack().is_empty());
+    }
+
+    #[test]
+    fn test_2nnn_should_fault_when_the_stack_is_full() {
+        let mut emu = a_chip8_with_program(&[0x2200]);
+        emu.set_stack_depth(12);
+        emu.start();
+
+        for _ in 0..12 {
+            step(&mut emu);
+        }
+        step(&mut emu);
+
+        assert_eq!(emu.fault(), Some(Fault::StackOverflow { pc: 0x200 }));
+        assert_eq!(emu.stack().len(), 12);
         assert_eq!(emu.pc, 0x200);
+        assert!(!emu.is_playing());
+    }
+
+    #[test]
+    fn test_00ee_should_fault_when_the_stack_is_empty() {
+        let mut emu = a_chip8_with_program(&[0x00EE]);
+
+        step(&mut emu);
+
+        assert_eq!(emu.fault(), Some(Fault::StackUnderflow { pc: 0x200 }));
+        assert_eq!(emu.pc, 0x200);
+    }
+
+    #[test]
+    fn test_unknown_opcodes_should_fault() {
+        for opcode in [0x0000, 0x8008, 0xE000, 0xF0FF] {
+            let mut emu = a_chip8_with_program(&[opcode]);
+
+            step(&mut emu);
+
+            assert_eq!(emu.fault(), Some(Fault::UnknownOpcode { pc: 0x200, opcode }));
+            assert_eq!(emu.pc, 0x200);
+        }
+    }
+
+    #[test]
+    fn test_faulted_machine_should_not_execute_further_instructions() {
+        let mut emu = a_chip8_with_program(&[0x00EE]);
+        emu.start();
+
+        assert_eq!(emu.run_frame(10), 1);
+        emu.stack.push(0x300);
+        emu.tick();
+
+        assert_eq!(emu.pc, 0x200);
+        assert_eq!(emu.stack(), &[0x300]);
+    }
+
+    #[test]
+    fn test_f000_nnnn_sets_i_to_nnnn_and_advances_four_bytes() {
+        let mut emu = a_chip8_on(Platform::XoChip, &[0xF000, 0xABCD]);
+
+        step(&mut emu);
+
+        assert_eq!(emu.address_register, 0xABCD);
+        assert_eq!(emu.pc, 0x204);
+    }
+
+    #[test]
+    fn test_skips_should_step_over_the_whole_f000_nnnn_instruction() {
+        for skip in [0x3000, 0x4001, 0x5010, 0x9020, 0xE0A1] {
+            let mut emu = a_chip8_on(Platform::XoChip, &[skip, 0xF000, 0xABCD, 0x7000]);
+            emu.v[2] = 1;
+
+            step(&mut emu);
+
+            assert_eq!(emu.pc, 0x206, "opcode {:04X}", skip);
+            assert_eq!(emu.address_register, 0);
+        }
+    }
+
+    #[test]
+    fn test_skips_over_f000_should_step_two_bytes_before_xo_chip() {
+        for platform in [Platform::OriginalChip8, Platform::ModernChip8, Platform::SuperChip] {
+            let mut emu = a_chip8_on(platform, &[0x3000, 0xF000, 0xABCD, 0x7000]);
+
+            step(&mut emu);
+
+            assert_eq!(emu.pc, 0x204, "{:?}", platform);
+        }
+    }
+
+    #[test]
+    fn test_super_chip_and_xo_chip_opcodes_should_be_unknown_before_them() {
+        for (platform, opcode) in [(Platform::ModernChip8, 0xF000), (Platform::SuperChip, 0xF000), (Platform::ModernChip8, 0xF030), (Platform::HybridVip, 0x00FF)] {
+            let mut emu = a_chip8_on(platform, &[opcode]);
+
+            step(&mut emu);
+
+            assert_eq!(emu.fault(), Some(Fault::UnknownOpcode { pc: 0x200, opcode }), "{:?}", platform);
+        }
+    }
+
+    #[test]
+    fn test_not_taken_skip_should_land_on_f000_nnnn() {
+        let mut emu = a_chip8_on(Platform::XoChip, &[0x3001, 0xF000, 0xABCD, 0x7000]);
+
+        step(&mut emu);
+        assert_eq!(emu.pc, 0x202);
+
+        step(&mut emu);
+        assert_eq!(emu.pc, 0x206);
+        assert_eq!(emu.address_register, 0xABCD);
     }
 
     #[test]
@@ -441,6 +646,7 @@ mod tests {
     #[test]
     fn test_8xy6_should_set_vf_to_1_when_least_significant_bit_is_1_and_divide_by_2() {
         let mut emu = a_chip8();
+        emu.quirks.shift = true;
         emu.v[0] = 11;
         emu.v[0xF] = 1;
         emu.opcode_set_vx_to_vy(0x8006, 0, 1);
@@ -452,6 +658,7 @@ mod tests {
     #[test]
     fn test_8xy6_should_not_set_vf_to_1_when_least_significant_bit_is_1_and_divide_by_2() {
         let mut emu = a_chip8();
+        emu.quirks.shift = true;
         emu.v[0] = 10;
         emu.v[0xF] = 1;
         emu.opcode_set_vx_to_vy(0x8006, 0, 1);
@@ -522,27 +729,172 @@ mod tests {
     }
 
     #[test]
-    fn test_9xy0_should_skip_next_instruction_if_vx_differs_from_vy() {
+    fn test_8xy6_should_shift_vy_into_vx_without_shift_quirk() {
+        let mut emu = a_chip8();
+        emu.v[0] = 0;
+        emu.v[1] = 0b101;
+        emu.opcode_set_vx_to_vy(0x8016, 0, 1);
+
+        assert_eq!(emu.v[0], 0b10);
+        assert_eq!(emu.v[1], 0b101, "Vy should keep original value.");
+        assert_eq!(emu.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_8xye_should_shift_vy_into_vx_without_shift_quirk() {
+        let mut emu = a_chip8();
+        emu.v[0] = 0;
+        emu.v[1] = 0b10000001;
+        emu.opcode_set_vx_to_vy(0x801E, 0, 1);
+
+        assert_eq!(emu.v[0], 0b10);
+        assert_eq!(emu.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_8xy1_should_reset_vf_with_logic_quirk() {
+        let mut emu = a_chip8();
+        emu.quirks.logic = true;
+        emu.v[0] = 0b01;
+        emu.v[1] = 0b10;
+        emu.v[0xF] = 0x55;
+        emu.opcode_set_vx_to_vy(0x8011, 0, 1);
+
+        assert_eq!(emu.v[0], 0b11);
+        assert_eq!(emu.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_8xy1_should_keep_vf_without_logic_quirk() {
+        let mut emu = a_chip8();
+        emu.v[0] = 0b01;
+        emu.v[1] = 0b10;
+        emu.v[0xF] = 0x55;
+        emu.opcode_set_vx_to_vy(0x8011, 0, 1);
+
+        assert_eq!(emu.v[0xF], 0x55);
+    }
+
+    #[test]
+    fn test_8fy4_should_leave_carry_in_vf() {
+        let mut emu = a_chip8();
+        emu.v[0xF] = 0xFF;
+        emu.v[1] = 0x02;
+        emu.opcode_set_vx_to_vy(0x8F14, 0xF, 1);
+
+        assert_eq!(emu.v[0xF], 1, "The flag is written after the result");
+    }
+
+    #[test]
+    fn test_8xf5_should_use_vf_as_operand_before_writing_flag() {
         let mut emu = a_chip8();
         emu.v[0] = 10;
+        emu.v[0xF] = 3;
+        emu.opcode_set_vx_to_vy(0x80F5, 0, 0xF);
+
+        assert_eq!(emu.v[0], 7);
+        assert_eq!(emu.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_8fy6_should_leave_shifted_out_bit_in_vf() {
+        let mut emu = a_chip8();
+        emu.quirks.shift = true;
+        emu.v[0xF] = 0b10;
+        emu.opcode_set_vx_to_vy(0x8F06, 0xF, 0);
+
+        assert_eq!(emu.v[0xF], 0, "The flag is written after the result");
+    }
+
+    // Reference semantics of 8XYN, computed in wider integers: returns all registers afterwards.
+    fn reference_8xyn(op: u16, x: usize, y: usize, mut v: [u8; 16], shift_quirk: bool, logic_quirk: bool) -> [u8; 16] {
+        let vx = v[x] as i32;
+        let vy = v[y] as i32;
+        let shifted = if shift_quirk { vx } else { vy };
+        let logic_flag = if logic_quirk { Some(0) } else { None };
+
+        let (result, flag) = match op {
+            0x0 => (vy, None),
+            0x1 => (vx | vy, logic_flag),
+            0x2 => (vx & vy, logic_flag),
+            0x3 => (vx ^ vy, logic_flag),
+            0x4 => (vx + vy, Some(if vx + vy > 255 { 1 } else { 0 })),
+            0x5 => (vx - vy, Some(if vx >= vy { 1 } else { 0 })),
+            0x6 => (shifted / 2, Some(shifted % 2)),
+            0x7 => (vy - vx, Some(if vy >= vx { 1 } else { 0 })),
+            0xE => (shifted * 2, Some(shifted / 128)),
+            _ => unreachable!(),
+        };
+
+        v[x] = result.rem_euclid(256) as u8;
+        if let Some(flag) = flag {
+            v[0xF] = flag as u8;
+        }
+        v
+    }
+
+    #[test]
+    fn test_8xyn_should_match_reference_for_every_register_pair_and_quirk() {
+        let values: [u8; 9] = [0x00, 0x01, 0x0F, 0x10, 0x7F, 0x80, 0x81, 0xFE, 0xFF];
+        let ops: [u16; 9] = [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE];
+
+        for shift_quirk in [false, true] {
+            for logic_quirk in [false, true] {
+                // 8XYN only touches the registers, so one emulator serves every case.
+                let mut emu = a_chip8();
+                emu.quirks.shift = shift_quirk;
+                emu.quirks.logic = logic_quirk;
+                for op in ops {
+                    for x in 0..16 {
+                        for y in 0..16 {
+                            for vx in values {
+                                for vy in values {
+                                    let mut registers = [0u8; 16];
+                                    for (index, register) in registers.iter_mut().enumerate() {
+                                        *register = 0x20 + index as u8;
+                                    }
+                                    registers[x] = vx;
+                                    registers[y] = vy;
+
+                                    emu.v = registers;
+                                    let opcode = 0x8000 | ((x as u16) << 8) | ((y as u16) << 4) | op;
+                                    emu.opcode_set_vx_to_vy(opcode, x, y);
+
+                                    let expected = reference_8xyn(op, x, y, registers, shift_quirk, logic_quirk);
+                                    assert_eq!(
+                                        emu.v, expected,
+                                        "{:04X} with V{:X}={:02X} V{:X}={:02X} shift={} logic={}",
+                                        opcode, x, registers[x], y, registers[y], shift_quirk, logic_quirk
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_9xy0_should_skip_next_instruction_if_vx_differs_from_vy() {
+        let mut emu = a_chip8_with_program(&[0x9010, 0x7000]);
+        emu.v[0] = 10;
         emu.v[1] = 9;
-        emu.pc = 0x200;
-        emu.opcode_skips_if_vx_diffs_vy(0, 1);
 
-        assert_eq!(emu.pc, 0x202);
-        assert_eq!(emu.skip_increment_pc, true);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x204);
     }
 
     #[test]
     fn test_9xy0_should_not_skip_next_instruction_if_vx_equals_vy() {
-        let mut emu = a_chip8();
+        let mut emu = a_chip8_with_program(&[0x9010, 0x7000]);
         emu.v[0] = 10;
         emu.v[1] = 10;
-        emu.pc = 0x200;
-        emu.opcode_skips_if_vx_diffs_vy(0, 1);
 
-        assert_eq!(emu.pc, 0x200);
-        assert_eq!(emu.skip_increment_pc, false);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x202);
     }
 
     #[test]
@@ -557,20 +909,30 @@ mod tests {
 
     #[test]
     fn test_bnnn_jumps_to_location() {
-        let mut emu = a_chip8();
-        emu.pc = 0x200;
+        let mut emu = a_chip8_with_program(&[0xB400]);
         emu.v[0] = 0xA;
 
-        emu.opcode_jmp_nnn_plus_v0(0x400);
+        step(&mut emu);
 
         assert_eq!(emu.pc, 0x40A);
-        assert_eq!(emu.skip_increment_pc, true);
+    }
+
+    #[test]
+    fn test_bxnn_jumps_to_xnn_plus_vx_with_jump_quirk() {
+        let mut emu = a_chip8_with_program(&[0xB410]);
+        emu.set_quirks(Quirks { jump: true, ..Quirks::default() });
+        emu.v[0] = 0xA;
+        emu.v[4] = 0x2;
+
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x412);
     }
 
     #[test]
     #[ignore]
     fn test_cnnn_generate_random_value() {
-        let mut emu = a_chip8();
+        let _emu = a_chip8();
 
         // Implement by mocking random generator.
     }
@@ -585,62 +947,119 @@ mod tests {
         emu.opcode_jmp_nnn_plus_v0(0x400);
 
         assert_eq!(emu.pc, 0x40A);
-        assert_eq!(emu.skip_increment_pc, true);
+    }
+
+    #[test]
+    fn test_dxyn_should_not_overlap_the_right_edge_with_the_next_row() {
+        let mut emu = a_chip8();
+        emu.write_memory(0x300, 0x80);
+
+        assert!(!emu.draw_sprite(63, 0, 0x300, 1));
+        assert!(!emu.draw_sprite(0, 1, 0x300, 1));
+    }
+
+    #[test]
+    fn test_dxyn_should_wrap_coordinates_past_the_right_and_bottom_edges() {
+        let mut emu = a_chip8();
+        emu.write_memory(0x300, 0x80);
+
+        assert!(!emu.draw_sprite(63, 31, 0x300, 1));
+        assert!(!emu.draw_sprite(64, 32, 0x300, 1));
+        assert!(emu.draw_sprite(0, 0, 0x300, 1));
+        assert!(emu.draw_sprite(127, 63, 0x300, 1));
+    }
+
+    #[test]
+    fn test_dxyn_should_draw_at_the_largest_coordinates() {
+        let mut emu = a_chip8();
+        emu.write_memory(0x300, 0xFF);
+        emu.write_memory(0x301, 0xFF);
+
+        emu.draw_sprite(255, 255, 0x300, 2);
+    }
+
+    #[test]
+    fn test_dxyn_should_clip_sprites_at_the_edges_without_the_wrap_quirk() {
+        let mut emu = a_chip8();
+        emu.quirks.wrap = false;
+        emu.write_memory(0x300, 0xFF);
+        emu.write_memory(0x301, 0xFF);
+        emu.write_memory(0x302, 0x80);
+
+        assert!(!emu.draw_sprite(60, 31, 0x300, 2));
+
+        assert!(!emu.draw_sprite(0, 31, 0x302, 1));
+        assert!(!emu.draw_sprite(60, 0, 0x302, 1));
+        assert!(emu.draw_sprite(63, 31, 0x302, 1));
+    }
+
+    #[test]
+    fn test_dxyn_should_wrap_sprites_at_the_edges_with_the_wrap_quirk() {
+        let mut emu = a_chip8();
+        emu.quirks.wrap = true;
+        emu.write_memory(0x300, 0xFF);
+        emu.write_memory(0x301, 0xFF);
+        emu.write_memory(0x302, 0x80);
+
+        assert!(!emu.draw_sprite(60, 31, 0x300, 2));
+
+        assert!(emu.draw_sprite(0, 31, 0x302, 1));
+        assert!(emu.draw_sprite(60, 0, 0x302, 1));
     }
 
     #[test]
     fn test_ex9e_should_skip_if_key_is_pressed() {
-        let mut emu  = a_chip8();
-        let key_index = 1;
-        emu.v[0] = key_index;
-        emu.pc = 0x200;
-        emu.keypad.press(key_index as usize);
+        let mut emu = a_chip8_with_program(&[0xE09E, 0x7000]);
+        emu.v[0] = 1;
+        emu.keypad.press(1);
 
-        emu.opcode_skip_key_pressed_in_vx(0);
+        step(&mut emu);
 
-        assert_eq!(emu.pc, 0x202);
-        assert_eq!(emu.skip_increment_pc, false);
+        assert_eq!(emu.pc, 0x204);
     }
 
     #[test]
     fn test_ex9e_should_not_skip_if_key_is_not_pressed() {
-        let mut emu  = a_chip8();
-        let key_index = 1;
-        emu.v[0] = key_index;
-        emu.pc = 0x200;
-        emu.keypad.release(key_index as usize);
+        let mut emu = a_chip8_with_program(&[0xE09E, 0x7000]);
+        emu.v[0] = 1;
+        emu.keypad.release(1);
 
-        emu.opcode_skip_key_pressed_in_vx(0);
+        step(&mut emu);
 
-        assert_eq!(emu.pc, 0x200);
-        assert_eq!(emu.skip_increment_pc, false);
+        assert_eq!(emu.pc, 0x202);
     }
+    // Found by fuzzing: VX above 0xF used to index past the keypad.
+    #[test]
+    fn test_ex9e_should_use_the_low_nibble_of_vx() {
+        let mut emu = a_chip8_with_program(&[0x649E, 0xE49E, 0x7000]);
+        emu.keypad.press(0xE);
+
+        step(&mut emu);
+        step(&mut emu);
+
+        assert_eq!(emu.pc, 0x206);
+    }
+
     #[test]
     fn test_exa1_should_skip_if_key_is_not_pressed() {
-        let mut emu  = a_chip8();
-        let key_index = 1;
-        emu.v[0] = key_index;
-        emu.pc = 0x200;
-        emu.keypad.release(key_index as usize);
+        let mut emu = a_chip8_with_program(&[0xE0A1, 0x7000]);
+        emu.v[0] = 1;
+        emu.keypad.release(1);
 
-        emu.opcode_skip_key_not_pressed_in_vx(0);
+        step(&mut emu);
 
-        assert_eq!(emu.pc, 0x202);
-        assert_eq!(emu.skip_increment_pc, false);
+        assert_eq!(emu.pc, 0x204);
     }
 
     #[test]
     fn test_exa1_should_not_skip_if_key_is_pressed() {
-        let mut emu  = a_chip8();
-        let key_index = 1;
-        emu.v[0] = key_index;
-        emu.pc = 0x200;
-        emu.keypad.press(key_index as usize);
+        let mut emu = a_chip8_with_program(&[0xE0A1, 0x7000]);
+        emu.v[0] = 1;
+        emu.keypad.press(1);
 
-        emu.opcode_skip_key_not_pressed_in_vx(0);
+        step(&mut emu);
 
-        assert_eq!(emu.pc, 0x200);
-        assert_eq!(emu.skip_increment_pc, false);
+        assert_eq!(emu.pc, 0x202);
     }
 
     #[test]
@@ -654,28 +1073,80 @@ mod tests {
         assert_eq!(emu.v[0], 10);
     }
 
+    enum Input {
+        Press(usize),
+        Release(usize),
+    }
+
+    // Runs one frame per entry: its key events are applied, then `instructions` opcodes are executed.
+    fn run_frames_with_input(emu: &mut Chip8, instructions: u32, script: &[&[Input]]) {
+        emu.start();
+        for frame in script {
+            for input in frame.iter() {
+                match input {
+                    Input::Press(key) => emu.press_key(*key),
+                    Input::Release(key) => emu.release_key(*key),
+                }
+            }
+            emu.run_frame(instructions);
+        }
+    }
+
     #[test]
     fn test_fx0a_should_wait_while_no_key_is_pressed() {
-        let mut emu = a_chip8();
-        emu.v[0] = 0;
-        emu.pc = 0x200;
-        emu.keypad.release(0);
+        let mut emu = a_chip8_with_program(&[0xF00A, 0x1202]);
+
+        run_frames_with_input(&mut emu, 3, &[&[], &[]]);
 
-        emu.opcode_wait_key(0);
+        assert_eq!(emu.pc, 0x200);
+    }
+
+    #[test]
+    fn test_fx0a_should_wait_for_the_key_to_be_released() {
+        let mut emu = a_chip8_with_program(&[0xF50A, 0x1202]);
+
+        run_frames_with_input(&mut emu, 3, &[&[Input::Press(3)], &[]]);
+        assert_eq!(emu.pc, 0x200);
 
-        assert_eq!(emu.skip_increment_pc, true);
+        run_frames_with_input(&mut emu, 3, &[&[Input::Release(3)]]);
+        assert_eq!(emu.pc, 0x202);
+        assert_eq!(emu.v[5], 3);
     }
 
     #[test]
-    fn test_fx0a_continue_if_key_is_pressed() {
-        let mut emu = a_chip8();
-        emu.v[0] = 0;
-        emu.pc = 0x200;
-        emu.keypad.press(0);
+    fn test_fx0a_should_accept_a_press_and_release_within_one_frame() {
+        let mut emu = a_chip8_with_program(&[0xF00A, 0x1202]);
 
-        emu.opcode_wait_key(0);
+        run_frames_with_input(&mut emu, 2, &[&[Input::Press(0xC), Input::Release(0xC)]]);
 
-        assert_eq!(emu.skip_increment_pc, false);
+        assert_eq!(emu.pc, 0x202);
+        assert_eq!(emu.v[0], 0xC);
+    }
+
+    #[test]
+    fn test_fx0a_should_ignore_a_key_held_before_the_wait_started() {
+        let mut emu = a_chip8_with_program(&[0xF00A, 0x1202]);
+
+        run_frames_with_input(&mut emu, 0, &[&[Input::Press(1)]]);
+        run_frames_with_input(&mut emu, 2, &[&[], &[Input::Release(1)], &[]]);
+        assert_eq!(emu.pc, 0x200);
+
+        run_frames_with_input(&mut emu, 2, &[&[Input::Press(2)], &[Input::Release(2)]]);
+        assert_eq!(emu.pc, 0x202);
+        assert_eq!(emu.v[0], 2);
+    }
+
+    #[test]
+    fn test_fx0a_should_keep_timers_running_while_waiting() {
+        let mut emu = a_chip8_with_program(&[0xF00A, 0x1202]);
+        emu.timer_delay = 10;
+        emu.timer_sound = 5;
+
+        run_frames_with_input(&mut emu, 10, &[&[], &[], &[]]);
+
+        assert_eq!(emu.pc, 0x200);
+        assert_eq!(emu.timer_delay, 7);
+        assert_eq!(emu.timer_sound, 2);
     }
 
     #[test]
@@ -722,6 +1193,32 @@ mod tests {
         assert_eq!(emu.address_register, 0);
     }
 
+    #[test]
+    fn test_fx1e_should_leave_vf_without_index_overflow_quirk() {
+        let mut emu = a_chip8();
+        emu.v[0] = 0x80;
+        emu.v[0xF] = 0x55;
+        emu.address_register = 0x0F80;
+
+        emu.opcode_adds_vx_to_i(0);
+
+        assert_eq!(emu.address_register, 0x1000);
+        assert_eq!(emu.v[0xF], 0x55);
+    }
+
+    #[test]
+    fn test_fx1e_should_set_vf_when_i_goes_past_the_12_bit_address_space_with_index_overflow_quirk() {
+        let mut emu = a_chip8();
+        emu.quirks.index_overflow = true;
+        emu.v[0] = 0x80;
+        emu.address_register = 0xFF80;
+
+        emu.opcode_adds_vx_to_i(0);
+
+        assert_eq!(emu.address_register, 0);
+        assert_eq!(emu.v[0xF], 1);
+    }
+
     #[test]
     fn test_fx29_should_set_i_with_location_of_sprite_located_in_vx() {
         let mut emu = a_chip8();
@@ -733,20 +1230,53 @@ mod tests {
         assert_eq!(emu.address_register, 5);
     }
 
+    #[test]
+    fn test_fx29_should_use_the_low_nibble_of_vx_and_the_font_address() {
+        let mut emu = a_chip8();
+        emu.set_font_address(0x50);
+        emu.v[0] = 0xFA;
+
+        emu.opcode_set_i_with_vx(0);
+
+        assert_eq!(emu.address_register, 0x50 + 0xA * 5);
+        assert_eq!(emu.memory()[0x50 + 0xA * 5..0x50 + 0xB * 5], [0xF0, 0x90, 0xF0, 0x90, 0x90]);
+    }
+
+    #[test]
+    fn test_fx30_should_point_i_at_the_big_glyph_after_the_small_font() {
+        let mut emu = a_chip8_on(Platform::SuperChip, &[0xF330]);
+        emu.set_font_address(0x50);
+        emu.v[3] = 0x18;
+
+        step(&mut emu);
+
+        assert_eq!(emu.address_register, 0x50 + 80 + 8 * 10);
+        assert_eq!(emu.memory()[0xF0..0xFA], [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C]);
+    }
+
+    #[test]
+    fn test_set_font_should_write_the_glyphs_to_memory() {
+        let mut emu = a_chip8();
+
+        emu.set_font(Font::builtin(FontStyle::Dream6800));
+
+        assert_eq!(emu.memory()[5..10], [0x40, 0x40, 0x40, 0x40, 0x40]);
+    }
+
     #[test]
     fn test_fx33_should_store_bcd_representation_of_vx_in_memory_locations() {
         let mut emu = a_chip8();
         emu.v[0] = 123;
         emu.address_register = 0;
-        emu.memory[0] = 0;
-        emu.memory[1] = 0;
-        emu.memory[2] = 0;
+        emu.memory.write(0, 0);
+        emu.memory.write(1, 0);
+        emu.memory.write(2, 0);
 
         emu.opcode_save_bin_vx(0);
 
-        assert_eq!(emu.memory[0], 1);
-        assert_eq!(emu.memory[1], 2);
-        assert_eq!(emu.memory[2], 3);
+        assert_eq!(emu.memory()[0], 1);
+        assert_eq!(emu.memory()[1], 2);
+        assert_eq!(emu.memory()[2], 3);
     }
 
     #[test]
@@ -756,22 +1286,130 @@ mod tests {
         let mut array = [0;16];
             array.copy_from_slice(&vec);
         emu.v = array;
+        emu.address_register = 0x300;
 
-        emu.opcode_dump_v_to_memory(16);
+        emu.opcode_dump_v_to_memory(15);
 
         for i in 0..=15 {
-            assert_eq!(emu.memory[i], i as u8 + 1);
+            assert_eq!(emu.memory()[0x300 + i], i as u8 + 1);
         }
     }
 
+    #[test]
+    fn test_fx55_should_store_vx_inclusive_and_nothing_past_it() {
+        let mut emu = a_chip8();
+        emu.v = [0xAA; 16];
+        emu.address_register = 0x300;
+
+        emu.opcode_dump_v_to_memory(2);
+
+        assert_eq!(emu.memory()[0x300..0x304], [0xAA, 0xAA, 0xAA, 0x00]);
+    }
+
+    #[test]
+    fn test_fx55_should_store_only_v0_when_x_is_0() {
+        let mut emu = a_chip8();
+        emu.v[0] = 0x12;
+        emu.v[1] = 0x34;
+        emu.address_register = 0x300;
+
+        emu.opcode_dump_v_to_memory(0);
+
+        assert_eq!(emu.memory()[0x300..0x302], [0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_fx55_should_wrap_around_the_end_of_memory() {
+        let mut emu = a_chip8();
+        emu.address_register = 0xFFF;
+        emu.v[0] = 0x11;
+        emu.v[1] = 0x22;
+
+        emu.opcode_dump_v_to_memory(1);
+
+        assert_eq!(emu.memory()[0xFFF], 0x11);
+        assert_eq!(emu.memory()[0x000], 0x22);
+    }
+
     #[test]
     fn test_fx65_should_fill_v_with_memory() {
         let mut emu = a_chip8();
-        emu.address_register = 0;
+        emu.address_register = 0x300;
+        for i in 0..=15 {
+            emu.memory.write(0x300 + i as u16, 0xF0 + i as u8);
+        }
+
+        emu.opcode_fill_v_with_memory(15);
+
+        for i in 0..=15 {
+            assert_eq!(emu.v[i], 0xF0 + i as u8, "V{:X}", i);
+        }
+    }
+
+    #[test]
+    fn test_fx65_should_fill_vx_inclusive_and_nothing_past_it() {
+        let mut emu = a_chip8();
+        emu.address_register = 0x300;
+        emu.memory.load(0x300, &[1, 2, 3, 4]);
+
+        emu.opcode_fill_v_with_memory(2);
+
+        assert_eq!(emu.v[0..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_fx55_fx65_should_increment_i_by_x_plus_1() {
+        let mut emu = a_chip8();
+        emu.address_register = 0x300;
+
+        emu.opcode_dump_v_to_memory(3);
+        assert_eq!(emu.address_register, 0x304);
+
+        emu.opcode_fill_v_with_memory(3);
+        assert_eq!(emu.address_register, 0x308);
+    }
+
+    #[test]
+    fn test_fx55_fx65_should_increment_i_by_x_with_memory_increment_by_x_quirk() {
+        let mut emu = a_chip8();
+        emu.quirks.memory_increment_by_x = true;
+        emu.address_register = 0x300;
+
+        emu.opcode_dump_v_to_memory(3);
+        assert_eq!(emu.address_register, 0x303);
+
+        emu.opcode_fill_v_with_memory(3);
+        assert_eq!(emu.address_register, 0x306);
+    }
+
+    #[test]
+    fn test_fx55_fx65_should_leave_i_unchanged_with_memory_leave_i_unchanged_quirk() {
+        let mut emu = a_chip8();
+        emu.quirks.memory_leave_i_unchanged = true;
+        emu.address_register = 0x300;
+
+        emu.opcode_dump_v_to_memory(3);
+        assert_eq!(emu.address_register, 0x300);
+
+        emu.opcode_fill_v_with_memory(3);
+        assert_eq!(emu.address_register, 0x300);
+    }
+
+    #[test]
+    fn test_fx55_then_fx65_should_round_trip_registers() {
+        let mut emu = a_chip8();
+        emu.quirks.memory_leave_i_unchanged = true;
+        emu.address_register = 0x300;
         for i in 0..=15 {
-            emu.memory[i] = i as u8;
+            emu.v[i] = i as u8 * 3;
         }
 
-        emu.opcode_fill_v_with_memory(16);
+        emu.opcode_dump_v_to_memory(15);
+        emu.v = [0; 16];
+        emu.opcode_fill_v_with_memory(15);
+
+        for i in 0..=15 {
+            assert_eq!(emu.v[i], i as u8 * 3, "V{:X}", i);
+        }
     }
 }
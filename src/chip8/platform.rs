@@ -0,0 +1,181 @@
+use crate::chip8::font::FontStyle;
+use crate::chip8::quirks::Quirks;
+
+/**
+CHIP-8 interpreters a program can target. Besides quirks, a platform selects the memory layout,
+where execution starts, the display geometry and the instruction set.
+Ids follow the platform ids used by the community chip-8-database, with `hiresChip8` and
+`chip8e` added for variants it does not list.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Platform {
+    OriginalChip8,
+    HybridVip,
+    HiResChip8,
+    Chip8E,
+    #[default]
+    ModernChip8,
+    Chip8X,
+    Chip48,
+    SuperChip1,
+    SuperChip,
+    MegaChip8,
+    XoChip,
+}
+
+/**
+Opcodes a platform understands. The variants are CHIP-8 with a few opcodes added or replaced:
+hi-res CHIP-8 has its own clear screen, CHIP-8X drives the VP-590 colour board and a second
+keypad, CHIP-8E adds skips, relative branches, block transfers and I/O port access, SUPER-CHIP
+adds the high resolution mode and the big font, and XO-CHIP adds `F000 NNNN` on top of those.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum InstructionSet {
+    #[default]
+    Chip8,
+    HiResChip8,
+    Chip8X,
+    Chip8E,
+    SuperChip,
+    XoChip,
+}
+
+impl InstructionSet {
+    // Size in bytes of the instruction starting with `opcode`. XO-CHIP's F000 NNNN is the only 4-byte one.
+    pub fn instruction_size(&self, opcode: u16) -> u16 {
+        if opcode == 0xF000 && *self == InstructionSet::XoChip { 4 } else { 2 }
+    }
+}
+
+impl Platform {
+    pub fn from_id(id: &str) -> Option<Platform> {
+        match id {
+            "originalChip8" => Some(Platform::OriginalChip8),
+            "hybridVIP" => Some(Platform::HybridVip),
+            "hiresChip8" => Some(Platform::HiResChip8),
+            "chip8e" => Some(Platform::Chip8E),
+            "modernChip8" => Some(Platform::ModernChip8),
+            "chip8x" => Some(Platform::Chip8X),
+            "chip48" => Some(Platform::Chip48),
+            "superchip1" => Some(Platform::SuperChip1),
+            "superchip" => Some(Platform::SuperChip),
+            "megachip8" => Some(Platform::MegaChip8),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::OriginalChip8 => "CHIP-8 (COSMAC VIP)",
+            Platform::HybridVip => "CHIP-8 with machine code (COSMAC VIP)",
+            Platform::HiResChip8 => "Hi-res CHIP-8 (COSMAC VIP)",
+            Platform::Chip8E => "CHIP-8E (COSMAC VIP)",
+            Platform::ModernChip8 => "Modern CHIP-8",
+            Platform::Chip8X => "CHIP-8X",
+            Platform::Chip48 => "CHIP-48",
+            Platform::SuperChip1 => "SUPER-CHIP 1.0",
+            Platform::SuperChip => "SUPER-CHIP 1.1",
+            Platform::MegaChip8 => "MEGA-CHIP8",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::OriginalChip8 | Platform::HybridVip | Platform::HiResChip8 | Platform::Chip8E | Platform::Chip8X => Quirks {
+                vblank: true,
+                logic: true,
+                vip_timing: true,
+                ..Quirks::default()
+            },
+            Platform::ModernChip8 => Quirks::default(),
+            Platform::Chip48 => Quirks {
+                shift: true,
+                memory_increment_by_x: true,
+                jump: true,
+                ..Quirks::default()
+            },
+            Platform::SuperChip1 | Platform::SuperChip | Platform::MegaChip8 => Quirks {
+                shift: true,
+                memory_leave_i_unchanged: true,
+                jump: true,
+                ..Quirks::default()
+            },
+            Platform::XoChip => Quirks {
+                wrap: true,
+                ..Quirks::default()
+            },
+        }
+    }
+
+    // Where programs are loaded. The CHIP-8X interpreter takes up the page below 0x300.
+    pub fn load_address(&self) -> u16 {
+        match self {
+            Platform::Chip8X => 0x300,
+            _ => 0x200,
+        }
+    }
+
+    // Where execution starts. Hi-res programs begin with a jump to machine code at 0x260 that
+    // switches the VIP to 64x64 and carries on at 0x2C0, which is where they are started instead.
+    pub fn start_address(&self) -> u16 {
+        match self {
+            Platform::HiResChip8 => 0x2C0,
+            _ => self.load_address(),
+        }
+    }
+
+    // Width and height of the screen in pixels.
+    pub fn display_size(&self) -> (usize, usize) {
+        match self {
+            Platform::HiResChip8 => (64, 64),
+            _ => (64, 32),
+        }
+    }
+
+    pub fn instruction_set(&self) -> InstructionSet {
+        match self {
+            Platform::HiResChip8 => InstructionSet::HiResChip8,
+            Platform::Chip8E => InstructionSet::Chip8E,
+            Platform::Chip8X => InstructionSet::Chip8X,
+            Platform::SuperChip1 | Platform::SuperChip | Platform::MegaChip8 => InstructionSet::SuperChip,
+            Platform::XoChip => InstructionSet::XoChip,
+            _ => InstructionSet::Chip8,
+        }
+    }
+
+    // Size of the address space. XO-CHIP extends it to 64KB, the others have 4KB.
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    // Number of return addresses the stack holds. The COSMAC VIP interpreter reserved room for 12.
+    pub fn stack_depth(&self) -> usize {
+        match self {
+            Platform::OriginalChip8 | Platform::HybridVip | Platform::HiResChip8 | Platform::Chip8E | Platform::Chip8X => 12,
+            _ => 16,
+        }
+    }
+
+    pub fn font_style(&self) -> FontStyle {
+        match self {
+            Platform::OriginalChip8 | Platform::HybridVip | Platform::HiResChip8 | Platform::Chip8E | Platform::Chip8X => FontStyle::Vip,
+            _ => FontStyle::SuperChip,
+        }
+    }
+
+    // Instructions per frame a program written for this platform usually expects.
+    pub fn default_tickrate(&self) -> u32 {
+        match self {
+            Platform::OriginalChip8 | Platform::HybridVip | Platform::HiResChip8 | Platform::Chip8E | Platform::Chip8X => 15,
+            Platform::ModernChip8 => 12,
+            Platform::Chip48 | Platform::SuperChip1 | Platform::SuperChip => 30,
+            Platform::MegaChip8 => 1000,
+            Platform::XoChip => 100,
+        }
+    }
+}
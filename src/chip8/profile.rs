@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use super::{Chip8, InstructionSet};
+use crate::chip8::decode::Instruction;
+
+// Instructions executed, by subroutine.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct SubroutineProfile {
+    pub calls: u64,
+    // Including the subroutines it called.
+    pub inclusive: u64,
+    // In its own instructions only.
+    pub exclusive: u64,
+}
+
+/**
+What a run executed: how often each address and each kind of instruction ran, and how many
+instructions ran under each chain of active subroutines, for finding hot loops and checking that
+a test ROM exercises every opcode. Time is counted in executed instructions, so an instruction
+waiting for a key or the vertical blank counts every time it runs again.
+ */
+#[derive(Clone, Debug)]
+pub struct Profile {
+    instruction_set: InstructionSet,
+    // Times an instruction started at each address.
+    executions: Vec<u64>,
+    // Times each opcode ran, grouped by instruction on demand.
+    opcodes: Vec<u64>,
+    // Entry points of the subroutines called and not returned from yet, outermost first.
+    frames: Vec<u16>,
+    // Instructions executed under each chain of frames.
+    stacks: HashMap<Vec<u16>, u64>,
+    calls: BTreeMap<u16, u64>,
+}
+
+impl Profile {
+    fn new(memory_size: usize, instruction_set: InstructionSet) -> Profile {
+        Profile {
+            instruction_set,
+            executions: vec![0; memory_size],
+            opcodes: vec![0; 0x10000],
+            frames: Vec::new(),
+            stacks: HashMap::new(),
+            calls: BTreeMap::new(),
+        }
+    }
+
+    // Times an instruction started at `address`.
+    pub fn executions(&self, address: u16) -> u64 {
+        self.executions.get(address as usize).copied().unwrap_or(0)
+    }
+
+    // Addresses instructions started at, with how many times, in address order.
+    pub fn executed(&self) -> impl Iterator<Item = (u16, u64)> + '_ {
+        self.executions.iter().enumerate().filter(|(_, count)| **count > 0).map(|(address, count)| (address as u16, *count))
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.executions.iter().sum()
+    }
+
+    // Instructions executed by kind, named by their pattern like `8XY4` or `FX65`.
+    pub fn histogram(&self) -> BTreeMap<String, u64> {
+        let mut histogram = BTreeMap::new();
+        for (opcode, count) in self.opcodes.iter().enumerate().filter(|(_, count)| **count > 0) {
+            *histogram.entry(pattern(opcode as u16, self.instruction_set)).or_insert(0) += count;
+        }
+        histogram
+    }
+
+    // Calls and instructions executed of every subroutine called, by entry point.
+    pub fn subroutines(&self) -> BTreeMap<u16, SubroutineProfile> {
+        let mut subroutines: BTreeMap<u16, SubroutineProfile> = self.calls.iter()
+            .map(|(address, calls)| (*address, SubroutineProfile { calls: *calls, ..Default::default() }))
+            .collect();
+        for (frames, count) in &self.stacks {
+            for (depth, address) in frames.iter().enumerate() {
+                // Recursive calls only count once.
+                if frames[..depth].contains(address) {
+                    continue;
+                }
+                let subroutine = subroutines.entry(*address).or_default();
+                subroutine.inclusive += count;
+                if depth == frames.len() - 1 {
+                    subroutine.exclusive += count;
+                }
+            }
+        }
+        subroutines
+    }
+
+    /**
+    Instructions executed under each chain of subroutines in the folded stack format of
+    flamegraph tools, one `main;outer;inner count` line per chain, sorted. Subroutines are named
+    by `labels`, or by their address.
+     */
+    pub fn folded_stacks(&self, labels: &BTreeMap<u16, String>) -> String {
+        let mut lines: Vec<String> = self.stacks.iter().map(|(frames, count)| {
+            let mut line = String::from("main");
+            for address in frames {
+                match labels.get(address) {
+                    Some(label) => { let _ = write!(line, ";{}", label); }
+                    None => { let _ = write!(line, ";sub_{:03X}", address); }
+                }
+            }
+            let _ = write!(line, " {}", count);
+            line
+        }).collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    fn record(&mut self, pc: u16, opcode: u16) {
+        if let Some(count) = self.executions.get_mut(pc as usize) {
+            *count += 1;
+        }
+        self.opcodes[opcode as usize] += 1;
+        match self.stacks.get_mut(self.frames.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.frames.clone(), 1);
+            }
+        }
+    }
+
+    // Follows the stack after an instruction: a new entry means PC is a subroutine just called.
+    fn follow_stack(&mut self, depth: usize, pc: u16) {
+        while self.frames.len() < depth {
+            self.frames.push(pc);
+            *self.calls.entry(pc).or_insert(0) += 1;
+        }
+        self.frames.truncate(depth);
+    }
+}
+
+// Pattern of the instruction `opcode` belongs to, with its operands replaced by letters.
+fn pattern(opcode: u16, set: InstructionSet) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    match opcode & 0xF000 {
+        // Those without operands, and machine code routines, which are all told apart.
+        0x0000 => format!("{:04X}", opcode),
+        0xB000 if set == InstructionSet::Chip8X => "BXYN".to_string(),
+        0xB000 if set == InstructionSet::Chip8E && (x == 0xB || x == 0xF) => format!("B{:X}NN", x),
+        0x1000 | 0x2000 | 0xA000 | 0xB000 => format!("{:X}NNN", opcode >> 12),
+        0x3000 | 0x4000 | 0x6000 | 0x7000 | 0xC000 => format!("{:X}XNN", opcode >> 12),
+        0x5000 | 0x8000 | 0x9000 => format!("{:X}XY{:X}", opcode >> 12, n),
+        0xD000 => "DXYN".to_string(),
+        _ if opcode == 0xF000 => "F000".to_string(),
+        _ => format!("{:X}X{:02X}", opcode >> 12, nn),
+    }
+}
+
+impl Chip8 {
+    /**
+    Starts profiling the running program, see `Profile`. The profile starts over on every reset.
+    While profiling, the recompiler is bypassed so that every instruction is counted.
+     */
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new(self.memory.size(), self.platform.instruction_set()));
+    }
+
+    pub fn disable_profiling(&mut self) {
+        self.profile = None;
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    // Starts the profile over, e.g. once the machine is reset.
+    pub(crate) fn restart_profile(&mut self) {
+        if self.profile.is_some() {
+            self.enable_profiling();
+        }
+    }
+
+    // Counts `instruction`, just executed at `pc`.
+    pub(crate) fn profile_after(&mut self, pc: u16, instruction: Instruction) {
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc, instruction.opcode());
+            profile.follow_stack(self.stack.len(), self.pc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(program: &[u16], instructions: u32) -> Profile {
+        let mut emu = Chip8::new();
+        emu.enable_profiling();
+        emu.load(program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect());
+        emu.start();
+        emu.run_frame(instructions);
+        emu.profile().unwrap().clone()
+    }
+
+    #[test]
+    fn executions_should_be_counted_by_address_and_instruction() {
+        // Counts V0 up to 3, then loops forever.
+        let profile = profile(&[0x7001, 0x3003, 0x1200, 0x1206], 10);
+
+        assert_eq!(profile.executions(0x200), 3);
+        assert_eq!(profile.executions(0x204), 2);
+        assert_eq!(profile.executions(0x206), 2);
+        assert_eq!(profile.instructions(), 10);
+        assert_eq!(profile.executed().collect::<Vec<_>>(), vec![(0x200, 3), (0x202, 3), (0x204, 2), (0x206, 2)]);
+        assert_eq!(profile.histogram(), BTreeMap::from([
+            ("1NNN".to_string(), 4),
+            ("3XNN".to_string(), 3),
+            ("7XNN".to_string(), 3),
+        ]));
+    }
+
+    #[test]
+    fn time_should_be_split_by_subroutine() {
+        let program = [
+            0x2206, // 200: call 206
+            0x2206, // 202: call 206
+            0x1204, // 204: loop
+            0x220C, // 206: call 20C
+            0x6001, // 208
+            0x00EE, // 20A
+            0x00EE, // 20C
+        ];
+        let profile = profile(&program, 14);
+
+        assert_eq!(profile.subroutines(), BTreeMap::from([
+            (0x206, SubroutineProfile { calls: 2, inclusive: 8, exclusive: 6 }),
+            (0x20C, SubroutineProfile { calls: 2, inclusive: 2, exclusive: 2 }),
+        ]));
+        let labels = BTreeMap::from([(0x206, "draw".to_string())]);
+        assert_eq!(profile.folded_stacks(&labels), "main 6\nmain;draw 6\nmain;draw;sub_20C 2\n");
+    }
+
+    #[test]
+    fn the_profile_should_start_over_on_reset() {
+        let mut emu = Chip8::new();
+        emu.enable_profiling();
+        emu.load(vec![0x12, 0x00]);
+        emu.start();
+        emu.run_frame(5);
+
+        emu.load(vec![0x12, 0x00]);
+
+        assert_eq!(emu.profile().unwrap().instructions(), 0);
+    }
+}
@@ -0,0 +1,27 @@
+/**
+Behaviour differences between CHIP-8 interpreters.
+Names follow the quirks used by the community chip-8-database.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    // FX55/FX65 increment I by X instead of X + 1.
+    pub memory_increment_by_x: bool,
+    // FX55/FX65 leave I unchanged.
+    pub memory_leave_i_unchanged: bool,
+    // Sprites wrap around the screen edges instead of being clipped.
+    pub wrap: bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump: bool,
+    // DXYN waits for the vertical blank interrupt.
+    pub vblank: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0.
+    pub logic: bool,
+    // Instructions take as many machine cycles as on the COSMAC VIP, which sets the speed
+    // instead of the tickrate. Not part of the chip-8-database quirks.
+    pub vip_timing: bool,
+    // FX1E sets VF when I goes past 0xFFF, and clears it otherwise, like the Amiga interpreter
+    // Spacefight 2091! relies on. Not part of the chip-8-database quirks.
+    pub index_overflow: bool,
+}
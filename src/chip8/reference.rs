@@ -0,0 +1,412 @@
+use std::fmt;
+use proptest::prelude::*;
+use super::{Chip8, Fault, InstructionSet, Platform, Quirks};
+
+const MEMORY_SIZE: usize = 0x1000;
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// Platforms running CHIP-8 or one of the instruction sets extending it without replacing opcodes.
+const PLATFORMS: [Platform; 8] = [
+    Platform::OriginalChip8,
+    Platform::HybridVip,
+    Platform::ModernChip8,
+    Platform::Chip48,
+    Platform::SuperChip1,
+    Platform::SuperChip,
+    Platform::MegaChip8,
+    Platform::XoChip,
+];
+
+/**
+Reference specification of CHIP-8, written to be read rather than to be fast: one function
+executing one instruction on a plain copy of the machine state. It shares no code with the
+interpreter, so the property tests below can check every opcode against it.
+ */
+#[derive(Clone)]
+struct Machine {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: Vec<u16>,
+    delay: u8,
+    sound: u8,
+    memory: Vec<u8>,
+    // Indexed by y * WIDTH + x.
+    screen: Vec<bool>,
+    fault: Option<Fault>,
+}
+
+// Memory and screen are summed up, since printing them whole buries the rest of a failing case.
+impl fmt::Debug for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Machine")
+            .field("opcode", &format_args!("{:04X}", self.word(self.pc)))
+            .field("v", &self.v)
+            .field("i", &format_args!("{:04X}", self.i))
+            .field("pc", &format_args!("{:03X}", self.pc))
+            .field("stack", &self.stack)
+            .field("delay", &self.delay)
+            .field("sound", &self.sound)
+            .field("memory at i", &(0..16).map(|offset| self.read(self.i.wrapping_add(offset))).collect::<Vec<_>>())
+            .field("lit pixels", &self.screen.iter().filter(|&&lit| lit).count())
+            .finish()
+    }
+}
+
+impl Machine {
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize % MEMORY_SIZE]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize % MEMORY_SIZE] = value;
+    }
+
+    fn word(&self, address: u16) -> u16 {
+        u16::from_be_bytes([self.read(address), self.read(address.wrapping_add(1))])
+    }
+
+    // Executes the instruction at PC with the given instruction set, quirks, keys held and stack depth.
+    fn step(&self, set: InstructionSet, quirks: Quirks, keys: [bool; 16], stack_depth: usize) -> Machine {
+        let mut m = self.clone();
+        let pc = m.pc;
+        let opcode = m.word(pc);
+        let x = ((opcode >> 8) & 0xF) as usize;
+        let y = ((opcode >> 4) & 0xF) as usize;
+        let n = opcode & 0xF;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        let next = pc.wrapping_add(2);
+        // Only XO-CHIP has a 4-byte instruction, F000 NNNN, which skips step over whole.
+        let xo_chip = set == InstructionSet::XoChip;
+        let super_chip = xo_chip || set == InstructionSet::SuperChip;
+        let after_next = next.wrapping_add(if xo_chip && m.word(next) == 0xF000 { 4 } else { 2 });
+        let skip = |condition: bool| if condition { after_next } else { next };
+
+        m.pc = match opcode >> 12 {
+            0x0 if opcode == 0x00E0 => {
+                m.screen = vec![false; WIDTH * HEIGHT];
+                next
+            }
+            0x0 if opcode == 0x00EE => match m.stack.pop() {
+                Some(address) => address,
+                None => return m.faulted(Fault::StackUnderflow { pc }),
+            },
+            0x1 => nnn,
+            0x2 if m.stack.len() == stack_depth => return m.faulted(Fault::StackOverflow { pc }),
+            0x2 => {
+                m.stack.push(next);
+                nnn
+            }
+            0x3 => skip(m.v[x] == nn),
+            0x4 => skip(m.v[x] != nn),
+            0x5 => skip(m.v[x] == m.v[y]),
+            0x6 => {
+                m.v[x] = nn;
+                next
+            }
+            0x7 => {
+                m.v[x] = ((m.v[x] as u16 + nn as u16) % 256) as u8;
+                next
+            }
+            0x8 => {
+                let (vx, vy) = (m.v[x] as u16, m.v[y] as u16);
+                let shifted = if quirks.shift { vx } else { vy };
+                let (result, flag) = match n {
+                    0x0 => (vy, None),
+                    0x1 => (vx | vy, quirks.logic.then_some(0)),
+                    0x2 => (vx & vy, quirks.logic.then_some(0)),
+                    0x3 => (vx ^ vy, quirks.logic.then_some(0)),
+                    0x4 => (vx + vy, Some(if vx + vy > 255 { 1 } else { 0 })),
+                    0x5 => (vx + 256 - vy, Some(if vx >= vy { 1 } else { 0 })),
+                    0x6 => (shifted / 2, Some(shifted % 2)),
+                    0x7 => (vy + 256 - vx, Some(if vy >= vx { 1 } else { 0 })),
+                    _ => (shifted * 2, Some(shifted / 128)),
+                };
+                m.v[x] = (result % 256) as u8;
+                if let Some(flag) = flag {
+                    m.v[0xF] = flag as u8;
+                }
+                next
+            }
+            0x9 => skip(m.v[x] != m.v[y]),
+            0xA => {
+                m.i = nnn;
+                next
+            }
+            0xB if quirks.jump => nnn + m.v[x] as u16,
+            0xB => nnn + m.v[0] as u16,
+            0xD => {
+                m.v[0xF] = if m.draw(m.v[x] as usize, m.v[y] as usize, n as usize, quirks.wrap) { 1 } else { 0 };
+                next
+            }
+            0xE if nn == 0x9E => skip(keys[(m.v[x] % 16) as usize]),
+            0xE if nn == 0xA1 => skip(!keys[(m.v[x] % 16) as usize]),
+            0xF if opcode == 0xF000 && xo_chip => {
+                m.i = m.word(next);
+                next.wrapping_add(2)
+            }
+            0xF if opcode == 0xF000 || (nn == 0x30 && !super_chip) => return m.faulted(Fault::UnknownOpcode { pc, opcode }),
+            0xF => {
+                match nn {
+                    0x07 => m.v[x] = m.delay,
+                    // Only completes once a key is released, which takes at least another step.
+                    0x0A => return m,
+                    0x15 => m.delay = m.v[x],
+                    0x18 => m.sound = m.v[x],
+                    0x1E => {
+                        let sum = m.i as u32 + m.v[x] as u32;
+                        m.i = (sum % 0x10000) as u16;
+                        if quirks.index_overflow {
+                            m.v[0xF] = if sum > 0xFFF { 1 } else { 0 };
+                        }
+                    }
+                    0x29 => m.i = (m.v[x] % 16) as u16 * 5,
+                    0x30 => m.i = 80 + (m.v[x] % 16) as u16 * 10,
+                    0x33 => {
+                        let digits = format!("{:03}", m.v[x]);
+                        for (offset, digit) in digits.bytes().enumerate() {
+                            m.write(m.i.wrapping_add(offset as u16), digit - b'0');
+                        }
+                    }
+                    0x55 | 0x65 => {
+                        for register in 0..=x {
+                            let address = m.i.wrapping_add(register as u16);
+                            if nn == 0x55 {
+                                m.write(address, m.v[register]);
+                            } else {
+                                m.v[register] = m.read(address);
+                            }
+                        }
+                        if !quirks.memory_leave_i_unchanged {
+                            m.i = m.i.wrapping_add(if quirks.memory_increment_by_x { x } else { x + 1 } as u16);
+                        }
+                    }
+                    _ => unreachable!("not generated"),
+                }
+                next
+            }
+            _ => unreachable!("not generated"),
+        };
+        m
+    }
+
+    fn faulted(mut self, fault: Fault) -> Machine {
+        self.fault = Some(fault);
+        self
+    }
+
+    // XORs an 8 pixel wide sprite read from I onto the screen. Returns whether a lit pixel was turned off.
+    fn draw(&mut self, x: usize, y: usize, height: usize, wrap: bool) -> bool {
+        let mut collision = false;
+        for row in 0..height {
+            let bits = self.read(self.i.wrapping_add(row as u16));
+            for column in 0..8 {
+                let (mut px, mut py) = (x % WIDTH + column, y % HEIGHT + row);
+                if wrap {
+                    px %= WIDTH;
+                    py %= HEIGHT;
+                } else if px >= WIDTH || py >= HEIGHT {
+                    continue;
+                }
+                if bits & (0x80 >> column) != 0 {
+                    collision |= self.screen[py * WIDTH + px];
+                    self.screen[py * WIDTH + px] ^= true;
+                }
+            }
+        }
+        collision
+    }
+}
+
+// Sets up the interpreter in the state of `machine`.
+fn chip8(machine: &Machine, platform: Platform, quirks: Quirks, keys: [bool; 16]) -> Chip8 {
+    let mut emu = Chip8::new();
+    emu.set_platform(platform);
+    emu.set_quirks(quirks);
+    emu.set_stack_depth(platform.stack_depth());
+    emu.set_memory_size(MEMORY_SIZE);
+    emu.memory.load(0, &machine.memory);
+    emu.v = machine.v;
+    emu.address_register = machine.i;
+    emu.pc = machine.pc;
+    for &address in &machine.stack {
+        emu.stack.push(address);
+    }
+    emu.timer_delay = machine.delay;
+    emu.timer_sound = machine.sound;
+    for (index, _) in machine.screen.iter().enumerate().filter(|(_, &lit)| lit) {
+        emu.display.draw((index % WIDTH) as u8, (index / WIDTH) as u8);
+    }
+    for (key, _) in keys.iter().enumerate().filter(|(_, &held)| held) {
+        emu.keypad.press(key);
+    }
+    emu
+}
+
+fn observe(emu: &Chip8) -> Machine {
+    Machine {
+        v: emu.v,
+        i: emu.address_register,
+        pc: emu.pc,
+        stack: emu.stack().to_vec(),
+        delay: emu.timer_delay,
+        sound: emu.timer_sound,
+        memory: emu.memory().to_vec(),
+        screen: (0..WIDTH * HEIGHT).map(|index| emu.pixel(index % WIDTH, index / WIDTH)).collect(),
+        fault: emu.fault(),
+    }
+}
+
+// A random machine about to execute an instruction drawn from `opcodes`. The stack can be full on
+// platforms with the smallest stack.
+fn machine(opcodes: impl Strategy<Value = u16>) -> impl Strategy<Value = Machine> {
+    (
+        any::<[u8; 16]>(),
+        any::<u16>(),
+        (0x100u16..0x7FF).prop_map(|word| word * 2),
+        prop::collection::vec(any::<u16>(), 0..=12),
+        any::<(u8, u8)>(),
+        prop::collection::vec(any::<u8>(), MEMORY_SIZE),
+        prop::collection::vec(any::<bool>(), WIDTH * HEIGHT),
+        opcodes,
+    ).prop_map(|(v, i, pc, stack, (delay, sound), mut memory, screen, opcode)| {
+        memory[pc as usize..pc as usize + 2].copy_from_slice(&opcode.to_be_bytes());
+        Machine { v, i, pc, stack, delay, sound, memory, screen, fault: None }
+    })
+}
+
+fn platforms() -> impl Strategy<Value = Platform> {
+    prop::sample::select(&PLATFORMS[..])
+}
+
+// Any combination of quirks, not only the ones a platform defaults to.
+fn quirks() -> impl Strategy<Value = Quirks> {
+    any::<[bool; 9]>().prop_map(|quirks| Quirks {
+        shift: quirks[0],
+        memory_increment_by_x: quirks[1],
+        memory_leave_i_unchanged: quirks[2],
+        wrap: quirks[3],
+        jump: quirks[4],
+        vblank: quirks[5],
+        logic: quirks[6],
+        vip_timing: quirks[7],
+        index_overflow: quirks[8],
+    })
+}
+
+// Operands filled with random values in an opcode pattern.
+const NONE: u16 = 0x0000;
+const X: u16 = 0x0F00;
+const XY: u16 = 0x0FF0;
+const XNN: u16 = 0x0FFF;
+
+// Opcodes made from one of the patterns, with random operands.
+fn opcodes(patterns: &'static [(u16, u16)]) -> impl Strategy<Value = u16> {
+    (prop::sample::select(patterns), any::<u16>()).prop_map(|((pattern, operands), value)| pattern | (value & operands))
+}
+
+// Steps the interpreter and the reference from the same state and compares the results.
+fn check(machine: Machine, platform: Platform, quirks: Quirks, keys: [bool; 16]) -> Result<(), TestCaseError> {
+    let expected = machine.step(platform.instruction_set(), quirks, keys, platform.stack_depth());
+    let mut emu = chip8(&machine, platform, quirks, keys);
+    emu.tick();
+    let actual = observe(&emu);
+
+    let context = format!("opcode {:04X} on {:?} with {:?}", machine.word(machine.pc), platform, quirks);
+    prop_assert_eq!(actual.v, expected.v, "V after {}", context);
+    prop_assert_eq!(actual.i, expected.i, "I after {}", context);
+    prop_assert_eq!(actual.pc, expected.pc, "PC after {}", context);
+    prop_assert_eq!(&actual.stack, &expected.stack, "stack after {}", context);
+    prop_assert_eq!((actual.delay, actual.sound), (expected.delay, expected.sound), "timers after {}", context);
+    prop_assert_eq!(actual.fault, expected.fault, "fault after {}", context);
+    let address = (0..MEMORY_SIZE).find(|&address| actual.memory[address] != expected.memory[address]);
+    prop_assert_eq!(address, None, "first differing memory address after {}", context);
+    let pixel = (0..WIDTH * HEIGHT).find(|&index| actual.screen[index] != expected.screen[index]);
+    prop_assert_eq!(pixel.map(|index| (index % WIDTH, index / WIDTH)), None, "first differing pixel after {}", context);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn arithmetic_and_logic_should_match_the_reference(
+        platform in platforms(),
+        quirks in quirks(),
+        machine in machine(opcodes(&[(0x6000, XNN), (0x7000, XNN), (0x8000, XY), (0x8001, XY), (0x8002, XY), (0x8003, XY), (0x8004, XY), (0x8005, XY), (0x8006, XY), (0x8007, XY), (0x800E, XY)])),
+    ) {
+        check(machine, platform, quirks, [false; 16])?;
+    }
+
+    #[test]
+    fn skips_should_match_the_reference(
+        platform in platforms(),
+        quirks in quirks(),
+        keys in any::<[bool; 16]>(),
+        machine in machine(opcodes(&[(0x3000, XNN), (0x4000, XNN), (0x5000, XY), (0x9000, XY), (0xE09E, X), (0xE0A1, X)])),
+    ) {
+        check(machine, platform, quirks, keys)?;
+    }
+
+    // Random memory almost never holds F000 after a skip, so it is put there.
+    #[test]
+    fn skips_over_f000_should_match_the_reference(
+        platform in platforms(),
+        quirks in quirks(),
+        mut machine in machine(opcodes(&[(0x3000, XNN), (0x4000, XNN), (0x5000, XY), (0x9000, XY)])),
+    ) {
+        let next = machine.pc as usize + 2;
+        machine.memory[next..next + 2].copy_from_slice(&0xF000u16.to_be_bytes());
+        check(machine, platform, quirks, [false; 16])?;
+    }
+
+    #[test]
+    fn control_flow_should_match_the_reference(
+        platform in platforms(),
+        quirks in quirks(),
+        machine in machine(opcodes(&[(0x00EE, NONE), (0x1000, XNN), (0x2000, XNN), (0xB000, XNN)])),
+    ) {
+        check(machine, platform, quirks, [false; 16])?;
+    }
+
+    #[test]
+    fn memory_and_index_should_match_the_reference(
+        platform in platforms(),
+        quirks in quirks(),
+        machine in machine(opcodes(&[(0xA000, XNN), (0xF000, NONE), (0xF01E, X), (0xF029, X), (0xF030, X), (0xF033, X), (0xF055, X), (0xF065, X)])),
+    ) {
+        check(machine, platform, quirks, [false; 16])?;
+    }
+
+    #[test]
+    fn sprites_should_match_the_reference(
+        platform in platforms(),
+        quirks in quirks(),
+        machine in machine(opcodes(&[(0x00E0, NONE), (0xD000, XNN)])),
+    ) {
+        check(machine, platform, quirks, [false; 16])?;
+    }
+
+    #[test]
+    fn timers_and_key_waits_should_match_the_reference(
+        platform in platforms(),
+        quirks in quirks(),
+        keys in any::<[bool; 16]>(),
+        machine in machine(opcodes(&[(0xF007, X), (0xF00A, X), (0xF015, X), (0xF018, X)])),
+    ) {
+        check(machine, platform, quirks, keys)?;
+    }
+
+    // CXNN is random, so only the mask can be checked.
+    #[test]
+    fn random_numbers_should_be_masked_with_nn(x in 0usize..16, nn: u8, seed: u64) {
+        let mut emu = Chip8::new();
+        emu.seed_rng(seed);
+        emu.memory.load(0x200, &(0xC000 | ((x as u16) << 8) | nn as u16).to_be_bytes());
+
+        emu.tick();
+
+        prop_assert_eq!(emu.v[x] & !nn, 0);
+        prop_assert_eq!(emu.pc, 0x202);
+    }
+}
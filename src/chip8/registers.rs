@@ -0,0 +1,103 @@
+use super::Chip8;
+
+/**
+CPU registers, as shown and edited by debuggers.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+impl Register {
+    pub const ALL: [Register; 21] = [
+        Register::V(0x0), Register::V(0x1), Register::V(0x2), Register::V(0x3),
+        Register::V(0x4), Register::V(0x5), Register::V(0x6), Register::V(0x7),
+        Register::V(0x8), Register::V(0x9), Register::V(0xA), Register::V(0xB),
+        Register::V(0xC), Register::V(0xD), Register::V(0xE), Register::V(0xF),
+        Register::I,
+        Register::Pc,
+        Register::Sp,
+        Register::Dt,
+        Register::St,
+    ];
+
+    pub fn name(&self) -> String {
+        match self {
+            Register::V(index) => format!("V{:X}", index),
+            Register::I => "I".to_string(),
+            Register::Pc => "PC".to_string(),
+            Register::Sp => "SP".to_string(),
+            Register::Dt => "DT".to_string(),
+            Register::St => "ST".to_string(),
+        }
+    }
+
+    // Size of the register in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Register::I | Register::Pc => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl Chip8 {
+    pub fn read_register(&self, register: Register) -> u16 {
+        match register {
+            Register::V(index) => self.v[index] as u16,
+            Register::I => self.address_register,
+            Register::Pc => self.pc,
+            Register::Sp => self.stack.len() as u16,
+            Register::Dt => self.timer_delay as u16,
+            Register::St => self.timer_sound as u16,
+        }
+    }
+
+    // Values wider than the register are truncated. SP is limited to the stack depth.
+    pub fn write_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::V(index) => self.v[index] = value as u8,
+            Register::I => self.address_register = value,
+            Register::Pc => self.pc = value,
+            Register::Sp => self.stack.set_len(value as usize),
+            Register::Dt => self.timer_delay = value as u8,
+            Register::St => self.timer_sound = value as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::stack::DEFAULT_STACK_DEPTH;
+
+    #[test]
+    fn test_registers_should_be_written_and_read_back() {
+        let mut emu = Chip8::new();
+
+        for (index, register) in Register::ALL.into_iter().enumerate() {
+            emu.write_register(register, index as u16);
+        }
+
+        for (index, register) in Register::ALL.into_iter().enumerate() {
+            let expected = if register == Register::Sp { DEFAULT_STACK_DEPTH } else { index };
+            assert_eq!(emu.read_register(register), expected as u16, "{}", register.name());
+        }
+    }
+
+    #[test]
+    fn test_write_register_should_truncate_values_to_register_size() {
+        let mut emu = Chip8::new();
+
+        emu.write_register(Register::V(0), 0x1234);
+        emu.write_register(Register::Sp, 0xFF);
+
+        assert_eq!(emu.read_register(Register::V(0)), 0x34);
+        assert_eq!(emu.read_register(Register::Sp), DEFAULT_STACK_DEPTH as u16);
+    }
+}
@@ -0,0 +1,111 @@
+use super::{Chip8, Fault, Memory, Platform, Quirks, V_SIZE};
+use crate::chip8::display::Display;
+use crate::chip8::font::Font;
+use crate::chip8::stack::Stack;
+
+/**
+Copy of the whole machine state, to go back to it later with `Chip8::restore`.
+Memory observers and the keypad are not part of it.
+ */
+#[derive(Clone, PartialEq)]
+pub struct Snapshot {
+    platform: Platform,
+    memory: Vec<u8>,
+    display: Display,
+    v: [u8; V_SIZE],
+    address_register: u16,
+    pc: u16,
+    stack: Stack,
+    timer_delay: u8,
+    timer_sound: u8,
+    fault: Option<Fault>,
+    awaited_key: Option<usize>,
+    waiting_delay: bool,
+    drawn_this_frame: bool,
+    waiting_vblank: bool,
+    frame_cycles: u32,
+    quirks: Quirks,
+    font: Font,
+    font_address: u16,
+}
+
+impl Chip8 {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            platform: self.platform,
+            memory: self.memory.as_slice().to_vec(),
+            display: self.display.clone(),
+            v: self.v,
+            address_register: self.address_register,
+            pc: self.pc,
+            stack: self.stack.clone(),
+            timer_delay: self.timer_delay,
+            timer_sound: self.timer_sound,
+            fault: self.fault,
+            awaited_key: self.awaited_key,
+            waiting_delay: self.waiting_delay,
+            drawn_this_frame: self.drawn_this_frame,
+            waiting_vblank: self.waiting_vblank,
+            frame_cycles: self.frame_cycles,
+            quirks: self.quirks,
+            font: self.font.clone(),
+            font_address: self.font_address,
+        }
+    }
+
+    // Observers stay attached unless the memory size changes.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if self.memory.size() != snapshot.memory.len() {
+            self.memory = Memory::new(snapshot.memory.len());
+            self.set_decode_cache(self.decode_cache.is_enabled());
+            #[cfg(feature = "jit")]
+            if let Some(jit) = &mut self.jit {
+                jit.resize(snapshot.memory.len());
+            }
+        }
+        self.platform = snapshot.platform;
+        self.memory.load(0, &snapshot.memory);
+        if let Some(linter) = &mut self.linter {
+            linter.assume_initialised(snapshot.memory.len());
+        }
+        self.forget_decoded_code();
+        self.display = snapshot.display.clone();
+        self.v = snapshot.v;
+        self.address_register = snapshot.address_register;
+        self.pc = snapshot.pc;
+        self.stack = snapshot.stack.clone();
+        self.timer_delay = snapshot.timer_delay;
+        self.timer_sound = snapshot.timer_sound;
+        self.fault = snapshot.fault;
+        self.awaited_key = snapshot.awaited_key;
+        self.waiting_delay = snapshot.waiting_delay;
+        self.drawn_this_frame = snapshot.drawn_this_frame;
+        self.waiting_vblank = snapshot.waiting_vblank;
+        self.frame_cycles = snapshot.frame_cycles;
+        self.quirks = snapshot.quirks;
+        self.font = snapshot.font.clone();
+        self.font_address = snapshot.font_address;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_should_bring_back_the_state_of_the_snapshot() {
+        let mut emu = Chip8::new();
+        emu.load(vec![0x60, 0x2A, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06]);
+        emu.start();
+        let snapshot = emu.snapshot();
+
+        emu.run_frame(4);
+        assert_eq!(emu.v[0], 0x2A);
+        assert_eq!(emu.memory()[0x300], 0x2A);
+
+        emu.restore(&snapshot);
+        assert_eq!(emu.v[0], 0);
+        assert_eq!(emu.memory()[0x300], 0);
+        assert_eq!(emu.pc, 0x200);
+    }
+}
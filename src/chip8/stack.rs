@@ -0,0 +1,88 @@
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+
+/**
+Return addresses of the subroutines being executed, oldest first.
+The depth is fixed by the platform: the COSMAC VIP interpreter had room for 12 entries,
+SCHIP and later ones for 16. Overflow and underflow are reported to the caller instead of
+being ignored, so they can be raised as machine faults.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Stack {
+    entries: Vec<u16>,
+    depth: usize,
+}
+
+impl Stack {
+    pub fn new(depth: usize) -> Stack {
+        Stack {
+            entries: Vec::with_capacity(depth),
+            depth,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn entries(&self) -> &[u16] {
+        &self.entries
+    }
+
+    // Returns false, leaving the stack untouched, when it is full.
+    pub fn push(&mut self, value: u16) -> bool {
+        if self.entries.len() >= self.depth {
+            return false;
+        }
+
+        self.entries.push(value);
+        true
+    }
+
+    // Returns None when the stack is empty.
+    pub fn pop(&mut self) -> Option<u16> {
+        self.entries.pop()
+    }
+
+    // Number of entries, i.e. the stack pointer.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Moves the stack pointer, limited to the depth. New entries are zeroed.
+    pub fn set_len(&mut self, len: usize) {
+        self.entries.resize(len.min(self.depth), 0);
+    }
+
+    pub fn write(&mut self, index: usize, value: u16) {
+        self.entries[index] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_should_fail_once_the_depth_is_reached() {
+        let mut stack = Stack::new(12);
+
+        for address in 0..12 {
+            assert!(stack.push(address));
+        }
+
+        assert!(!stack.push(0x300));
+        assert_eq!(stack.len(), 12);
+        assert_eq!(stack.entries().last(), Some(&11));
+    }
+
+    #[test]
+    fn pop_should_return_entries_last_first_and_none_when_empty() {
+        let mut stack = Stack::new(DEFAULT_STACK_DEPTH);
+        stack.push(0x202);
+        stack.push(0x404);
+
+        assert_eq!(stack.pop(), Some(0x404));
+        assert_eq!(stack.pop(), Some(0x202));
+        assert_eq!(stack.pop(), None);
+    }
+}
@@ -0,0 +1,141 @@
+use super::{Chip8, ProgramCounter};
+
+/**
+COSMAC VIP timing.
+The CDP1802 runs at 1.7609 MHz and a machine cycle takes 8 clock cycles, which gives about
+3668 machine cycles per 60Hz frame. The CDP1861 video chip steals 1024 of them through DMA to
+display the 256 bytes of the screen, and its interrupt routine, which also updates the timers,
+takes about 46 more. The rest is left to the interpreter.
+ */
+pub const VIP_MACHINE_CYCLES_PER_FRAME: u32 = 3668;
+pub const VIP_DISPLAY_CYCLES_PER_FRAME: u32 = 1024 + 46;
+pub const VIP_INTERPRETER_CYCLES_PER_FRAME: u32 = VIP_MACHINE_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES_PER_FRAME;
+
+// Machine cycles the interpreter loop takes to fetch and decode an instruction.
+const FETCH_CYCLES: u32 = 40;
+
+impl Chip8 {
+    /**
+    Approximate machine cycles the COSMAC VIP interpreter spends on `opcode`, including its
+    fetch and decode. `next` is the result of executing it, since taken skips cost more.
+    Costs that depend on operands (sprite height and alignment, BCD digits, registers
+    transferred) are computed from the machine state after execution.
+     */
+    pub(crate) fn vip_cycles(&self, opcode: u16, next: ProgramCounter) -> u32 {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let skip = if next == ProgramCounter::Skip { 4 } else { 0 };
+
+        let execute = match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => 24 + 3054,
+                0x00EE => 10,
+                _ => 0,
+            },
+            0x1000 => 12,
+            0x2000 => 26,
+            0x3000 | 0x4000 => 10 + skip,
+            0x5000 | 0x9000 => 18 + skip,
+            0x6000 => 6,
+            0x7000 => 10,
+            0x8000 => 44,
+            0xA000 => 12,
+            0xB000 => 22,
+            0xC000 => 36,
+            0xD000 => self.vip_draw_cycles(x, (opcode & 0x000F) as u32),
+            0xE000 => 18 + skip,
+            0xF000 => match opcode & 0x00FF {
+                0x0A => 19,
+                0x1E => 16,
+                0x29 => 16,
+                // The interpreter subtracts powers of ten repeatedly, once per unit of each digit.
+                0x33 => {
+                    let value = self.v[x] as u32;
+                    84 + 16 * (value / 100 + (value / 10) % 10 + value % 10)
+                }
+                0x55 | 0x65 => 14 + 14 * (x as u32 + 1),
+                _ => 10,
+            },
+            _ => 0,
+        };
+
+        FETCH_CYCLES + execute
+    }
+
+    // Each sprite row is shifted into place bit by bit, and rows not aligned to a byte touch two.
+    fn vip_draw_cycles(&self, x: usize, height: u32) -> u32 {
+        let misalignment = (self.v[x] % 8) as u32;
+        let row = if misalignment == 0 { 36 } else { 56 + 8 * misalignment };
+        26 + height * row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Quirks;
+
+    fn a_chip8_with_program(program: &[u16], quirks: Quirks) -> Chip8 {
+        let mut emu = Chip8::new();
+        let program: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        emu.memory.load(0x200, &program);
+        emu.set_quirks(quirks);
+        emu.start();
+        emu
+    }
+
+    #[test]
+    fn vip_timing_should_run_instructions_until_the_frame_cycles_are_spent() {
+        // 6000 costs 46 cycles and 1200 costs 52: 54 instructions spend 2646 of the 2598 cycles.
+        let mut emu = a_chip8_with_program(&[0x6000, 0x1200], Quirks { vip_timing: true, ..Quirks::default() });
+
+        assert_eq!(emu.run_frame(1), 54);
+        assert_eq!(emu.frame_cycles, 2646 - VIP_INTERPRETER_CYCLES_PER_FRAME);
+    }
+
+    #[test]
+    fn cycles_should_only_be_counted_with_vip_timing() {
+        // Clears the screen, the most expensive instruction, in a loop.
+        let mut emu = a_chip8_with_program(&[0x00E0, 0x1200], Quirks { vip_timing: false, ..Quirks::default() });
+
+        for _ in 0..1000 {
+            emu.run_frame(1000);
+        }
+        for _ in 0..1000 {
+            emu.tick();
+        }
+        assert_eq!(emu.frame_cycles, 0);
+
+        emu.set_quirks(Quirks { vip_timing: true, ..Quirks::default() });
+        assert_eq!(emu.run_frame(1), 1);
+    }
+
+    #[test]
+    fn draw_cycles_should_depend_on_height_and_alignment() {
+        let mut emu = Chip8::new();
+        emu.v[0] = 8;
+        emu.v[1] = 11;
+
+        assert_eq!(emu.vip_cycles(0xD005, ProgramCounter::Next), FETCH_CYCLES + 26 + 5 * 36);
+        assert_eq!(emu.vip_cycles(0xD10A, ProgramCounter::Next), FETCH_CYCLES + 26 + 10 * (56 + 8 * 3));
+    }
+
+    #[test]
+    fn taken_skips_and_bcd_digits_should_cost_more() {
+        let mut emu = Chip8::new();
+        emu.v[2] = 199;
+
+        assert_eq!(emu.vip_cycles(0x3000, ProgramCounter::Skip) - emu.vip_cycles(0x3000, ProgramCounter::Next), 4);
+        assert_eq!(emu.vip_cycles(0xF233, ProgramCounter::Next), FETCH_CYCLES + 84 + 16 * 19);
+    }
+
+    #[test]
+    fn vblank_quirk_should_draw_one_sprite_per_frame() {
+        let mut emu = a_chip8_with_program(&[0xD001, 0xD001, 0x1204], Quirks { vblank: true, ..Quirks::default() });
+
+        assert_eq!(emu.run_frame(10), 2);
+        assert_eq!(emu.pc, 0x202);
+
+        emu.run_frame(10);
+        assert_eq!(emu.pc, 0x204);
+    }
+}
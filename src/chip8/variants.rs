@@ -0,0 +1,287 @@
+use super::{Chip8, ProgramCounter};
+
+// The colour board's coarse zones are 4 pixel rows high.
+const COLOUR_ZONE_HEIGHT: usize = 4;
+
+impl Chip8 {
+    // 02A0	CHIP-8X: steps the background colour through blue, black, green and red.
+    pub fn opcode_cycle_background(&mut self) -> ProgramCounter {
+        self.display.cycle_background();
+        ProgramCounter::Next
+    }
+
+    // 5XY1	CHIP-8X: adds VY to VX nibble by nibble, each nibble wrapping around at 8.
+    pub fn opcode_add_vy_to_vx_by_nibble(&mut self, x: usize, y: usize) -> ProgramCounter {
+        let (vx, vy) = (self.v[x], self.v[y]);
+        self.v[x] = (((vx & 0x70) + (vy & 0x70)) & 0x70) | (((vx & 0x07) + (vy & 0x07)) & 0x07);
+        ProgramCounter::Next
+    }
+
+    // BXYN	CHIP-8X: sets the foreground colour of a block of zones to VY. The low nibble of VX is
+    // the first 8 pixel wide column and its high nibble the number of columns after it. With N = 0,
+    // V(X+1) selects 4 pixel high rows the same way, otherwise N pixel rows starting at V(X+1).
+    pub fn opcode_set_foreground(&mut self, x: usize, y: usize, n: u8) -> ProgramCounter {
+        let horizontal = self.v[x];
+        let vertical = self.v[(x + 1) & 0xF];
+        let columns = zone_span(horizontal);
+        let rows = if n == 0 {
+            let zones = zone_span(vertical);
+            zones.start * COLOUR_ZONE_HEIGHT..zones.end * COLOUR_ZONE_HEIGHT
+        } else {
+            vertical as usize..vertical as usize + n as usize
+        };
+        self.display.set_foreground(columns, rows, self.v[y]);
+        ProgramCounter::Next
+    }
+
+    // EXF2 and EXF5	CHIP-8X: like EX9E and EXA1, on the second keypad.
+    pub fn opcode_skip_second_key_pressed_in_vx(&mut self, x: usize) -> ProgramCounter {
+        ProgramCounter::skip_if(self.second_keypad.status((self.v[x] & 0xF) as usize) == 1)
+    }
+    pub fn opcode_skip_second_key_not_pressed_in_vx(&mut self, x: usize) -> ProgramCounter {
+        ProgramCounter::skip_if(self.second_keypad.status((self.v[x] & 0xF) as usize) == 0)
+    }
+
+    // FXF8 (CHIP-8X) and FX03 (CHIP-8E)	Sends VX to an output port. No device is attached.
+    pub fn opcode_output_vx(&mut self, _x: usize) -> ProgramCounter {
+        ProgramCounter::Next
+    }
+
+    // FXFB (CHIP-8X), FXE3 and FXE7 (CHIP-8E)	Reads an input port into VX. With no device
+    // attached, the port reads 0 and the strobe FXFB and FXE3 wait for is always there.
+    pub fn opcode_input_to_vx(&mut self, x: usize) -> ProgramCounter {
+        self.v[x] = 0;
+        ProgramCounter::Next
+    }
+
+    // 00ED	CHIP-8E: stops the program. It stays on this instruction until reset.
+    pub fn opcode_stop(&mut self) -> ProgramCounter {
+        self.play = false;
+        ProgramCounter::Wait
+    }
+
+    // 0151	CHIP-8E: waits until the delay timer reaches 0.
+    pub fn opcode_wait_delay(&mut self) -> ProgramCounter {
+        if self.timer_delay > 0 { ProgramCounter::Wait } else { ProgramCounter::Next }
+    }
+
+    // 5XY1	CHIP-8E: skips the next instruction if VX is greater than VY.
+    pub fn opcode_skip_if_vx_greater_than_vy(&mut self, x: usize, y: usize) -> ProgramCounter {
+        ProgramCounter::skip_if(self.v[x] > self.v[y])
+    }
+
+    // 5XY2	CHIP-8E: stores VX to VY (including VY) in memory starting at I, which is left past
+    // the last byte written like FX55 on the VIP. Nothing is stored when X is greater than Y.
+    pub fn opcode_store_vx_to_vy(&mut self, x: usize, y: usize) -> ProgramCounter {
+        for register in x..=y {
+            self.store(self.address_register, self.v[register]);
+            self.address_register = self.address_register.wrapping_add(1);
+        }
+        ProgramCounter::Next
+    }
+
+    // 5XY3	CHIP-8E: fills VX to VY (including VY) from memory starting at I, like 5XY2.
+    pub fn opcode_load_vx_to_vy(&mut self, x: usize, y: usize) -> ProgramCounter {
+        for register in x..=y {
+            self.v[register] = self.memory.read_u8(self.address_register);
+            self.address_register = self.address_register.wrapping_add(1);
+        }
+        ProgramCounter::Next
+    }
+
+    // BBNN and BFNN	CHIP-8E: branches NN bytes back or forward from this instruction.
+    pub fn opcode_branch_back(&mut self, nn: u8) -> ProgramCounter {
+        ProgramCounter::Jump(self.pc.wrapping_sub(nn as u16))
+    }
+    pub fn opcode_branch_forward(&mut self, nn: u8) -> ProgramCounter {
+        ProgramCounter::Jump(self.pc.wrapping_add(nn as u16))
+    }
+
+    // FX1B	CHIP-8E: skips VX bytes past this instruction.
+    pub fn opcode_skip_vx_bytes(&mut self, x: usize) -> ProgramCounter {
+        ProgramCounter::Jump(self.pc.wrapping_add(2).wrapping_add(self.v[x] as u16))
+    }
+
+    // FX4F	CHIP-8E: sets the delay timer to VX and waits until it reaches 0.
+    pub fn opcode_wait_vx_frames(&mut self, x: usize) -> ProgramCounter {
+        if !self.waiting_delay {
+            self.timer_delay = self.v[x];
+            self.waiting_delay = true;
+        }
+        if self.timer_delay > 0 {
+            return ProgramCounter::Wait;
+        }
+        self.waiting_delay = false;
+        ProgramCounter::Next
+    }
+}
+
+// Colour zones selected by a BXYN coordinate: the low nibble is the first one, the high nibble
+// how many follow it.
+fn zone_span(coordinate: u8) -> std::ops::Range<usize> {
+    let first = (coordinate & 0xF) as usize;
+    first..first + (coordinate >> 4) as usize + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Platform;
+
+    // A Chip8 set up for `platform` with `program` stored where execution starts.
+    fn a_chip8_with_program(platform: Platform, program: &[u16]) -> Chip8 {
+        let mut emu = Chip8::new();
+        emu.set_platform(platform);
+        let program: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        emu.memory.load(platform.start_address(), &program);
+        emu.start();
+        emu
+    }
+
+    fn step(emu: &mut Chip8, instructions: usize) {
+        for _ in 0..instructions {
+            emu.tick();
+        }
+    }
+
+    #[test]
+    fn set_platform_should_select_start_address_and_display_geometry() {
+        let mut emu = Chip8::new();
+
+        emu.set_platform(Platform::HiResChip8);
+        assert_eq!((emu.pc, emu.display_size()), (0x2C0, (64, 64)));
+        emu.set_platform(Platform::Chip8X);
+        assert_eq!((emu.pc, emu.display_size()), (0x300, (64, 32)));
+        emu.set_platform(Platform::OriginalChip8);
+        assert_eq!((emu.pc, emu.display_size()), (0x200, (64, 32)));
+    }
+
+    #[test]
+    fn hires_chip8_should_draw_on_the_lower_half_and_clear_with_0230() {
+        let mut emu = a_chip8_with_program(Platform::HiResChip8, &[0x6130, 0x6228, 0xA000, 0xD121, 0x0230]);
+        emu.memory.load(0x000, &[0x80]);
+
+        step(&mut emu, 4);
+        assert!(emu.pixel(0x30, 0x28));
+
+        step(&mut emu, 1);
+        assert!(!emu.pixel(0x30, 0x28));
+        assert_eq!(emu.fault(), None);
+    }
+
+    #[test]
+    fn variant_opcodes_should_be_unknown_on_chip8() {
+        let mut emu = a_chip8_with_program(Platform::ModernChip8, &[0x0230]);
+
+        step(&mut emu, 1);
+
+        assert!(emu.fault().is_some());
+    }
+
+    #[test]
+    fn test_5xy1_should_decode_per_platform() {
+        for (platform, v0, offset) in [
+            // CHIP-8 ignores N and compares V0 with V1.
+            (Platform::ModernChip8, 0x35, 6),
+            (Platform::Chip8X, 0x40, 6),
+            (Platform::Chip8E, 0x35, 8),
+        ] {
+            let mut emu = a_chip8_with_program(platform, &[0x6035, 0x6113, 0x5011]);
+
+            step(&mut emu, 3);
+
+            assert_eq!((emu.v[0], emu.pc - platform.start_address()), (v0, offset), "{:?}", platform);
+        }
+    }
+
+    #[test]
+    fn chip8x_bxy0_should_colour_zones_of_8x4_pixels() {
+        // Columns 1 to 2, rows 4 to 7, colour 5.
+        let mut emu = a_chip8_with_program(Platform::Chip8X, &[0x6011, 0x6101, 0x6205, 0xB020, 0x02A0]);
+
+        step(&mut emu, 4);
+
+        assert_eq!(emu.display.colour_codes(8, 4), Some((5, 2)));
+        assert_eq!(emu.display.colour_codes(23, 7), Some((5, 2)));
+        assert_eq!(emu.display.colour_codes(7, 4), Some((1, 2)));
+        assert_eq!(emu.display.colour_codes(8, 8), Some((1, 2)));
+
+        step(&mut emu, 1);
+        assert_eq!(emu.display.colour_codes(0, 0), Some((1, 0)));
+    }
+
+    #[test]
+    fn chip8x_bxyn_should_colour_n_pixel_rows() {
+        let mut emu = a_chip8_with_program(Platform::Chip8X, &[0x6000, 0x6103, 0x6207, 0xB022]);
+
+        step(&mut emu, 4);
+
+        assert_eq!(emu.display.colour_codes(0, 2), Some((1, 2)));
+        assert_eq!(emu.display.colour_codes(0, 3), Some((7, 2)));
+        assert_eq!(emu.display.colour_codes(0, 4), Some((7, 2)));
+        assert_eq!(emu.display.colour_codes(0, 5), Some((1, 2)));
+    }
+
+    #[test]
+    fn chip8x_exf2_should_read_the_second_keypad() {
+        let mut emu = a_chip8_with_program(Platform::Chip8X, &[0x6007, 0xE0F2, 0x0000, 0xE09E]);
+        emu.press_second_key(7);
+
+        step(&mut emu, 3);
+
+        assert_eq!(emu.pc, 0x308);
+    }
+
+    #[test]
+    fn chip8e_5xy2_and_5xy3_should_transfer_a_register_range() {
+        let mut emu = a_chip8_with_program(Platform::Chip8E, &[0x6211, 0x6322, 0x6433, 0xA400, 0x5242, 0xA401, 0x5673]);
+
+        step(&mut emu, 7);
+
+        assert_eq!(&emu.memory()[0x400..0x403], &[0x11, 0x22, 0x33]);
+        assert_eq!((emu.v[6], emu.v[7]), (0x22, 0x33));
+        assert_eq!(emu.address_register, 0x403);
+    }
+
+    #[test]
+    fn chip8e_branches_should_be_relative_to_the_instruction() {
+        let mut emu = a_chip8_with_program(Platform::Chip8E, &[0xBF06, 0x0000, 0x0000, 0xBB04]);
+
+        step(&mut emu, 1);
+        assert_eq!(emu.pc, 0x206);
+        step(&mut emu, 1);
+        assert_eq!(emu.pc, 0x202);
+    }
+
+    #[test]
+    fn chip8e_fx1b_should_skip_vx_bytes() {
+        let mut emu = a_chip8_with_program(Platform::Chip8E, &[0x6003, 0xF01B]);
+
+        step(&mut emu, 2);
+
+        assert_eq!(emu.pc, 0x207);
+    }
+
+    #[test]
+    fn chip8e_fx4f_should_wait_for_vx_frames() {
+        let mut emu = a_chip8_with_program(Platform::Chip8E, &[0x6002, 0xF04F, 0x6101]);
+        emu.set_quirks(Default::default());
+
+        emu.run_frame(10);
+        assert_eq!((emu.pc, emu.timer_delay), (0x202, 1));
+        emu.run_frame(10);
+        assert_eq!((emu.pc, emu.timer_delay), (0x202, 0));
+        emu.run_frame(10);
+        assert_eq!(emu.v[1], 1);
+    }
+
+    #[test]
+    fn chip8e_00ed_should_stop_the_program() {
+        let mut emu = a_chip8_with_program(Platform::Chip8E, &[0x00ED, 0x6001]);
+
+        assert_eq!(emu.run_frame(10), 1);
+
+        assert!(!emu.is_playing());
+        assert_eq!((emu.pc, emu.v[0]), (0x200, 0));
+    }
+}
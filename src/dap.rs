@@ -0,0 +1,887 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::chip8::{BreakpointOwner, Chip8, Register};
+
+// The only thread reported to the IDE.
+const THREAD_ID: u64 = 1;
+
+// Variable references of the scopes of every stack frame.
+const REGISTERS_REFERENCE: u64 = 1;
+const STACK_REFERENCE: u64 = 2;
+
+#[derive(Deserialize)]
+struct DebugInfoFile {
+    lines: Vec<LineEntry>,
+    #[serde(default)]
+    symbols: BTreeMap<String, u16>,
+}
+
+#[derive(Deserialize)]
+struct LineEntry {
+    source: PathBuf,
+    line: u32,
+    address: u16,
+}
+
+/**
+Debug info emitted by an assembler: the source line every instruction was assembled from, and
+the names of addresses. It is read from JSON, with source paths relative to the file:
+
+```json
+{
+  "lines": [{ "source": "game.8o", "line": 12, "address": 512 }],
+  "symbols": { "main": 512 }
+}
+```
+ */
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct DebugInfo {
+    sources: Vec<PathBuf>,
+    // Source index and line of every instruction.
+    lines: BTreeMap<u16, (usize, u32)>,
+    // First address assembled from every source index and line.
+    addresses: BTreeMap<(usize, u32), u16>,
+    symbols: BTreeMap<u16, String>,
+}
+
+impl DebugInfo {
+    pub fn load(path: &Path) -> io::Result<DebugInfo> {
+        let json = fs::read_to_string(path)?;
+        DebugInfo::parse(&json, path.parent().unwrap_or(Path::new("")))
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    // Parses debug info whose source paths are relative to `base`.
+    pub fn parse(json: &str, base: &Path) -> Result<DebugInfo, serde_json::Error> {
+        let file: DebugInfoFile = serde_json::from_str(json)?;
+        let mut info = DebugInfo::default();
+        for entry in file.lines {
+            let path = base.join(&entry.source);
+            let source = match info.sources.iter().position(|source| *source == path) {
+                Some(source) => source,
+                None => {
+                    info.sources.push(path);
+                    info.sources.len() - 1
+                }
+            };
+            info.lines.insert(entry.address, (source, entry.line));
+            let address = info.addresses.entry((source, entry.line)).or_insert(entry.address);
+            *address = (*address).min(entry.address);
+        }
+        info.symbols = file.symbols.into_iter().map(|(name, address)| (address, name)).collect();
+        Ok(info)
+    }
+
+    // Source and line of the instruction at `address`.
+    pub fn line_at(&self, address: u16) -> Option<(&Path, u32)> {
+        self.lines.get(&address).map(|(source, line)| (self.sources[*source].as_path(), *line))
+    }
+
+    /**
+    Address of the first instruction assembled from `line` of `source`, or from the first line
+    after it that has code, like IDEs move breakpoints set on comments and blank lines. Returns
+    the line the address belongs to as well. Sources are matched by path, or by file name when
+    no path matches, since IDEs and assemblers often see them from different directories.
+     */
+    pub fn address_of(&self, source: &Path, line: u32) -> Option<(u32, u16)> {
+        let index = self.sources.iter().position(|path| path == source)
+            .or_else(|| self.sources.iter().position(|path| path.file_name().is_some() && path.file_name() == source.file_name()))?;
+        self.addresses.range((index, line)..(index + 1, 0)).next().map(|((_, line), address)| (*line, *address))
+    }
+
+    // Name of the symbol at or before `address`.
+    pub fn symbol_at(&self, address: u16) -> Option<&str> {
+        self.symbols.range(..=address).next_back().map(|(_, name)| name.as_str())
+    }
+
+    // Source and line of the instruction at or before `address`, for addresses inside an instruction.
+    fn location(&self, address: u16) -> Option<(&Path, u32)> {
+        self.lines.range(..=address).next_back().map(|(_, (source, line))| (self.sources[*source].as_path(), *line))
+    }
+}
+
+/**
+A Debug Adapter Protocol server, so IDEs like VS Code can debug the running `Chip8` by source
+line, using the debug info of the assembler given to `launch` or `attach` as `debugInfo`. It
+supports breakpoints by line, stepping over, into and out of calls by line or by instruction,
+pausing, a variables view of the registers and the stack, and reading and writing memory.
+One IDE is served at a time, over TCP, e.g. with `debugServer` in a VS Code launch configuration.
+
+It never blocks: `poll` handles requests every frame, and `run_frame` runs the emulator in place of
+`Chip8::run_frame` while an IDE is attached. Stepping checks every instruction of the frames it
+runs, so it goes through the same loop, timers and keypad as running, and always ends in the
+same place. Attaching pauses the emulator, and disconnecting removes the IDE's breakpoints and
+resumes the program.
+ */
+pub struct DapServer {
+    listener: TcpListener,
+    session: Option<Session>,
+}
+
+struct Session {
+    stream: TcpStream,
+    // Bytes received and not handled yet.
+    input: Vec<u8>,
+    seq: u64,
+    debug_info: DebugInfo,
+    stop_on_entry: bool,
+    // Addresses of the breakpoints of every source, as last set by the IDE.
+    breakpoints: BTreeMap<PathBuf, BTreeSet<u16>>,
+    state: State,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Stopped,
+    Running,
+    Stepping(Step),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum StepKind {
+    Over,
+    In,
+    Out,
+}
+
+// A step in progress, from the stack depth and source line it started at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Step {
+    kind: StepKind,
+    depth: usize,
+    // Source index and line, or None to step by instruction.
+    line: Option<(usize, u32)>,
+}
+
+impl Step {
+    // Whether the step is over once PC got where it is.
+    fn is_done(&self, chip8: &Chip8, debug_info: &DebugInfo) -> bool {
+        let depth = chip8.stack().len();
+        let left_line = match self.line {
+            Some(line) => debug_info.lines.get(&chip8.read_register(Register::Pc)).is_some_and(|at| *at != line),
+            None => true,
+        };
+        match self.kind {
+            StepKind::Over => depth <= self.depth && left_line,
+            StepKind::In => left_line,
+            StepKind::Out => depth < self.depth,
+        }
+    }
+}
+
+impl DapServer {
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<DapServer> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(DapServer { listener, session: None })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.session.is_some()
+    }
+
+    // Accepts an IDE, handles the requests it sent, and tells it when the program stopped.
+    pub fn poll(&mut self, chip8: &mut Chip8) {
+        if self.session.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => match Session::attach(stream, chip8) {
+                    Ok(session) => self.session = Some(session),
+                    Err(e) => println!("IDE could not attach: {}", e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => println!("IDE could not attach: {}", e),
+            }
+        }
+
+        let Some(session) = &mut self.session else { return };
+        match session.poll(chip8) {
+            Ok(true) => {}
+            Ok(false) => self.detach(chip8),
+            Err(e) => {
+                println!("IDE disconnected: {}", e);
+                self.detach(chip8);
+            }
+        }
+    }
+
+    /**
+    Runs one frame of `instructions` opcodes like `Chip8::run_frame`, stopping where the current
+    step ends, and tells the IDE if the program stopped. Returns the number of instructions executed.
+     */
+    pub fn run_frame(&mut self, chip8: &mut Chip8, instructions: u32) -> u32 {
+        let Some(session) = &mut self.session else { return chip8.run_frame(instructions) };
+        let executed = match session.state {
+            State::Stepping(step) => chip8.run_frame_until(instructions, |chip8| step.is_done(chip8, &session.debug_info)),
+            _ => chip8.run_frame(instructions),
+        };
+        if let Err(e) = session.report_stop(chip8) {
+            println!("IDE disconnected: {}", e);
+            self.detach(chip8);
+        }
+        executed
+    }
+
+    fn detach(&mut self, chip8: &mut Chip8) {
+        if self.session.take().is_some() {
+            chip8.clear_breakpoints(BreakpointOwner::Dap);
+            if chip8.fault().is_none() {
+                chip8.start();
+            }
+        }
+    }
+}
+
+impl Session {
+    fn attach(stream: TcpStream, chip8: &mut Chip8) -> io::Result<Session> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        chip8.pause();
+        Ok(Session {
+            stream,
+            input: Vec::new(),
+            seq: 0,
+            debug_info: DebugInfo::default(),
+            stop_on_entry: false,
+            breakpoints: BTreeMap::new(),
+            state: State::Stopped,
+        })
+    }
+
+    // Returns false once the IDE disconnected.
+    fn poll(&mut self, chip8: &mut Chip8) -> io::Result<bool> {
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(read) => self.input.extend_from_slice(&buffer[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        while let Some(message) = self.next_message()? {
+            if !self.handle(&message, chip8)? {
+                return Ok(false);
+            }
+        }
+        self.report_stop(chip8)?;
+        Ok(true)
+    }
+
+    // Takes the next complete message off the input, framed by a Content-Length header.
+    fn next_message(&mut self) -> io::Result<Option<Value>> {
+        let Some(header_end) = self.input.windows(4).position(|window| window == b"\r\n\r\n") else { return Ok(None) };
+        let header = String::from_utf8_lossy(&self.input[..header_end]).into_owned();
+        let length = header.lines()
+            .find_map(|line| line.strip_prefix("Content-Length:"))
+            .and_then(|length| length.trim().parse::<usize>().ok())
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "missing Content-Length"))?;
+        let body_start = header_end + 4;
+        if self.input.len() < body_start + length {
+            return Ok(None);
+        }
+        let body: Vec<u8> = self.input.drain(..body_start + length).skip(body_start).collect();
+        serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    // Tells the IDE once the program stopped running or stepping, or started running without it.
+    fn report_stop(&mut self, chip8: &mut Chip8) -> io::Result<()> {
+        let stopped = !chip8.is_playing() || chip8.fault().is_some();
+        match self.state {
+            State::Running | State::Stepping(_) if stopped => {
+                let reason = match (chip8.fault(), self.state) {
+                    (Some(_), _) => "exception",
+                    (None, _) if chip8.is_paused_at_breakpoint() => "breakpoint",
+                    (None, State::Stepping(_)) => "step",
+                    (None, _) => "pause",
+                };
+                self.stop(chip8, reason)
+            }
+            // The user resumed the program, e.g. from the frontend.
+            State::Stopped if !stopped => {
+                self.state = State::Running;
+                self.event("continued", json!({ "threadId": THREAD_ID, "allThreadsContinued": true }))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn stop(&mut self, chip8: &Chip8, reason: &str) -> io::Result<()> {
+        self.state = State::Stopped;
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(fault) = chip8.fault() {
+            body["text"] = json!(fault.to_string());
+        }
+        self.event("stopped", body)
+    }
+
+    // Handles a request and responds to it. Returns false when the IDE disconnected.
+    fn handle(&mut self, message: &Value, chip8: &mut Chip8) -> io::Result<bool> {
+        if message["type"] != "request" {
+            return Ok(true);
+        }
+        let command = message["command"].as_str().unwrap_or_default();
+        let arguments = &message["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSteppingGranularity": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsWriteMemoryRequest": true,
+            })),
+            "launch" | "attach" => self.configure(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments, chip8),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace(chip8)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+            ] })),
+            "variables" => Ok(self.variables(arguments, chip8)),
+            "setVariable" => set_variable(arguments, chip8),
+            "readMemory" => read_memory(arguments, chip8),
+            "writeMemory" => write_memory(arguments, chip8),
+            "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" | "pause" | "disconnect" => Ok(json!({})),
+            _ => Err(format!("{} is not supported", command)),
+        };
+        self.respond(message, command, result)?;
+
+        // Requests that make the program run or stop are handled once they were answered, so
+        // that the events they raise come after the responses, like IDEs expect.
+        let granularity_is_instruction = arguments["granularity"] == "instruction";
+        match command {
+            "initialize" => self.event("initialized", json!({}))?,
+            "configurationDone" if self.stop_on_entry => self.stop(chip8, "entry")?,
+            "configurationDone" | "continue" => self.resume(chip8)?,
+            "next" => self.step(chip8, StepKind::Over, granularity_is_instruction)?,
+            "stepIn" => self.step(chip8, StepKind::In, granularity_is_instruction)?,
+            "stepOut" => self.step(chip8, StepKind::Out, granularity_is_instruction)?,
+            "pause" => chip8.pause(),
+            "disconnect" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn configure(&mut self, arguments: &Value) -> Result<Value, String> {
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        if let Some(path) = arguments["debugInfo"].as_str() {
+            self.debug_info = DebugInfo::load(Path::new(path)).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        }
+        Ok(json!({}))
+    }
+
+    // Replaces the breakpoints of a source, moving them to the next line with code.
+    fn set_breakpoints(&mut self, arguments: &Value, chip8: &mut Chip8) -> Result<Value, String> {
+        let source = PathBuf::from(arguments["source"]["path"].as_str().ok_or("The source has no path")?);
+        let lines: Vec<u32> = match arguments["breakpoints"].as_array() {
+            Some(breakpoints) => breakpoints.iter().filter_map(|breakpoint| breakpoint["line"].as_u64()).map(|line| line as u32).collect(),
+            None => arguments["lines"].as_array().into_iter().flatten().filter_map(Value::as_u64).map(|line| line as u32).collect(),
+        };
+
+        let previous = self.breakpoints.remove(&source).unwrap_or_default();
+        let mut addresses = BTreeSet::new();
+        let breakpoints: Vec<Value> = lines.iter().map(|line| match self.debug_info.address_of(&source, *line) {
+            Some((line, address)) => {
+                addresses.insert(address);
+                json!({ "verified": true, "line": line, "instructionReference": format!("0x{:03X}", address) })
+            }
+            None => json!({ "verified": false, "line": line, "message": "No code was assembled from this line" }),
+        }).collect();
+        for address in &addresses {
+            chip8.add_breakpoint(*address, BreakpointOwner::Dap);
+        }
+        self.breakpoints.insert(source, addresses);
+        // Breakpoints still used by a source stay.
+        let used: BTreeSet<u16> = self.breakpoints.values().flatten().copied().collect();
+        for address in previous.difference(&used) {
+            chip8.remove_breakpoint(*address, BreakpointOwner::Dap);
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    // PC, then the calls that led to it, innermost first.
+    fn stack_trace(&self, chip8: &Chip8) -> Value {
+        let pc = chip8.read_register(Register::Pc);
+        // Return addresses follow the calls, which are 2 bytes long.
+        let calls = chip8.stack().iter().rev().map(|address| address.wrapping_sub(2));
+        let frames: Vec<Value> = std::iter::once(pc).chain(calls).enumerate().map(|(id, address)| {
+            let name = match self.debug_info.symbol_at(address) {
+                Some(symbol) => format!("{} ({:03X})", symbol, address),
+                None => format!("{:03X}", address),
+            };
+            let mut frame = json!({
+                "id": id,
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("0x{:03X}", address),
+            });
+            if let Some((source, line)) = self.debug_info.location(address) {
+                frame["source"] = json!({
+                    "name": source.file_name().map(|name| name.to_string_lossy()).unwrap_or_default(),
+                    "path": source.to_string_lossy(),
+                });
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+            }
+            frame
+        }).collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(&self, arguments: &Value, chip8: &Chip8) -> Value {
+        let variables: Vec<Value> = match arguments["variablesReference"].as_u64() {
+            Some(REGISTERS_REFERENCE) => Register::ALL.iter().map(|register| {
+                let value = chip8.read_register(*register);
+                let mut variable = json!({
+                    "name": register.name(),
+                    "value": format!("0x{:0width$X}", value, width = register.size() * 2),
+                    "variablesReference": 0,
+                });
+                if matches!(register, Register::I | Register::Pc) {
+                    variable["memoryReference"] = json!(format!("0x{:03X}", value));
+                }
+                variable
+            }).collect(),
+            Some(STACK_REFERENCE) => chip8.stack().iter().enumerate().map(|(index, address)| {
+                let value = match self.debug_info.symbol_at(address.wrapping_sub(2)) {
+                    Some(symbol) => format!("0x{:03X} ({})", address, symbol),
+                    None => format!("0x{:03X}", address),
+                };
+                json!({ "name": format!("[{}]", index), "value": value, "variablesReference": 0, "memoryReference": format!("0x{:03X}", address) })
+            }).collect(),
+            _ => Vec::new(),
+        };
+        json!({ "variables": variables })
+    }
+
+    // Lets the program run. A breakpoint at PC is stepped over first.
+    fn resume(&mut self, chip8: &mut Chip8) -> io::Result<()> {
+        if chip8.fault().is_some() {
+            return self.stop(chip8, "exception");
+        }
+        if chip8.has_breakpoint(chip8.read_register(Register::Pc)) && !chip8.is_paused_at_breakpoint() {
+            chip8.tick();
+        }
+        self.state = State::Running;
+        chip8.start();
+        Ok(())
+    }
+
+    /**
+    Executes the instruction at PC, then lets the program run until the step is over. Stepping
+    by line ends on the first instruction of another line with debug info, and by instruction
+    on the next instruction, in both cases once any call returned when stepping over.
+     */
+    fn step(&mut self, chip8: &mut Chip8, kind: StepKind, by_instruction: bool) -> io::Result<()> {
+        if chip8.fault().is_some() {
+            return self.stop(chip8, "exception");
+        }
+        let pc = chip8.read_register(Register::Pc);
+        let line = if by_instruction { None } else { self.debug_info.lines.get(&pc).copied() };
+        let step = Step { kind, depth: chip8.stack().len(), line };
+        chip8.tick();
+        if step.is_done(chip8, &self.debug_info) || chip8.fault().is_some() {
+            let reason = if chip8.fault().is_some() { "exception" } else { "step" };
+            return self.stop(chip8, reason);
+        }
+        self.state = State::Stepping(step);
+        chip8.start();
+        Ok(())
+    }
+
+    fn respond(&mut self, request: &Value, command: &str, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        let mut bytes = format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes();
+        // Waits for the socket when its buffer is full.
+        while !bytes.is_empty() {
+            match self.stream.write(&bytes) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    bytes.drain(..written);
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set_variable(arguments: &Value, chip8: &mut Chip8) -> Result<Value, String> {
+    let name = arguments["name"].as_str().unwrap_or_default();
+    let text = arguments["value"].as_str().unwrap_or_default();
+    let value = parse_number(text).ok_or_else(|| format!("{} is not a number", text))?;
+    match arguments["variablesReference"].as_u64() {
+        Some(REGISTERS_REFERENCE) => {
+            let register = Register::ALL.into_iter().find(|register| register.name() == name).ok_or_else(|| format!("No register {}", name))?;
+            chip8.write_register(register, value);
+            let value = chip8.read_register(register);
+            Ok(json!({ "value": format!("0x{:0width$X}", value, width = register.size() * 2) }))
+        }
+        Some(STACK_REFERENCE) => {
+            let index = name.trim_matches(|c| c == '[' || c == ']').parse::<usize>().ok()
+                .filter(|index| *index < chip8.stack().len())
+                .ok_or_else(|| format!("No stack entry {}", name))?;
+            chip8.write_stack(index, value);
+            Ok(json!({ "value": format!("0x{:03X}", chip8.stack()[index]) }))
+        }
+        _ => Err("Only registers and stack entries can be set".to_string()),
+    }
+}
+
+// Hexadecimal with a 0x prefix, or decimal.
+fn parse_number(text: &str) -> Option<u16> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Start of the memory range of a request, and its length limited to the end of memory.
+fn memory_range(arguments: &Value, chip8: &Chip8, count: usize) -> Result<(usize, usize), String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or_default();
+    let base = parse_number(reference).ok_or_else(|| format!("Invalid memory reference {}", reference))?;
+    let address = base as i64 + arguments["offset"].as_i64().unwrap_or(0);
+    let size = chip8.memory().len();
+    if address < 0 || address as usize >= size {
+        return Err(format!("{:X} is outside memory", address));
+    }
+    Ok((address as usize, count.min(size - address as usize)))
+}
+
+fn read_memory(arguments: &Value, chip8: &Chip8) -> Result<Value, String> {
+    let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+    let (address, length) = memory_range(arguments, chip8, count)?;
+    Ok(json!({
+        "address": format!("0x{:03X}", address),
+        "data": base64_encode(&chip8.memory()[address..address + length]),
+        "unreadableBytes": count - length,
+    }))
+}
+
+fn write_memory(arguments: &Value, chip8: &mut Chip8) -> Result<Value, String> {
+    let data = base64_decode(arguments["data"].as_str().unwrap_or_default()).ok_or("Invalid base64 data")?;
+    let (address, length) = memory_range(arguments, chip8, data.len())?;
+    for (offset, value) in data[..length].iter().enumerate() {
+        chip8.write_memory(address + offset, *value);
+    }
+    Ok(json!({ "bytesWritten": length }))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| bits | (*byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - 6 * index)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u32> = text.trim_end_matches('=').bytes()
+        .map(|byte| BASE64.iter().position(|digit| *digit == byte).map(|digit| digit as u32))
+        .collect::<Option<_>>()?;
+    let mut bytes = Vec::new();
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, digit)| bits | digit << (18 - 6 * index));
+        for index in 0..chunk.len() - 1 {
+            bytes.push((bits >> (16 - 8 * index)) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::thread;
+
+    // A made up game.8o and the debug info of assembling it:
+    //  1 : main
+    //  2   v0 := 1      200 6001
+    //  3   draw         202 2208
+    //  4   v1 := 2      204 6102
+    //  5 : loop
+    //  6   jump loop    206 1206
+    //  7
+    //  8 : draw
+    //  9   v2 := 3      208 6203
+    // 10   return       20A 00EE
+    const PROGRAM: [u16; 6] = [0x6001, 0x2208, 0x6102, 0x1206, 0x6203, 0x00EE];
+    const DEBUG_INFO: &str = r#"{
+        "lines": [
+            { "source": "game.8o", "line": 2, "address": 512 },
+            { "source": "game.8o", "line": 3, "address": 514 },
+            { "source": "game.8o", "line": 4, "address": 516 },
+            { "source": "game.8o", "line": 6, "address": 518 },
+            { "source": "game.8o", "line": 9, "address": 520 },
+            { "source": "game.8o", "line": 10, "address": 522 }
+        ],
+        "symbols": { "main": 512, "loop": 518, "draw": 520 }
+    }"#;
+
+    // A scripted IDE, talking to the server from another thread.
+    struct Ide {
+        stream: TcpStream,
+        input: Vec<u8>,
+        seq: u64,
+        // Events received while waiting for responses.
+        events: VecDeque<Value>,
+    }
+
+    impl Ide {
+        fn read_message(&mut self) -> Value {
+            loop {
+                if let Some(header_end) = self.input.windows(4).position(|window| window == b"\r\n\r\n") {
+                    let header = String::from_utf8_lossy(&self.input[..header_end]).into_owned();
+                    let length: usize = header.trim_start_matches("Content-Length: ").parse().unwrap();
+                    if self.input.len() >= header_end + 4 + length {
+                        let body: Vec<u8> = self.input.drain(..header_end + 4 + length).skip(header_end + 4).collect();
+                        return serde_json::from_slice(&body).unwrap();
+                    }
+                }
+                let mut buffer = [0; 4096];
+                let read = self.stream.read(&mut buffer).unwrap();
+                assert!(read > 0, "the server hung up");
+                self.input.extend_from_slice(&buffer[..read]);
+            }
+        }
+
+        // Sends a request and returns the body of its response, which must be successful.
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let body = json!({ "seq": self.seq, "type": "request", "command": command, "arguments": arguments }).to_string();
+            self.stream.write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes()).unwrap();
+            loop {
+                let message = self.read_message();
+                if message["type"] == "event" {
+                    self.events.push_back(message);
+                } else {
+                    assert_eq!(message["request_seq"], self.seq);
+                    assert_eq!(message["success"], true, "{}", message);
+                    return message["body"].clone();
+                }
+            }
+        }
+
+        fn event(&mut self, event: &str) -> Value {
+            let message = self.events.pop_front().unwrap_or_else(|| self.read_message());
+            assert_eq!(message["event"], event, "{}", message);
+            message["body"].clone()
+        }
+
+        // Source line of the top stack frame.
+        fn line(&mut self) -> Value {
+            self.request("stackTrace", json!({ "threadId": THREAD_ID }))["stackFrames"][0]["line"].clone()
+        }
+    }
+
+    // Runs PROGRAM under the server while `script` debugs it, then disconnects and returns the emulator.
+    fn debug(script: impl FnOnce(&mut Ide) + Send + 'static) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load(PROGRAM.iter().flat_map(|opcode| opcode.to_be_bytes()).collect());
+        debug_on(chip8, script)
+    }
+
+    // Like `debug`, on an emulator already set up with PROGRAM.
+    fn debug_on(mut chip8: Chip8, script: impl FnOnce(&mut Ide) + Send + 'static) -> Chip8 {
+        let mut server = DapServer::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let ide = thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            stream.set_nodelay(true).unwrap();
+            let mut ide = Ide { stream, input: Vec::new(), seq: 0, events: VecDeque::new() };
+            script(&mut ide);
+            ide.request("disconnect", json!({}));
+        });
+        while !ide.is_finished() {
+            server.poll(&mut chip8);
+            server.run_frame(&mut chip8, 10);
+            thread::yield_now();
+        }
+        if let Err(panic) = ide.join() {
+            std::panic::resume_unwind(panic);
+        }
+        server.poll(&mut chip8);
+        assert!(!server.is_attached());
+        chip8
+    }
+
+    fn debug_info_file() -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rusted-chip8-dap-{}.json", std::process::id()));
+        fs::write(&path, DEBUG_INFO).unwrap();
+        path
+    }
+
+    #[test]
+    fn breakpoints_should_move_to_the_next_line_with_code() {
+        let info = DebugInfo::parse(DEBUG_INFO, Path::new("/games")).unwrap();
+
+        assert_eq!(info.address_of(Path::new("/games/game.8o"), 5), Some((6, 0x206)));
+        assert_eq!(info.address_of(Path::new("/elsewhere/game.8o"), 3), Some((3, 0x202)));
+        assert_eq!(info.address_of(Path::new("/games/game.8o"), 11), None);
+        assert_eq!(info.line_at(0x208), Some((Path::new("/games/game.8o"), 9)));
+        assert_eq!(info.symbol_at(0x20A), Some("draw"));
+    }
+
+    #[test]
+    fn base64_should_round_trip() {
+        for bytes in [&[][..], &[0x60], &[0x60, 0x01], &[0x60, 0x01, 0x22], &[0x60, 0x01, 0x22, 0x08]] {
+            assert_eq!(base64_decode(&base64_encode(bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base64_encode(&[0x60, 0x01, 0x22, 0x08]), "YAEiCA==");
+    }
+
+    #[test]
+    fn a_session_should_stop_at_breakpoints_and_step_by_line() {
+        let path = debug_info_file();
+        let chip8 = debug(move |ide| {
+            assert_eq!(ide.request("initialize", json!({ "adapterID": "chip8" }))["supportsConfigurationDoneRequest"], true);
+            ide.event("initialized");
+            ide.request("launch", json!({ "debugInfo": path, "stopOnEntry": true }));
+            let breakpoints = ide.request("setBreakpoints", json!({
+                "source": { "path": "game.8o" },
+                "breakpoints": [{ "line": 3 }, { "line": 5 }, { "line": 12 }],
+            }));
+            assert_eq!(breakpoints["breakpoints"][0]["line"], 3);
+            assert_eq!(breakpoints["breakpoints"][1]["line"], 6);
+            assert_eq!(breakpoints["breakpoints"][2]["verified"], false);
+            ide.request("configurationDone", json!({}));
+            assert_eq!(ide.event("stopped")["reason"], "entry");
+            assert_eq!(ide.line(), 2);
+
+            ide.request("continue", json!({ "threadId": THREAD_ID }));
+            assert_eq!(ide.event("stopped")["reason"], "breakpoint");
+            assert_eq!(ide.line(), 3);
+
+            ide.request("stepIn", json!({ "threadId": THREAD_ID }));
+            assert_eq!(ide.event("stopped")["reason"], "step");
+            let frames = ide.request("stackTrace", json!({ "threadId": THREAD_ID }))["stackFrames"].clone();
+            assert_eq!((frames[0]["line"].clone(), frames[0]["name"].clone()), (json!(9), json!("draw (208)")));
+            assert_eq!((frames[1]["line"].clone(), frames[1]["name"].clone()), (json!(3), json!("main (202)")));
+
+            ide.request("stepOut", json!({ "threadId": THREAD_ID }));
+            assert_eq!(ide.event("stopped")["reason"], "step");
+            assert_eq!(ide.line(), 4);
+
+            ide.request("next", json!({ "threadId": THREAD_ID }));
+            assert_eq!(ide.event("stopped")["reason"], "step");
+            assert_eq!(ide.line(), 6);
+
+            let registers = ide.request("variables", json!({ "variablesReference": REGISTERS_REFERENCE }))["variables"].clone();
+            assert_eq!(registers[1], json!({ "name": "V1", "value": "0x02", "variablesReference": 0 }));
+            assert_eq!(registers[2]["value"], "0x03");
+            let memory = ide.request("readMemory", json!({ "memoryReference": "0x200", "offset": 2, "count": 2 }));
+            assert_eq!(memory, json!({ "address": "0x202", "data": "Igg=", "unreadableBytes": 0 }));
+        });
+
+        assert_eq!(chip8.breakpoints().next(), None);
+        assert!(chip8.is_playing());
+    }
+
+    #[test]
+    fn breakpoints_should_only_be_removed_once_nothing_else_uses_them() {
+        let path = debug_info_file();
+        let mut chip8 = Chip8::new();
+        chip8.load(PROGRAM.iter().flat_map(|opcode| opcode.to_be_bytes()).collect());
+        // Set by GDB before the IDE attached.
+        chip8.add_breakpoint(0x206, BreakpointOwner::Gdb);
+
+        let chip8 = debug_on(chip8, move |ide| {
+            ide.request("launch", json!({ "debugInfo": path, "stopOnEntry": true }));
+            ide.request("setBreakpoints", json!({ "source": { "path": "game.8o" }, "breakpoints": [{ "line": 3 }, { "line": 6 }] }));
+            ide.request("setBreakpoints", json!({ "source": { "path": "/elsewhere/game.8o" }, "breakpoints": [{ "line": 3 }] }));
+            ide.request("setBreakpoints", json!({ "source": { "path": "game.8o" }, "breakpoints": [] }));
+            ide.request("configurationDone", json!({}));
+            assert_eq!(ide.event("stopped")["reason"], "entry");
+
+            // The other source still breaks at 202.
+            ide.request("continue", json!({ "threadId": THREAD_ID }));
+            assert_eq!(ide.event("stopped")["reason"], "breakpoint");
+            assert_eq!(ide.line(), 3);
+        });
+
+        assert_eq!(chip8.breakpoints().collect::<Vec<_>>(), vec![0x206]);
+    }
+
+    #[test]
+    fn stepping_over_a_call_by_instruction_should_run_the_whole_call() {
+        debug(|ide| {
+            ide.request("launch", json!({ "stopOnEntry": true }));
+            ide.request("configurationDone", json!({}));
+            ide.event("stopped");
+
+            ide.request("next", json!({ "threadId": THREAD_ID, "granularity": "instruction" }));
+            ide.event("stopped");
+            ide.request("next", json!({ "threadId": THREAD_ID, "granularity": "instruction" }));
+            ide.event("stopped");
+
+            let frames = ide.request("stackTrace", json!({ "threadId": THREAD_ID }));
+            assert_eq!(frames["stackFrames"][0]["instructionPointerReference"], "0x204");
+            let set = ide.request("setVariable", json!({ "variablesReference": REGISTERS_REFERENCE, "name": "V2", "value": "0x2A" }));
+            assert_eq!(set["value"], "0x2A");
+            ide.request("writeMemory", json!({ "memoryReference": "0x300", "data": "YAEiCA==" }));
+            assert_eq!(ide.request("readMemory", json!({ "memoryReference": "0x2FF", "count": 3 }))["data"], "AGAB");
+        });
+    }
+
+    #[test]
+    fn faults_should_stop_with_an_exception() {
+        debug(|ide| {
+            ide.request("launch", json!({}));
+            ide.request("writeMemory", json!({ "memoryReference": "0x200", "data": "AO4=" }));
+            ide.request("configurationDone", json!({}));
+
+            let stopped = ide.event("stopped");
+            assert_eq!(stopped["reason"], "exception");
+            assert_eq!(stopped["text"], "stack underflow at 200");
+        });
+    }
+}
@@ -0,0 +1,340 @@
+use macroquad::color::{Color, BLACK, DARKGRAY, GREEN, LIGHTGRAY, RED, SKYBLUE, WHITE, YELLOW};
+use macroquad::input::{clear_input_queue, get_char_pressed, is_key_pressed, is_mouse_button_pressed, mouse_position, mouse_wheel, KeyCode, MouseButton};
+use macroquad::math::Rect;
+use macroquad::shapes::draw_rectangle;
+use macroquad::text::{draw_text, measure_text};
+use macroquad::window::screen_height;
+use rusted_chip8::chip8::{Chip8, Register};
+
+const FONT_SIZE_PX: u16 = 16;
+const LINE_HEIGHT: f32 = 18.0;
+const BYTES_PER_ROW: usize = 16;
+
+// Hex view below the CHIP-8 display, register panel to its right.
+const MEMORY_LEFT: f32 = 10.0;
+const MEMORY_TOP: f32 = 330.0;
+const MEMORY_ROWS: usize = 12;
+const REGISTERS_LEFT: f32 = 650.0;
+const REGISTERS_TOP: f32 = 10.0;
+
+// Frames a changed byte stays highlighted.
+const FLASH_FRAMES: u8 = 30;
+
+const FLASH_COLOR: Color = RED;
+const I_POINTER_COLOR: Color = Color::new(0.6, 0.5, 0.0, 1.0);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Selection {
+    Memory(usize),
+    Register(Register),
+    Stack(usize),
+}
+
+/**
+Hex view of the address space with a register panel next to it.
+
+The font region, the program region and the byte I points to are highlighted, and bytes that
+change while running flash for a moment. While the emulator is paused, memory, registers and
+stack entries can be selected with the mouse (memory also with the arrow keys) and edited by
+typing hex digits. Enter commits a partial value and Escape cancels.
+ */
+pub struct DebugOverlay {
+    scroll_row: usize,
+    selection: Option<Selection>,
+    input: String,
+    previous_memory: Vec<u8>,
+    flash: Vec<u8>,
+}
+
+impl DebugOverlay {
+    // An overlay scrolled to `address`, e.g. where the program was loaded.
+    pub fn new(address: u16) -> DebugOverlay {
+        DebugOverlay {
+            scroll_row: address as usize / BYTES_PER_ROW,
+            selection: None,
+            input: String::new(),
+            previous_memory: Vec::new(),
+            flash: Vec::new(),
+        }
+    }
+
+    // Flags the bytes that changed since the previous call. Call once per frame, after running.
+    pub fn track_changes(&mut self, emulator: &Chip8) {
+        let memory = emulator.memory();
+        self.flash.resize(memory.len(), 0);
+        for (address, value) in memory.iter().enumerate() {
+            if self.previous_memory.get(address).is_some_and(|previous| previous != value) {
+                self.flash[address] = FLASH_FRAMES;
+            } else {
+                self.flash[address] = self.flash[address].saturating_sub(1);
+            }
+        }
+        self.previous_memory = memory.to_vec();
+    }
+
+    pub fn update(&mut self, emulator: &mut Chip8) {
+        let rows = emulator.memory().len().div_ceil(BYTES_PER_ROW);
+        let max_scroll_row = rows.saturating_sub(MEMORY_ROWS);
+
+        if is_key_pressed(KeyCode::PageDown) {
+            self.scroll_row += MEMORY_ROWS;
+        }
+        if is_key_pressed(KeyCode::PageUp) {
+            self.scroll_row = self.scroll_row.saturating_sub(MEMORY_ROWS);
+        }
+        let (_, wheel) = mouse_wheel();
+        if wheel < 0.0 {
+            self.scroll_row += 1;
+        } else if wheel > 0.0 {
+            self.scroll_row = self.scroll_row.saturating_sub(1);
+        }
+        self.scroll_row = self.scroll_row.min(max_scroll_row);
+
+        if emulator.is_playing() {
+            self.selection = None;
+            self.input.clear();
+            clear_input_queue();
+            return;
+        }
+
+        if is_mouse_button_pressed(MouseButton::Left) {
+            let (x, y) = mouse_position();
+            self.selection = self.selection_at(x, y, emulator);
+            self.input.clear();
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            self.selection = None;
+            self.input.clear();
+        }
+
+        if let Some(Selection::Memory(address)) = self.selection {
+            let last_address = emulator.memory().len() - 1;
+            let moved = if is_key_pressed(KeyCode::Left) {
+                Some(address.saturating_sub(1))
+            } else if is_key_pressed(KeyCode::Right) {
+                Some((address + 1).min(last_address))
+            } else if is_key_pressed(KeyCode::Up) {
+                Some(address.saturating_sub(BYTES_PER_ROW))
+            } else if is_key_pressed(KeyCode::Down) {
+                Some((address + BYTES_PER_ROW).min(last_address))
+            } else {
+                None
+            };
+            if let Some(address) = moved {
+                self.select_memory(address);
+            }
+        }
+
+        self.handle_input(emulator);
+    }
+
+    pub fn draw(&self, emulator: &Chip8, program_size: usize) {
+        self.draw_memory(emulator, program_size);
+        self.draw_registers(emulator);
+    }
+
+    fn handle_input(&mut self, emulator: &mut Chip8) {
+        let mut typed = Vec::new();
+        while let Some(character) = get_char_pressed() {
+            typed.push(character);
+        }
+        // Characters are popped most recent first.
+        typed.reverse();
+
+        let selection = match self.selection {
+            Some(selection) => selection,
+            None => return,
+        };
+
+        for character in typed {
+            if character.is_ascii_hexdigit() {
+                self.input.push(character.to_ascii_uppercase());
+            }
+        }
+
+        let digits = match selection {
+            Selection::Memory(_) => 2,
+            Selection::Register(register) => register.size() * 2,
+            Selection::Stack(_) => 4,
+        };
+        let commit = self.input.len() >= digits || (is_key_pressed(KeyCode::Enter) && !self.input.is_empty());
+        if !commit {
+            return;
+        }
+
+        let value = u16::from_str_radix(&self.input[..self.input.len().min(digits)], 16).unwrap_or(0);
+        self.input.clear();
+        match selection {
+            Selection::Memory(address) => {
+                emulator.write_memory(address, value as u8);
+                let next = (address + 1).min(emulator.memory().len() - 1);
+                self.select_memory(next);
+            }
+            Selection::Register(register) => emulator.write_register(register, value),
+            Selection::Stack(index) => emulator.write_stack(index, value),
+        }
+    }
+
+    fn select_memory(&mut self, address: usize) {
+        self.selection = Some(Selection::Memory(address));
+        self.input.clear();
+
+        let row = address / BYTES_PER_ROW;
+        if row < self.scroll_row {
+            self.scroll_row = row;
+        } else if row >= self.scroll_row + MEMORY_ROWS {
+            self.scroll_row = row + 1 - MEMORY_ROWS;
+        }
+    }
+
+    fn selection_at(&self, x: f32, y: f32, emulator: &Chip8) -> Option<Selection> {
+        let memory_len = emulator.memory().len();
+        let first_address = self.scroll_row * BYTES_PER_ROW;
+        let last_address = (first_address + MEMORY_ROWS * BYTES_PER_ROW).min(memory_len);
+        if let Some(address) = (first_address..last_address).find(|address| self.memory_cell(*address).contains((x, y).into())) {
+            return Some(Selection::Memory(address));
+        }
+
+        if let Some(register) = Register::ALL.into_iter().find(|register| register_cell(*register).contains((x, y).into())) {
+            return Some(Selection::Register(register));
+        }
+
+        (0..emulator.stack().len())
+            .find(|index| stack_cell(*index).contains((x, y).into()))
+            .map(Selection::Stack)
+    }
+
+    // Area of the value of a byte in the hex view, which must be visible.
+    fn memory_cell(&self, address: usize) -> Rect {
+        let char_width = char_width();
+        let row = address / BYTES_PER_ROW - self.scroll_row;
+        let column = address % BYTES_PER_ROW;
+        // "0200: " prefix, then "XX " per byte.
+        let x = MEMORY_LEFT + (6 + column * 3) as f32 * char_width;
+        let y = MEMORY_TOP + row as f32 * LINE_HEIGHT;
+
+        Rect::new(x, y, 2.0 * char_width, LINE_HEIGHT)
+    }
+
+    fn draw_memory(&self, emulator: &Chip8, program_size: usize) {
+        let memory = emulator.memory();
+        let i_pointer = emulator.read_register(Register::I) as usize;
+        let font = emulator.font_range();
+        let program_start = emulator.load_address() as usize;
+        let program = program_start..program_start + program_size;
+
+        let height = MEMORY_ROWS as f32 * LINE_HEIGHT;
+        draw_rectangle(0.0, MEMORY_TOP, REGISTERS_LEFT - 10.0, height, BLACK);
+
+        for row in 0..MEMORY_ROWS {
+            let row_address = (self.scroll_row + row) * BYTES_PER_ROW;
+            if row_address >= memory.len() {
+                break;
+            }
+            let y = MEMORY_TOP + row as f32 * LINE_HEIGHT;
+            draw_text(&format!("{:04X}:", row_address), MEMORY_LEFT, y + LINE_HEIGHT - 4.0, FONT_SIZE_PX as f32, LIGHTGRAY);
+
+            let row_end = (row_address + BYTES_PER_ROW).min(memory.len());
+            for (address, value) in memory.iter().enumerate().take(row_end).skip(row_address) {
+                let cell = self.memory_cell(address);
+                let selected = self.selection == Some(Selection::Memory(address));
+
+                let background = if selected {
+                    Some(WHITE)
+                } else if self.flash.get(address).is_some_and(|frames| *frames > 0) {
+                    let mut color = FLASH_COLOR;
+                    color.a = self.flash[address] as f32 / FLASH_FRAMES as f32;
+                    Some(color)
+                } else if address == i_pointer {
+                    Some(I_POINTER_COLOR)
+                } else {
+                    None
+                };
+                if let Some(color) = background {
+                    draw_rectangle(cell.x, cell.y, cell.w, cell.h, color);
+                }
+
+                let foreground = if selected {
+                    BLACK
+                } else if font.contains(&address) {
+                    SKYBLUE
+                } else if program.contains(&address) {
+                    GREEN
+                } else {
+                    DARKGRAY
+                };
+                let text = if selected && !self.input.is_empty() {
+                    format!("{:_<2}", self.input)
+                } else {
+                    format!("{:02X}", value)
+                };
+                draw_text(&text, cell.x, cell.y + LINE_HEIGHT - 4.0, FONT_SIZE_PX as f32, foreground);
+            }
+        }
+    }
+
+    fn draw_registers(&self, emulator: &Chip8) {
+        draw_rectangle(REGISTERS_LEFT - 10.0, 0.0, 200.0, screen_height(), BLACK);
+
+        for register in Register::ALL {
+            let digits = register.size() * 2;
+            let value = format!("{:0width$X}", emulator.read_register(register), width = digits);
+            self.draw_value(&register.name(), &value, register_cell(register), Selection::Register(register));
+        }
+
+        draw_text("Stack", REGISTERS_LEFT, stack_cell(0).y - 4.0, FONT_SIZE_PX as f32, YELLOW);
+        for (index, address) in emulator.stack().iter().enumerate() {
+            let value = format!("{:04X}", address);
+            self.draw_value(&format!("{:X}", index), &value, stack_cell(index), Selection::Stack(index));
+        }
+    }
+
+    fn draw_value(&self, name: &str, value: &str, cell: Rect, selection: Selection) {
+        let baseline = cell.y + LINE_HEIGHT - 4.0;
+        let char_width = char_width();
+        draw_text(name, cell.x - 3.0 * char_width, baseline, FONT_SIZE_PX as f32, LIGHTGRAY);
+
+        if self.selection == Some(selection) {
+            draw_rectangle(cell.x, cell.y, cell.w, cell.h, WHITE);
+            let text = if self.input.is_empty() {
+                value.to_string()
+            } else {
+                format!("{:_<width$}", self.input, width = value.len())
+            };
+            draw_text(&text, cell.x, baseline, FONT_SIZE_PX as f32, BLACK);
+        } else {
+            draw_text(value, cell.x, baseline, FONT_SIZE_PX as f32, WHITE);
+        }
+    }
+}
+
+fn char_width() -> f32 {
+    measure_text("0", None, FONT_SIZE_PX, 1.0).width
+}
+
+// Area of the value of a register: V registers in two columns, then the others in one.
+fn register_cell(register: Register) -> Rect {
+    let char_width = char_width();
+    let (column, row) = match register {
+        Register::V(index) => (index / 8, index % 8),
+        Register::I => (0, 8),
+        Register::Pc => (0, 9),
+        Register::Sp => (0, 10),
+        Register::Dt => (0, 11),
+        Register::St => (0, 12),
+    };
+    let x = REGISTERS_LEFT + (3 + column * 9) as f32 * char_width;
+    let y = REGISTERS_TOP + row as f32 * LINE_HEIGHT;
+
+    Rect::new(x, y, (register.size() * 2) as f32 * char_width, LINE_HEIGHT)
+}
+
+// Area of the value of a stack entry, listed below the registers.
+fn stack_cell(index: usize) -> Rect {
+    let char_width = char_width();
+    let x = REGISTERS_LEFT + 3.0 * char_width;
+    let y = REGISTERS_TOP + (15 + index) as f32 * LINE_HEIGHT;
+
+    Rect::new(x, y, 4.0 * char_width, LINE_HEIGHT)
+}
@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crate::analysis::{Analysis, ByteKind};
+use crate::chip8::{InstructionSet, Platform, Profile};
+
+// Data bytes shown per line.
+const BYTES_PER_LINE: usize = 8;
+
+/**
+Disassembles `program` as loaded for `platform`, or at `program_address`, using the control-flow
+analysis to tell code from data and to name the addresses instructions refer to. Code the
+analysis couldn't reach is shown as data, since it can't be told apart from it.
+ */
+pub fn disassemble(program: &[u8], platform: Platform, program_address: Option<u16>) -> String {
+    listing(program, platform, program_address, None)
+}
+
+/**
+Disassembles `program` like `disassemble`, with how many times each instruction ran in `profile`
+in front of it and a summary of the coverage and of the instructions executed at the end.
+Instructions only reached through computed jumps are listed as code too once they ran.
+ */
+pub fn annotate(program: &[u8], platform: Platform, program_address: Option<u16>, profile: &Profile) -> String {
+    let mut listing = listing(program, platform, program_address, Some(profile));
+    let analysis = Analysis::new(program, platform, program_address);
+    let origin = program_address.unwrap_or(platform.load_address());
+    let in_program = |address: u16| (address.wrapping_sub(origin) as usize) < program.len();
+    let reachable: Vec<u16> = analysis.blocks().flat_map(|block| analysis.instructions(block)).collect();
+    let covered = reachable.iter().filter(|address| profile.executions(**address) > 0).count();
+    let outside = profile.executed().filter(|(address, _)| !in_program(*address)).count();
+
+    let _ = writeln!(listing, "\n; {} of {} reachable instructions executed", covered, reachable.len());
+    if outside > 0 {
+        let _ = writeln!(listing, "; {} addresses executed outside the program", outside);
+    }
+    let _ = writeln!(listing, "; Instructions executed by kind:");
+    for (pattern, count) in profile.histogram() {
+        let _ = writeln!(listing, ";   {:<5} {}", pattern, count);
+    }
+    listing
+}
+
+fn listing(program: &[u8], platform: Platform, program_address: Option<u16>, profile: Option<&Profile>) -> String {
+    let analysis = Analysis::new(program, platform, program_address);
+    let labels = analysis.labels();
+    let set = platform.instruction_set();
+    let origin = program_address.unwrap_or(platform.load_address()) as usize % platform.memory_size();
+    let end = (origin + program.len()).min(platform.memory_size());
+
+    let mut listing = String::new();
+    let mut address = origin;
+    while address < end {
+        if let Some(label) = labels.get(&(address as u16)) {
+            let _ = writeln!(listing, "{}:", label);
+        }
+        let executions = profile.map(|profile| profile.executions(address as u16));
+        // Counts are right-aligned in a column of their own, and only appear when profiling.
+        let count = match executions {
+            Some(0) => format!("{:>10}", "-"),
+            Some(executions) => format!("{:>10}", executions),
+            None => String::new(),
+        };
+        if analysis.is_instruction(address as u16) || executions.is_some_and(|executions| executions > 0) {
+            let opcode = analysis.opcode_at(address as u16);
+            let size = set.instruction_size(opcode) as usize;
+            let text = if size == 4 {
+                format!("LD I, {}", target(analysis.opcode_at((address as u16).wrapping_add(2)), &labels))
+            } else {
+                mnemonic(opcode, set, &labels)
+            };
+            let _ = writeln!(listing, "{}    {:03X}  {:<9} {}", count, address, hex(&program[address - origin..(address + size).min(end) - origin]), text);
+            address += size;
+        } else {
+            // Runs of data stop at the next label or instruction.
+            let kind = analysis.kind(address as u16);
+            let mut length = 1;
+            while length < BYTES_PER_LINE
+                && address + length < end
+                && !labels.contains_key(&((address + length) as u16))
+                && !analysis.is_instruction((address + length) as u16)
+                && profile.is_none_or(|profile| profile.executions((address + length) as u16) == 0)
+                && analysis.kind((address + length) as u16) == kind
+            {
+                length += 1;
+            }
+            let bytes = &program[address - origin..address - origin + length];
+            let comment = match kind {
+                ByteKind::Sprite => format!("  ; {}", bytes.iter().map(|byte| format!("{:08b}", byte).replace('0', ".").replace('1', "#")).collect::<Vec<_>>().join(" ")),
+                _ => String::new(),
+            };
+            let padding = if profile.is_some() { " ".repeat(10) } else { String::new() };
+            let _ = writeln!(listing, "{}    {:03X}  DB {}{}", padding, address, bytes.iter().map(|byte| format!("#{:02X}", byte)).collect::<Vec<_>>().join(", "), comment);
+            address += length;
+        }
+    }
+    listing
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn target(address: u16, labels: &BTreeMap<u16, String>) -> String {
+    labels.get(&address).cloned().unwrap_or_else(|| format!("#{:03X}", address))
+}
+
+/**
+The classic mnemonic of `opcode`, naming addresses from `labels` where it has one. Opcodes the
+instruction set doesn't know are shown as a data word.
+ */
+pub fn mnemonic(opcode: u16, set: InstructionSet, labels: &BTreeMap<u16, String>) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    let variant = match set {
+        InstructionSet::Chip8 => None,
+        InstructionSet::HiResChip8 => match opcode {
+            0x0230 => Some("CLS".to_string()),
+            _ => None,
+        },
+        InstructionSet::Chip8X => match opcode & 0xF000 {
+            0x0000 if opcode == 0x02A0 => Some("BGCOL".to_string()),
+            0x5000 if n == 1 => Some(format!("ADD V{:X}, V{:X}, nibbles", x, y)),
+            0xB000 => Some(format!("COL V{:X}, V{:X}, {}", x, y, n)),
+            0xE000 if nn == 0xF2 => Some(format!("SKP2 V{:X}", x)),
+            0xE000 if nn == 0xF5 => Some(format!("SKNP2 V{:X}", x)),
+            0xF000 if nn == 0xF8 => Some(format!("OUT V{:X}", x)),
+            0xF000 if nn == 0xFB => Some(format!("IN V{:X}", x)),
+            _ => None,
+        },
+        InstructionSet::Chip8E => match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00ED => Some("STOP".to_string()),
+                0x00F2 => Some("NOP".to_string()),
+                0x0151 => Some("WAIT DT".to_string()),
+                0x0188 => Some("SKIP".to_string()),
+                _ => None,
+            },
+            0x5000 if n == 1 => Some(format!("SGT V{:X}, V{:X}", x, y)),
+            0x5000 if n == 2 => Some(format!("LD [I], V{:X}-V{:X}", x, y)),
+            0x5000 if n == 3 => Some(format!("LD V{:X}-V{:X}, [I]", x, y)),
+            0xB000 if x == 0xB => Some(format!("JP -{}", nn)),
+            0xB000 if x == 0xF => Some(format!("JP +{}", nn)),
+            0xF000 => match nn {
+                0x03 => Some(format!("OUT V{:X}", x)),
+                0x1B => Some(format!("SKIP V{:X}", x)),
+                0x4F => Some(format!("WAIT V{:X}", x)),
+                0xE3 | 0xE7 => Some(format!("IN V{:X}", x)),
+                _ => None,
+            },
+            _ => None,
+        },
+        InstructionSet::SuperChip | InstructionSet::XoChip => match opcode & 0xF000 {
+            0x0000 if opcode == 0x00FF => Some("HIGH".to_string()),
+            0xF000 if nn == 0x30 => Some(format!("LD HF, V{:X}", x)),
+            _ => None,
+        },
+    };
+    if let Some(text) = variant {
+        return text;
+    }
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            _ => format!("SYS #{:03X}", nnn),
+        },
+        0x1000 => format!("JP {}", target(nnn, labels)),
+        0x2000 => format!("CALL {}", target(nnn, labels)),
+        0x3000 => format!("SE V{:X}, #{:02X}", x, nn),
+        0x4000 => format!("SNE V{:X}, #{:02X}", x, nn),
+        0x5000 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, #{:02X}", x, nn),
+        0x7000 => format!("ADD V{:X}, #{:02X}", x, nn),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => format!("DW #{:04X}", opcode),
+        },
+        0x9000 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, {}", target(nnn, labels)),
+        0xB000 => format!("JP V0, {}", target(nnn, labels)),
+        0xC000 => format!("RND V{:X}, #{:02X}", x, nn),
+        0xD000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 if nn == 0x9E => format!("SKP V{:X}", x),
+        0xE000 if nn == 0xA1 => format!("SKNP V{:X}", x),
+        0xF000 => match nn {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => format!("DW #{:04X}", opcode),
+        },
+        _ => format!("DW #{:04X}", opcode),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    fn bytes(program: &[u16]) -> Vec<u8> {
+        program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn mnemonics_should_name_operands_and_labels() {
+        let labels = BTreeMap::from([(0x20A, "sub_20A".to_string())]);
+
+        assert_eq!(mnemonic(0x220A, InstructionSet::Chip8, &labels), "CALL sub_20A");
+        assert_eq!(mnemonic(0x1300, InstructionSet::Chip8, &labels), "JP #300");
+        assert_eq!(mnemonic(0x8AB4, InstructionSet::Chip8, &labels), "ADD VA, VB");
+        assert_eq!(mnemonic(0xF165, InstructionSet::Chip8, &labels), "LD V1, [I]");
+        assert_eq!(mnemonic(0x8AB9, InstructionSet::Chip8, &labels), "DW #8AB9");
+        assert_eq!(mnemonic(0x00ED, InstructionSet::Chip8E, &labels), "STOP");
+        assert_eq!(mnemonic(0xB123, InstructionSet::Chip8X, &labels), "COL V1, V2, 3");
+        assert_eq!(mnemonic(0xF230, InstructionSet::SuperChip, &labels), "LD HF, V2");
+        assert_eq!(mnemonic(0xF230, InstructionSet::Chip8, &labels), "DW #F230");
+        assert_eq!(mnemonic(0x00FF, InstructionSet::Chip8, &labels), "SYS #0FF");
+    }
+
+    #[test]
+    fn f000_nnnn_should_only_be_a_4_byte_instruction_on_xo_chip() {
+        let program = bytes(&[0xF000, 0x0300, 0x1204]);
+
+        assert!(disassemble(&program, Platform::XoChip, None).contains("    200  F0000300  LD I, #300\n"));
+        assert!(disassemble(&program, Platform::ModernChip8, None).contains("    200  F000      DW #F000\n    202  0300      SYS #300\n"));
+    }
+
+    #[test]
+    fn f000_nnnn_should_read_its_address_past_the_wrap_at_the_top_of_xo_chip_memory() {
+        let listing = disassemble(&bytes(&[0x00E0, 0xF000]), Platform::XoChip, Some(0xFFFC));
+
+        assert_eq!(listing, "main:\n    FFFC  00E0      CLS\n    FFFE  F000      LD I, #000\n");
+    }
+
+    #[test]
+    fn disassembly_should_label_code_and_show_sprites_as_data() {
+        let listing = disassemble(&bytes(&[0xA206, 0xD011, 0x1204, 0x8142]), Platform::ModernChip8, None);
+
+        assert_eq!(listing, [
+            "main:",
+            "    200  A206      LD I, sprite_206",
+            "    202  D011      DRW V0, V1, 1",
+            "label_204:",
+            "    204  1204      JP label_204",
+            "sprite_206:",
+            "    206  DB #81  ; #......#",
+            "    207  DB #42",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn annotated_disassembly_should_count_executions_and_summarise_coverage() {
+        // Jumps over a dead instruction through BNNN, into code the analysis can't see.
+        let program = bytes(&[0x6002, 0xB204, 0x6001, 0x1206]);
+        let mut emu = Chip8::new();
+        emu.enable_profiling();
+        emu.load(program.clone());
+        emu.start();
+        emu.run_frame(4);
+
+        let listing = annotate(&program, Platform::ModernChip8, None, emu.profile().unwrap());
+
+        assert_eq!(listing, [
+            "main:",
+            "         1    200  6002      LD V0, #02",
+            "         1    202  B204      JP V0, #204",
+            "              204  DB #60, #01",
+            "         2    206  1206      JP #206",
+            "",
+            "; 2 of 2 reachable instructions executed",
+            "; Instructions executed by kind:",
+            ";   1NNN  2",
+            ";   6XNN  1",
+            ";   BNNN  1",
+            "",
+        ].join("\n"));
+    }
+}
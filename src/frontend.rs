@@ -0,0 +1,763 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+use macroquad::color::{BLACK, GRAY, WHITE, YELLOW};
+use macroquad::input::{clear_input_queue, is_key_down, is_key_pressed, KeyCode};
+use macroquad::miniquad::window::{dropped_file_count, dropped_file_path};
+use macroquad::text::draw_text;
+use macroquad::window::{clear_background, screen_height};
+use rusted_chip8::analysis::Analysis;
+use rusted_chip8::disassembler;
+use rusted_chip8::dap::DapServer;
+use rusted_chip8::gdb::GdbStub;
+use rusted_chip8::chip8::{Chip8, Diagnostic, Font, MemoryFill, Platform, Register, DEFAULT_FONT_ADDRESS};
+use rusted_chip8::rom_database::{RomDatabase, RomMetadata};
+use rusted_chip8::rom_loader::{self, LoadError, Rom};
+use crate::debug_overlay::DebugOverlay;
+use crate::rom_browser::RomBrowser;
+use crate::rom_watcher::RomWatcher;
+
+pub const ROMS_DIRECTORY: &str = "roms";
+
+// Instructions executed per 60Hz frame at 1x speed, unless the ROM database recommends otherwise.
+pub const DEFAULT_TICKRATE: u32 = 10;
+
+// Wall clock time the unthrottled mode may spend emulating in a single frame.
+const FAST_FORWARD_FRAME_BUDGET: f64 = 0.8 / 60.0;
+
+// Emulated frames stepping over a call may take before giving up on it returning.
+const STEP_OVER_FRAME_LIMIT: u32 = 600;
+
+const STATUS_FONT_SIZE: f32 = 20.0;
+const INFO_TOP: f32 = 350.0;
+const INFO_LINE_HEIGHT: f32 = 22.0;
+const INFO_WRAP_WIDTH: usize = 70;
+
+/**
+Default mapping from the host keyboard to the CHIP-8 hex keypad:
+
+    Keyboard     Keypad
+    1 2 3 4      1 2 3 C
+    Q W E R      4 5 6 D
+    A S D F      7 8 9 E
+    Z X C V      A 0 B F
+ */
+const KEY_MAP: [(KeyCode, usize); 16] = [
+    (KeyCode::X, 0x0),
+    (KeyCode::Key1, 0x1),
+    (KeyCode::Key2, 0x2),
+    (KeyCode::Key3, 0x3),
+    (KeyCode::Q, 0x4),
+    (KeyCode::W, 0x5),
+    (KeyCode::E, 0x6),
+    (KeyCode::A, 0x7),
+    (KeyCode::S, 0x8),
+    (KeyCode::D, 0x9),
+    (KeyCode::Z, 0xA),
+    (KeyCode::C, 0xB),
+    (KeyCode::Key4, 0xC),
+    (KeyCode::R, 0xD),
+    (KeyCode::F, 0xE),
+    (KeyCode::V, 0xF),
+];
+
+/**
+Mapping from the numeric keypad to CHIP-8X's second hex keypad, laid out like KEY_MAP:
+
+    Numpad       Keypad
+    7 8 9 /      1 2 3 C
+    4 5 6 *      4 5 6 D
+    1 2 3 -      7 8 9 E
+    0 . + Enter  A 0 B F
+ */
+const SECOND_KEY_MAP: [(KeyCode, usize); 16] = [
+    (KeyCode::KpDecimal, 0x0),
+    (KeyCode::Kp7, 0x1),
+    (KeyCode::Kp8, 0x2),
+    (KeyCode::Kp9, 0x3),
+    (KeyCode::Kp4, 0x4),
+    (KeyCode::Kp5, 0x5),
+    (KeyCode::Kp6, 0x6),
+    (KeyCode::Kp1, 0x7),
+    (KeyCode::Kp2, 0x8),
+    (KeyCode::Kp3, 0x9),
+    (KeyCode::Kp0, 0xA),
+    (KeyCode::KpAdd, 0xB),
+    (KeyCode::KpDivide, 0xC),
+    (KeyCode::KpMultiply, 0xD),
+    (KeyCode::KpSubtract, 0xE),
+    (KeyCode::KpEnter, 0xF),
+];
+
+/**
+Host keys for the logical inputs of the ROM database.
+A ROM's `keys` entry maps them to the CHIP-8 keys the program reads.
+ */
+const ROM_INPUT_MAP: [(&str, KeyCode); 6] = [
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("a", KeyCode::Space),
+    ("b", KeyCode::Enter),
+];
+
+// Hotkeys. None of them overlap with KEY_MAP, SECOND_KEY_MAP or ROM_INPUT_MAP.
+const KEY_BROWSER: KeyCode = KeyCode::O;
+const KEY_DEBUG_OVERLAY: KeyCode = KeyCode::F2;
+const KEY_CLOSE_BROWSER: KeyCode = KeyCode::Escape;
+const KEY_PAUSE: KeyCode = KeyCode::P;
+const KEY_RESET: KeyCode = KeyCode::F5;
+const KEY_FRAME_ADVANCE: KeyCode = KeyCode::F6;
+const KEY_STEP: KeyCode = KeyCode::F7;
+const KEY_STEP_OVER: KeyCode = KeyCode::F8;
+const KEY_WRITE_PROFILE: KeyCode = KeyCode::F9;
+const KEY_SLOWER: KeyCode = KeyCode::Minus;
+const KEY_FASTER: KeyCode = KeyCode::Equal;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Speed {
+    Quarter,
+    Half,
+    Normal,
+    Double,
+    Quadruple,
+    Octuple,
+    Unthrottled,
+}
+
+impl Speed {
+    const PRESETS: [Speed; 7] = [
+        Speed::Quarter,
+        Speed::Half,
+        Speed::Normal,
+        Speed::Double,
+        Speed::Quadruple,
+        Speed::Octuple,
+        Speed::Unthrottled,
+    ];
+
+    // Emulated frames per real frame. None means as many as the host can run.
+    pub fn multiplier(&self) -> Option<f64> {
+        match self {
+            Speed::Quarter => Some(0.25),
+            Speed::Half => Some(0.5),
+            Speed::Normal => Some(1.0),
+            Speed::Double => Some(2.0),
+            Speed::Quadruple => Some(4.0),
+            Speed::Octuple => Some(8.0),
+            Speed::Unthrottled => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Speed::Quarter => "0.25x",
+            Speed::Half => "0.5x",
+            Speed::Normal => "1x",
+            Speed::Double => "2x",
+            Speed::Quadruple => "4x",
+            Speed::Octuple => "8x",
+            Speed::Unthrottled => "fast-forward",
+        }
+    }
+
+    pub fn faster(&self) -> Speed {
+        let index = self.index();
+        Speed::PRESETS[(index + 1).min(Speed::PRESETS.len() - 1)]
+    }
+
+    pub fn slower(&self) -> Speed {
+        let index = self.index();
+        Speed::PRESETS[index.saturating_sub(1)]
+    }
+
+    fn index(&self) -> usize {
+        Speed::PRESETS.iter().position(|speed| speed == self).unwrap()
+    }
+}
+
+// Counts executed instructions and publishes the total once per second.
+struct IpsCounter {
+    window_start: Instant,
+    instructions: u32,
+    ips: u32,
+}
+
+impl IpsCounter {
+    fn new() -> Self {
+        IpsCounter {
+            window_start: Instant::now(),
+            instructions: 0,
+            ips: 0,
+        }
+    }
+
+    fn add(&mut self, instructions: u32) {
+        self.instructions += instructions;
+
+        let elapsed = self.window_start.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            self.ips = (self.instructions as f64 / elapsed) as u32;
+            self.instructions = 0;
+            self.window_start = Instant::now();
+        }
+    }
+}
+
+pub struct Frontend {
+    emulator: Chip8,
+    database: RomDatabase,
+    browser: Option<RomBrowser>,
+    debug_overlay: Option<DebugOverlay>,
+    rom: Vec<u8>,
+    // Reloads the ROM when its file changes on disk.
+    rom_watcher: Option<RomWatcher>,
+    // Files of the last drag and drop, to tell new drops apart from the previous one.
+    dropped_files: Vec<PathBuf>,
+    rom_metadata: Option<RomMetadata>,
+    // Keys from the ROM database for the loaded ROM, in addition to KEY_MAP.
+    rom_key_map: Vec<(KeyCode, usize)>,
+    tickrate: u32,
+    speed: Speed,
+    // Fraction of an emulated frame carried over between real frames, used by speeds below 1x.
+    frame_budget: f64,
+    ips: IpsCounter,
+    // Font chosen by the user. Otherwise the font of the ROM's platform is used.
+    font: Option<Font>,
+    font_address: Option<u16>,
+    // Platform chosen by the user. Otherwise the one of the ROM is used.
+    platform: Option<Platform>,
+    // Program address chosen by the user. Otherwise the one of the ROM or its platform is used.
+    program_address: Option<u16>,
+    // Diagnostics of the running program, when linting, and how many arrived since it was loaded.
+    diagnostics: Option<Receiver<Diagnostic>>,
+    diagnostic_count: usize,
+    // Control flow of the loaded ROM, to step over its calls.
+    analysis: Option<Analysis>,
+    // Debugger server, which runs and stops the emulator while one is attached.
+    gdb: Option<GdbStub>,
+    // Debug adapter server, which runs the emulator frame by frame while an IDE is attached.
+    dap: Option<DapServer>,
+}
+
+impl Frontend {
+    pub fn new(database: RomDatabase) -> Frontend {
+        let mut emulator = Chip8::new();
+        emulator.set_decode_cache(true);
+        #[cfg(feature = "jit")]
+        if let Err(e) = emulator.set_jit(true) {
+            println!("{}, interpreting instead", e);
+        }
+
+        Frontend {
+            emulator,
+            database,
+            browser: None,
+            debug_overlay: None,
+            rom: Vec::new(),
+            rom_watcher: None,
+            dropped_files: Vec::new(),
+            rom_metadata: None,
+            rom_key_map: Vec::new(),
+            tickrate: DEFAULT_TICKRATE,
+            speed: Speed::Normal,
+            frame_budget: 0.0,
+            ips: IpsCounter::new(),
+            font: None,
+            font_address: None,
+            platform: None,
+            program_address: None,
+            diagnostics: None,
+            diagnostic_count: 0,
+            analysis: None,
+            gdb: None,
+            dap: None,
+        }
+    }
+
+    // Font and font address applied to the ROMs loaded from now on.
+    pub fn set_font(&mut self, font: Option<Font>, address: Option<u16>) {
+        self.font = font;
+        self.font_address = address;
+    }
+
+    // Platform the ROMs loaded from now on run on, instead of the one they are meant for.
+    pub fn set_platform(&mut self, platform: Option<Platform>) {
+        self.platform = platform;
+    }
+
+    // Address the ROMs loaded from now on are loaded and started at.
+    pub fn set_program_address(&mut self, address: Option<u16>) {
+        self.program_address = address;
+    }
+
+    // What the emulator memory holds before a ROM is loaded into it.
+    pub fn set_memory_fill(&mut self, fill: MemoryFill) {
+        self.emulator.set_memory_fill(fill);
+    }
+
+    // Reports likely bugs of the running program on the console, see `Chip8::enable_lints`.
+    pub fn enable_lints(&mut self) {
+        self.diagnostics = Some(self.emulator.enable_lints());
+    }
+
+    // Counts what the running program executes, see `Chip8::enable_profiling`.
+    pub fn enable_profiling(&mut self) {
+        self.emulator.enable_profiling();
+    }
+
+    // Lets GDB attach to the emulator on `port` of the local host, see `GdbStub`.
+    pub fn listen_gdb(&mut self, port: u16) -> io::Result<()> {
+        let gdb = GdbStub::bind(("127.0.0.1", port))?;
+        println!("Waiting for GDB on {}", gdb.local_addr()?);
+        self.gdb = Some(gdb);
+        Ok(())
+    }
+
+    // Lets an IDE attach to the emulator on `port` of the local host, see `DapServer`.
+    pub fn listen_dap(&mut self, port: u16) -> io::Result<()> {
+        let dap = DapServer::bind(("127.0.0.1", port))?;
+        println!("Waiting for an IDE on {}", dap.local_addr()?);
+        self.dap = Some(dap);
+        Ok(())
+    }
+
+    // Loads and starts a ROM file, which is then watched to reload it when it changes.
+    pub fn load_rom(&mut self, path: &Path) -> Result<(), LoadError> {
+        let rom = rom_loader::load_file(path)?;
+        println!("Rom opened: {} len {}", path.display(), rom.program.len());
+
+        self.load_program(rom)?;
+        self.rom_watcher = Some(RomWatcher::new(path));
+
+        Ok(())
+    }
+
+    /**
+    Loads and starts a program. If the ROM database knows it, its recommended quirks,
+    tickrate and key map are applied. Otherwise the settings of the ROM container are used,
+    falling back to the defaults. A platform chosen by the user takes precedence over both.
+    Programs starting past the end of the platform memory are refused.
+     */
+    fn load_program(&mut self, rom: Rom) -> Result<(), LoadError> {
+        self.rom_metadata = self.database.lookup(&rom.program);
+        if let (Some(platform), Some(metadata)) = (self.platform, &mut self.rom_metadata) {
+            metadata.platform = platform;
+            metadata.quirks = platform.quirks();
+        }
+        let platform = self.rom_metadata.as_ref().map(|metadata| metadata.platform).or(self.platform).or(rom.platform).unwrap_or_default();
+        self.emulator.set_platform(platform);
+        self.emulator.set_program_address(self.program_address.or(rom.start_address)).map_err(LoadError::AddressOutOfRange)?;
+        self.emulator.set_font_address(self.font_address.unwrap_or(DEFAULT_FONT_ADDRESS));
+        self.emulator.set_font(self.font.clone().unwrap_or(Font::builtin(platform.font_style())));
+        match &self.rom_metadata {
+            Some(metadata) => {
+                self.emulator.set_quirks(metadata.quirks);
+                self.tickrate = metadata.tickrate;
+                self.rom_key_map = ROM_INPUT_MAP
+                    .iter()
+                    .filter_map(|(input, key_code)| {
+                        metadata.keys.get(*input).map(|key| (*key_code, *key as usize & 0xF))
+                    })
+                    .collect();
+            }
+            None => {
+                let rom_platform = self.platform.or(rom.platform);
+                let platform_quirks = rom_platform.map(|platform| platform.quirks());
+                let platform_tickrate = rom_platform.map(|platform| platform.default_tickrate());
+                self.emulator.set_quirks(rom.quirks.or(platform_quirks).unwrap_or_default());
+                self.tickrate = rom.tickrate.or(platform_tickrate).unwrap_or(DEFAULT_TICKRATE);
+                self.rom_key_map = Vec::new();
+            }
+        }
+
+        self.rom = rom.program;
+        self.analysis = Some(Analysis::new(&self.rom, platform, self.program_address.or(rom.start_address)));
+        self.emulator.load(self.rom.clone());
+        self.emulator.start();
+        self.frame_budget = 0.0;
+        self.diagnostic_count = 0;
+        Ok(())
+    }
+
+    pub fn open_browser(&mut self, directory: &Path) -> io::Result<()> {
+        self.browser = Some(RomBrowser::open(directory, &self.database)?);
+        Ok(())
+    }
+
+    // Handles input and runs the emulator for one host frame.
+    pub fn update(&mut self) {
+        self.handle_dropped_files();
+        self.reload_changed_rom();
+
+        if self.browser.is_some() {
+            self.update_browser();
+            return;
+        }
+
+        if is_key_pressed(KEY_BROWSER) {
+            if let Err(e) = self.open_browser(Path::new(ROMS_DIRECTORY)) {
+                println!("Could not open {}: {}", ROMS_DIRECTORY, e);
+            }
+            return;
+        }
+
+        if self.rom.is_empty() {
+            return;
+        }
+
+        if is_key_pressed(KEY_DEBUG_OVERLAY) {
+            self.debug_overlay = match self.debug_overlay {
+                Some(_) => None,
+                None => Some(DebugOverlay::new(self.emulator.load_address())),
+            };
+        }
+
+        match &mut self.debug_overlay {
+            Some(overlay) => overlay.update(&mut self.emulator),
+            None => clear_input_queue(),
+        }
+
+        self.handle_hotkeys();
+        self.update_keypad();
+        if let Some(gdb) = &mut self.gdb {
+            gdb.poll(&mut self.emulator);
+        }
+        if let Some(dap) = &mut self.dap {
+            dap.poll(&mut self.emulator);
+        }
+
+        // An attached IDE runs one frame at a time, to stop exactly where its steps end.
+        let executed = match &mut self.dap {
+            Some(dap) if dap.is_attached() => dap.run_frame(&mut self.emulator, self.tickrate),
+            _ => match self.speed.multiplier() {
+                Some(multiplier) => self.run_throttled(multiplier),
+                None => self.run_unthrottled(),
+            },
+        };
+        self.ips.add(executed);
+        self.print_diagnostics();
+
+        if let Some(overlay) = &mut self.debug_overlay {
+            overlay.track_changes(&self.emulator);
+        }
+    }
+
+    pub fn draw(&self) {
+        clear_background(BLACK);
+        self.emulator.render();
+
+        if let Some(browser) = &self.browser {
+            browser.draw();
+            return;
+        }
+
+        match &self.debug_overlay {
+            Some(overlay) => overlay.draw(&self.emulator, self.rom.len()),
+            None => self.draw_rom_info(),
+        }
+
+        let mode = match self.emulator.fault() {
+            Some(fault) => format!("HALTED ({})", fault),
+            None if self.emulator.is_playing() => "RUNNING".to_string(),
+            None => "PAUSED".to_string(),
+        };
+        let mut status = format!("{} {} | {} IPS", mode, self.speed.label(), self.ips.ips);
+        if self.diagnostic_count > 0 {
+            status += &format!(" | {} lints", self.diagnostic_count);
+        }
+        if self.gdb.as_ref().is_some_and(|gdb| gdb.is_attached()) {
+            status += " | GDB";
+        }
+        if self.dap.as_ref().is_some_and(|dap| dap.is_attached()) {
+            status += " | IDE";
+        }
+        draw_text(&status, 10.0, screen_height() - 30.0, STATUS_FONT_SIZE, YELLOW);
+        draw_text(
+            "O open  F2 debug  P pause  F5 reset  F6 frame  F7 step  F8 over  -/= speed",
+            10.0,
+            screen_height() - 10.0,
+            STATUS_FONT_SIZE,
+            GRAY,
+        );
+    }
+
+    fn print_diagnostics(&mut self) {
+        if let Some(diagnostics) = &self.diagnostics {
+            for diagnostic in diagnostics.try_iter() {
+                println!("Lint: {}", diagnostic);
+                self.diagnostic_count += 1;
+            }
+        }
+    }
+
+    /**
+    Writes the profile of the run so far to the working directory, named after the ROM: an
+    annotated disassembly to `<rom>.profile.asm` and the time spent per subroutine to
+    `<rom>.folded`, for flamegraph tools. Does nothing unless profiling.
+     */
+    fn write_profile(&self) -> io::Result<()> {
+        let (Some(profile), Some(analysis)) = (self.emulator.profile(), &self.analysis) else { return Ok(()) };
+        let name = self.rom_watcher.as_ref()
+            .and_then(|watcher| watcher.path().file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "rom".to_string());
+
+        let platform = self.emulator.platform();
+        let program_address = self.emulator.program_address();
+        let listing = format!("{}.profile.asm", name);
+        fs::write(&listing, disassembler::annotate(&self.rom, platform, program_address, profile))?;
+        let folded = format!("{}.folded", name);
+        fs::write(&folded, profile.folded_stacks(&analysis.labels()))?;
+        println!("Profile of {} instructions written to {} and {}", profile.instructions(), listing, folded);
+        Ok(())
+    }
+
+    fn draw_rom_info(&self) {
+        let metadata = match &self.rom_metadata {
+            Some(metadata) => metadata,
+            None => return,
+        };
+
+        let mut lines = vec![
+            format!(
+                "{} ({}) by {}",
+                metadata.title,
+                metadata.release.as_deref().unwrap_or("?"),
+                metadata.authors.join(", ")
+            ),
+            if metadata.quirks.vip_timing {
+                format!("{}, COSMAC VIP timing", metadata.platform.name())
+            } else {
+                format!("{}, tickrate {}", metadata.platform.name(), metadata.tickrate)
+            },
+        ];
+        if let Some(description) = &metadata.description {
+            lines.extend(wrap_text(description, INFO_WRAP_WIDTH));
+        }
+
+        for (index, line) in lines.iter().enumerate() {
+            let y = INFO_TOP + index as f32 * INFO_LINE_HEIGHT;
+            draw_text(line, 10.0, y, STATUS_FONT_SIZE, WHITE);
+        }
+    }
+
+    // Loads the first file dropped onto the window.
+    fn handle_dropped_files(&mut self) {
+        let dropped_files: Vec<PathBuf> = (0..dropped_file_count()).filter_map(dropped_file_path).collect();
+        if dropped_files == self.dropped_files {
+            return;
+        }
+        self.dropped_files = dropped_files;
+
+        if let Some(path) = self.dropped_files.first().cloned() {
+            self.browser = None;
+            if let Err(e) = self.load_rom(&path) {
+                println!("Could not open rom {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn reload_changed_rom(&mut self) {
+        let changed_path = match &mut self.rom_watcher {
+            Some(watcher) => {
+                if !watcher.poll() {
+                    return;
+                }
+                watcher.path().to_path_buf()
+            }
+            None => return,
+        };
+
+        println!("Rom changed on disk, reloading");
+        if let Err(e) = self.load_rom(&changed_path) {
+            println!("Could not reload rom {}: {}", changed_path.display(), e);
+        }
+    }
+
+    fn update_browser(&mut self) {
+        if is_key_pressed(KEY_CLOSE_BROWSER) {
+            self.browser = None;
+            return;
+        }
+
+        let selected_rom = self.browser.as_mut().and_then(|browser| browser.update());
+        if let Some(path) = selected_rom {
+            self.browser = None;
+            if let Err(e) = self.load_rom(&path) {
+                println!("Could not open rom {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn handle_hotkeys(&mut self) {
+        if is_key_pressed(KEY_PAUSE) {
+            if self.emulator.is_playing() {
+                self.emulator.pause();
+            } else {
+                self.emulator.start();
+            }
+        }
+
+        if is_key_pressed(KEY_RESET) {
+            self.reset();
+        }
+
+        if is_key_pressed(KEY_SLOWER) {
+            self.set_speed(self.speed.slower());
+        }
+
+        if is_key_pressed(KEY_FASTER) {
+            self.set_speed(self.speed.faster());
+        }
+
+        if is_key_pressed(KEY_WRITE_PROFILE) {
+            if let Err(e) = self.write_profile() {
+                println!("Could not write the profile: {}", e);
+            }
+        }
+
+        if !self.emulator.is_playing() {
+            if is_key_pressed(KEY_FRAME_ADVANCE) {
+                self.advance_frame();
+            }
+
+            if is_key_pressed(KEY_STEP) {
+                self.emulator.tick();
+                self.ips.add(1);
+            }
+
+            if is_key_pressed(KEY_STEP_OVER) {
+                self.step_over();
+            }
+        }
+    }
+
+    fn update_keypad(&mut self) {
+        let mut pressed = [false; 16];
+        for (key_code, key_index) in KEY_MAP.iter().chain(self.rom_key_map.iter()) {
+            pressed[*key_index] |= is_key_down(*key_code);
+        }
+
+        for (key_index, is_pressed) in pressed.into_iter().enumerate() {
+            if is_pressed {
+                self.emulator.press_key(key_index);
+            } else {
+                self.emulator.release_key(key_index);
+            }
+        }
+
+        for (key_code, key_index) in SECOND_KEY_MAP {
+            if is_key_down(key_code) {
+                self.emulator.press_second_key(key_index);
+            } else {
+                self.emulator.release_second_key(key_index);
+            }
+        }
+    }
+
+    // Soft reset: reloads the current ROM keeping the paused/running state.
+    fn reset(&mut self) {
+        let was_playing = self.emulator.is_playing();
+        self.emulator.stop();
+        self.emulator.load(self.rom.clone());
+        if was_playing {
+            self.emulator.start();
+        }
+        self.frame_budget = 0.0;
+    }
+
+    fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.frame_budget = 0.0;
+    }
+
+    // Runs exactly one emulated frame while paused.
+    fn advance_frame(&mut self) {
+        self.emulator.start();
+        let executed = self.emulator.run_frame(self.tickrate);
+        self.emulator.pause();
+        self.ips.add(executed);
+    }
+
+    /**
+    Executes one instruction while paused, running calls until they return to the next one.
+    Timers keep running at the current tickrate meanwhile, and calls that don't return within
+    STEP_OVER_FRAME_LIMIT frames, or raise a fault, are left where they got to.
+     */
+    fn step_over(&mut self) {
+        let pc = self.emulator.read_register(Register::Pc);
+        // The analysis saw the ROM as loaded, so make sure the program didn't rewrite the call.
+        let is_call = self.emulator.memory().get(pc as usize).is_some_and(|byte| byte & 0xF0 == 0x20);
+        let return_address = self.analysis.as_ref().and_then(|analysis| analysis.step_over(pc)).filter(|_| is_call);
+        let depth = self.emulator.stack().len();
+
+        self.emulator.tick();
+        let mut executed = 1;
+        if let Some(return_address) = return_address {
+            while executed < STEP_OVER_FRAME_LIMIT * self.tickrate && self.emulator.fault().is_none() {
+                if self.emulator.read_register(Register::Pc) == return_address && self.emulator.stack().len() <= depth {
+                    break;
+                }
+                self.emulator.tick();
+                executed += 1;
+                if executed % self.tickrate == 0 {
+                    self.emulator.update_timers();
+                }
+            }
+        }
+        self.ips.add(executed);
+    }
+
+    fn run_throttled(&mut self, multiplier: f64) -> u32 {
+        if !self.emulator.is_playing() {
+            return 0;
+        }
+
+        let mut executed = 0;
+        self.frame_budget += multiplier;
+        while self.frame_budget >= 1.0 {
+            executed += self.emulator.run_frame(self.tickrate);
+            self.frame_budget -= 1.0;
+        }
+
+        executed
+    }
+
+    fn run_unthrottled(&mut self) -> u32 {
+        if !self.emulator.is_playing() {
+            return 0;
+        }
+
+        let started = Instant::now();
+        let mut executed = 0;
+        while started.elapsed().as_secs_f64() < FAST_FORWARD_FRAME_BUDGET {
+            executed += self.emulator.run_frame(self.tickrate);
+        }
+
+        executed
+    }
+}
+
+// Splits `text` into lines of at most `width` characters, breaking at spaces.
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
@@ -0,0 +1,534 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use crate::chip8::{BreakpointOwner, Chip8, Fault, Register};
+
+/**
+Target description sent to debuggers, listing the registers in the order of `g` packets and
+`Register::ALL`. Values are sent big-endian, like CHIP-8 stores them in memory.
+ */
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rusted-chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// Largest packet accepted, advertised to the debugger.
+const PACKET_SIZE: usize = 0x1000;
+
+// Signals reported when the program stops.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/**
+A GDB remote serial protocol server, so standard debuggers and IDEs can attach to a running
+`Chip8` over TCP. It supports reading and writing registers and memory, software and hardware
+breakpoints, single-stepping, continuing and interrupting. One debugger is served at a time.
+
+It never blocks: `poll` is called every frame alongside `Chip8::run_frame`. Attaching pauses the
+emulator, and from then on the debugger decides when it plays. Detaching, or disconnecting,
+removes the breakpoints the debugger set and resumes the program.
+ */
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    // Bytes received and not handled yet.
+    input: Vec<u8>,
+    // The debugger asked not to acknowledge packets anymore.
+    no_ack: bool,
+    // The program runs until it stops, and then the debugger is told why.
+    running: bool,
+}
+
+impl GdbStub {
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub { listener, client: None })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Accepts a debugger, handles the packets it sent, and tells it when the program stopped.
+    pub fn poll(&mut self, chip8: &mut Chip8) {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => match Client::attach(stream, chip8) {
+                    Ok(client) => self.client = Some(client),
+                    Err(e) => println!("Debugger could not attach: {}", e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => println!("Debugger could not attach: {}", e),
+            }
+        }
+
+        let Some(client) = &mut self.client else { return };
+        match client.poll(chip8) {
+            Ok(true) => {}
+            Ok(false) => self.detach(chip8),
+            Err(e) => {
+                println!("Debugger disconnected: {}", e);
+                self.detach(chip8);
+            }
+        }
+    }
+
+    fn detach(&mut self, chip8: &mut Chip8) {
+        if self.client.take().is_some() {
+            chip8.clear_breakpoints(BreakpointOwner::Gdb);
+            if chip8.fault().is_none() {
+                chip8.start();
+            }
+        }
+    }
+}
+
+impl Client {
+    fn attach(stream: TcpStream, chip8: &mut Chip8) -> io::Result<Client> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        chip8.pause();
+        Ok(Client {
+            stream,
+            input: Vec::new(),
+            no_ack: false,
+            running: false,
+        })
+    }
+
+    // Returns false once the debugger detached or disconnected.
+    fn poll(&mut self, chip8: &mut Chip8) -> io::Result<bool> {
+        let mut buffer = [0; PACKET_SIZE];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(read) => self.input.extend_from_slice(&buffer[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        while let Some(&first) = self.input.first() {
+            match first {
+                // Ctrl-C in the debugger.
+                0x03 => {
+                    self.input.remove(0);
+                    if self.running {
+                        chip8.pause();
+                        self.running = false;
+                        self.send(&stop_reply(chip8, SIGINT))?;
+                    }
+                }
+                b'$' => {
+                    let Some(end) = self.input.iter().position(|byte| *byte == b'#') else { break };
+                    if self.input.len() < end + 3 {
+                        break;
+                    }
+                    let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+                    let data = String::from_utf8_lossy(&packet[1..end]).into_owned();
+                    let checksum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    if checksum != Some(checksum_of(data.as_bytes())) {
+                        self.write(b"-")?;
+                        continue;
+                    }
+                    if !self.no_ack {
+                        self.write(b"+")?;
+                    }
+                    if !self.handle(&data, chip8)? {
+                        return Ok(false);
+                    }
+                }
+                // Acknowledgements, which are not checked, and line noise.
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+
+        if self.running && (!chip8.is_playing() || chip8.fault().is_some()) {
+            self.running = false;
+            self.send(&stop_reply(chip8, SIGTRAP))?;
+        }
+        Ok(true)
+    }
+
+    // Handles a packet and replies to it. Returns false when the debugger detached.
+    fn handle(&mut self, packet: &str, chip8: &mut Chip8) -> io::Result<bool> {
+        let reply = match packet.as_bytes().first().copied().unwrap_or(0) {
+            b'?' => stop_reply(chip8, SIGTRAP),
+            b'g' => Register::ALL.iter().map(|register| register_hex(chip8, *register)).collect(),
+            b'G' => write_registers(chip8, &packet[1..]),
+            b'p' => match usize::from_str_radix(&packet[1..], 16).ok().and_then(|index| Register::ALL.get(index)) {
+                Some(register) => register_hex(chip8, *register),
+                None => "E01".to_string(),
+            },
+            b'P' => write_register(chip8, &packet[1..]),
+            b'm' => read_memory(chip8, &packet[1..]),
+            b'M' => write_memory(chip8, &packet[1..]),
+            b'Z' | b'z' => self.breakpoint(chip8, packet),
+            b's' => {
+                if let Some(address) = parse_hex(&packet[1..]) {
+                    chip8.write_register(Register::Pc, address as u16);
+                }
+                self.step(chip8)
+            }
+            b'c' => {
+                if let Some(address) = parse_hex(&packet[1..]) {
+                    chip8.write_register(Register::Pc, address as u16);
+                }
+                self.resume(chip8)
+            }
+            b'v' => self.handle_v(packet, chip8),
+            b'q' | b'Q' => self.handle_query(packet),
+            b'H' | b'T' => "OK".to_string(),
+            b'D' => {
+                self.send("OK")?;
+                return Ok(false);
+            }
+            b'k' => return Ok(false),
+            _ => String::new(),
+        };
+        if !reply.is_empty() || !self.running {
+            self.send(&reply)?;
+        }
+        Ok(true)
+    }
+
+    fn handle_v(&mut self, packet: &str, chip8: &mut Chip8) -> String {
+        if packet == "vCont?" {
+            return "vCont;c;C;s;S".to_string();
+        }
+        let Some(actions) = packet.strip_prefix("vCont;") else { return String::new() };
+        // Only one thread, so the first action applies to it.
+        match actions.as_bytes().first() {
+            Some(b's' | b'S') => self.step(chip8),
+            Some(b'c' | b'C') => self.resume(chip8),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:X};qXfer:features:read+;QStartNoAckMode+;vContSupported+", PACKET_SIZE);
+        }
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = request.split_once(',') else { return "E01".to_string() };
+            let (Some(offset), Some(length)) = (parse_hex(offset), parse_hex(length)) else { return "E01".to_string() };
+            let chunk = TARGET_XML.get(offset.min(TARGET_XML.len())..(offset + length).min(TARGET_XML.len())).unwrap_or("");
+            let more = offset + length < TARGET_XML.len();
+            return format!("{}{}", if more { "m" } else { "l" }, chunk);
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                // The packet itself was acknowledged already.
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // Z0 and Z1 insert software and hardware breakpoints, z0 and z1 remove them. Both are the same here.
+    fn breakpoint(&mut self, chip8: &mut Chip8, packet: &str) -> String {
+        let mut fields = packet[1..].split(',');
+        let (Some(kind), Some(address)) = (fields.next(), fields.next().and_then(parse_hex)) else { return "E01".to_string() };
+        if kind != "0" && kind != "1" {
+            return String::new();
+        }
+        let address = address as u16;
+        if packet.starts_with('Z') {
+            chip8.add_breakpoint(address, BreakpointOwner::Gdb);
+        } else {
+            chip8.remove_breakpoint(address, BreakpointOwner::Gdb);
+        }
+        "OK".to_string()
+    }
+
+    fn step(&mut self, chip8: &mut Chip8) -> String {
+        chip8.pause();
+        chip8.tick();
+        stop_reply(chip8, SIGTRAP)
+    }
+
+    // Lets the program run, replying once it stops. A breakpoint at PC is stepped over first.
+    fn resume(&mut self, chip8: &mut Chip8) -> String {
+        if chip8.fault().is_some() {
+            return stop_reply(chip8, SIGTRAP);
+        }
+        if chip8.has_breakpoint(chip8.read_register(Register::Pc)) && !chip8.is_paused_at_breakpoint() {
+            chip8.tick();
+        }
+        chip8.start();
+        self.running = true;
+        String::new()
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.write(packet.as_bytes())
+    }
+
+    // Writes all of `bytes`, waiting for the socket when its buffer is full.
+    fn write(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match self.stream.write(bytes) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => bytes = &bytes[written..],
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_hex(hex: &str) -> Option<usize> {
+    usize::from_str_radix(hex, 16).ok()
+}
+
+// Why the program stopped, with `signal` unless a fault stopped it.
+fn stop_reply(chip8: &Chip8, signal: u8) -> String {
+    let signal = match chip8.fault() {
+        Some(Fault::UnknownOpcode { .. }) => SIGILL,
+        Some(Fault::StackOverflow { .. } | Fault::StackUnderflow { .. }) => SIGSEGV,
+        None => signal,
+    };
+    format!("S{:02x}", signal)
+}
+
+fn register_hex(chip8: &Chip8, register: Register) -> String {
+    format!("{:0width$x}", chip8.read_register(register), width = register.size() * 2)
+}
+
+fn write_register(chip8: &mut Chip8, request: &str) -> String {
+    let Some((index, value)) = request.split_once('=') else { return "E01".to_string() };
+    match (parse_hex(index).and_then(|index| Register::ALL.get(index)), parse_hex(value)) {
+        (Some(register), Some(value)) => {
+            chip8.write_register(*register, value as u16);
+            "OK".to_string()
+        }
+        _ => "E01".to_string(),
+    }
+}
+
+fn write_registers(chip8: &mut Chip8, mut hex: &str) -> String {
+    for register in Register::ALL {
+        let digits = register.size() * 2;
+        let Some(value) = hex.get(..digits).and_then(parse_hex) else { return "E01".to_string() };
+        chip8.write_register(register, value as u16);
+        hex = &hex[digits..];
+    }
+    "OK".to_string()
+}
+
+// `addr,length`, limited to the end of memory.
+fn memory_range(chip8: &Chip8, request: &str) -> Option<(usize, usize)> {
+    let (address, length) = request.split_once(',')?;
+    let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+    let size = chip8.memory().len();
+    (address < size).then(|| (address, length.min(size - address)))
+}
+
+fn read_memory(chip8: &Chip8, request: &str) -> String {
+    match memory_range(chip8, request) {
+        Some((address, length)) => chip8.memory()[address..address + length].iter().map(|byte| format!("{:02x}", byte)).collect(),
+        None => "E01".to_string(),
+    }
+}
+
+fn write_memory(chip8: &mut Chip8, request: &str) -> String {
+    let Some((range, data)) = request.split_once(':') else { return "E01".to_string() };
+    let Some((address, length)) = memory_range(chip8, range) else { return "E01".to_string() };
+    if data.len() < length * 2 || !data.is_ascii() {
+        return "E01".to_string();
+    }
+    for offset in 0..length {
+        let Some(value) = parse_hex(&data[offset * 2..offset * 2 + 2]) else { return "E01".to_string() };
+        chip8.write_memory(address + offset, value as u8);
+    }
+    "OK".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::thread;
+
+    // A scripted debugger, talking to the stub from another thread.
+    struct Debugger {
+        reader: io::BufReader<TcpStream>,
+        stream: TcpStream,
+    }
+
+    impl Debugger {
+        // Sends a packet, checking it is acknowledged.
+        fn send(&mut self, data: &str) {
+            self.stream.write_all(format!("${}#{:02x}", data, checksum_of(data.as_bytes())).as_bytes()).unwrap();
+            let mut ack = [0];
+            self.reader.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            let mut packet = Vec::new();
+            self.reader.read_until(b'#', &mut packet).unwrap();
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+            let data = String::from_utf8(packet[1..packet.len() - 1].to_vec()).unwrap();
+            assert_eq!(u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(), checksum_of(data.as_bytes()));
+            data
+        }
+    }
+
+    // Runs `program` under the stub while `script` debugs it, then detaches and returns the emulator.
+    fn debug(program: &[u16], script: impl FnOnce(&mut Debugger) + Send + 'static) -> Chip8 {
+        let mut stub = GdbStub::bind("127.0.0.1:0").unwrap();
+        let address = stub.local_addr().unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.load(program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect());
+
+        let debugger = thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            stream.set_nodelay(true).unwrap();
+            let mut debugger = Debugger { reader: io::BufReader::new(stream.try_clone().unwrap()), stream };
+            script(&mut debugger);
+            assert_eq!(debugger.request("D"), "OK");
+        });
+        while !debugger.is_finished() {
+            stub.poll(&mut chip8);
+            chip8.run_frame(10);
+            thread::yield_now();
+        }
+        if let Err(panic) = debugger.join() {
+            std::panic::resume_unwind(panic);
+        }
+        stub.poll(&mut chip8);
+        assert!(!stub.is_attached());
+        chip8
+    }
+
+    #[test]
+    fn registers_should_be_read_and_written() {
+        let chip8 = debug(&[0x6A2B, 0xA123, 0x1204], |debugger| {
+            assert_eq!(debugger.request("?"), "S05");
+            assert_eq!(debugger.request("s"), "S05");
+            assert_eq!(debugger.request("s"), "S05");
+            assert_eq!(debugger.request("g"), format!("{}2b{}01230204000000", "00".repeat(10), "00".repeat(5)));
+            assert_eq!(debugger.request("p11"), "0204");
+            assert_eq!(debugger.request("P0=7f"), "OK");
+            assert_eq!(debugger.request("p0"), "7f");
+            assert_eq!(debugger.request("p15"), "E01");
+        });
+
+        assert_eq!(chip8.read_register(Register::V(0)), 0x7F);
+    }
+
+    #[test]
+    fn memory_should_be_read_and_written() {
+        let chip8 = debug(&[0x1200], |debugger| {
+            assert_eq!(debugger.request("m200,2"), "1200");
+            assert_eq!(debugger.request("M300,3:abcdef"), "OK");
+            assert_eq!(debugger.request("m300,3"), "abcdef");
+            assert_eq!(debugger.request("mffe,4"), "0000");
+            assert_eq!(debugger.request("m1000,1"), "E01");
+        });
+
+        assert_eq!(chip8.memory()[0x301], 0xCD);
+    }
+
+    #[test]
+    fn continue_should_stop_at_breakpoints_and_step_over_them() {
+        // Counts V0 up forever.
+        let chip8 = debug(&[0x7001, 0x6100, 0x1200], |debugger| {
+            assert_eq!(debugger.request("Z0,202,2"), "OK");
+            assert_eq!(debugger.request("c"), "S05");
+            assert_eq!(debugger.request("p0"), "01");
+            assert_eq!(debugger.request("c"), "S05");
+            assert_eq!(debugger.request("p0"), "02");
+            assert_eq!(debugger.request("p11"), "0202");
+            assert_eq!(debugger.request("z0,202,2"), "OK");
+
+            // Interrupting a program without breakpoints.
+            debugger.send("vCont;c");
+            debugger.stream.write_all(&[0x03]).unwrap();
+            assert_eq!(debugger.reply(), "S02");
+            assert_eq!(debugger.request("Z0,204,2"), "OK");
+        });
+
+        assert_eq!(chip8.breakpoints().next(), None);
+        assert!(chip8.is_playing());
+    }
+
+    #[test]
+    fn faults_should_be_reported_as_signals() {
+        debug(&[0x00EE], |debugger| {
+            assert_eq!(debugger.request("c"), "S0b");
+        });
+    }
+
+    #[test]
+    fn the_target_description_should_be_served_in_chunks() {
+        debug(&[0x1200], |debugger| {
+            assert!(debugger.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+            let mut xml = String::new();
+            loop {
+                let chunk = debugger.request(&format!("qXfer:features:read:target.xml:{:x},80", xml.len()));
+                xml += &chunk[1..];
+                if chunk.starts_with('l') {
+                    break;
+                }
+            }
+            assert_eq!(xml, TARGET_XML);
+        });
+    }
+}
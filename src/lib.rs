@@ -0,0 +1,8 @@
+pub mod analysis;
+pub mod chip8;
+pub mod dap;
+pub mod disassembler;
+pub mod gdb;
+pub mod octo;
+pub mod rom_database;
+pub mod rom_loader;
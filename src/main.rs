@@ -1,29 +1,125 @@
-use std::fs;
+use std::env;
+use std::path::Path;
 use macroquad::prelude::next_frame;
-use crate::chip8::Chip8;
-
-mod chip8;
-
-#[macroquad::main("Rusted Chip8")]
-async fn main() {
-    let mut emulator: Chip8 = Chip8::new();
-
-    //let result = fs::read("roms/Particle Demo [zeroZshadow, 2008].ch8");
-    let result = fs::read("roms/Space Invaders [David Winter].ch8");
-    let program: Vec<u8>;
-    match result {
-        Ok(contents) => {
-            program = contents.into();
-        },
-        Err(e) => {
-            panic!("Could not open rom {}", e);
+use macroquad::Window;
+use rusted_chip8::analysis::Analysis;
+use rusted_chip8::chip8::{Font, FontStyle, MemoryFill, Platform};
+use rusted_chip8::disassembler;
+use rusted_chip8::rom_database::RomDatabase;
+use rusted_chip8::rom_loader;
+use crate::frontend::{Frontend, ROMS_DIRECTORY};
+
+mod debug_overlay;
+mod frontend;
+mod rom_browser;
+mod rom_watcher;
+
+const USAGE: &str = "Usage: rusted-chip8 [--font vip|dream6800|eti660|schip|<file>] [--font-address <hex>] [--platform <id>] [--start-address <hex>] [--memory-fill zero|random[:<seed>]|<hex>] [--lint] [--profile] [--gdb <port>] [--dap <port>] [--disassemble|--dot] [rom]";
+
+// What to print about the ROM instead of running it.
+enum Listing {
+    Disassembly,
+    Dot,
+}
+
+// Arguments are handled before the window is created, so listings print without opening one.
+fn main() {
+    let mut frontend = Frontend::new(RomDatabase::bundled());
+
+    let mut rom_path = None;
+    let mut font = None;
+    let mut font_address = None;
+    let mut platform = None;
+    let mut program_address = None;
+    let mut listing = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--font" => {
+                let name = args.next().expect(USAGE);
+                font = Some(match FontStyle::from_id(&name) {
+                    Some(style) => Font::builtin(style),
+                    None => Font::load_file(Path::new(&name))
+                        .unwrap_or_else(|e| panic!("Could not open font {}: {}", name, e)),
+                });
+            }
+            "--font-address" => {
+                let address = args.next().expect(USAGE);
+                let address = u16::from_str_radix(address.trim_start_matches("0x"), 16).expect(USAGE);
+                font_address = Some(address);
+            }
+            "--platform" => {
+                let id = args.next().expect(USAGE);
+                platform = Some(Platform::from_id(&id).unwrap_or_else(|| panic!("Unknown platform {}", id)));
+            }
+            "--start-address" => {
+                let address = args.next().expect(USAGE);
+                program_address = Some(u16::from_str_radix(address.trim_start_matches("0x"), 16).expect(USAGE));
+            }
+            "--memory-fill" => {
+                let fill = args.next().expect(USAGE);
+                frontend.set_memory_fill(MemoryFill::from_id(&fill).expect(USAGE));
+            }
+            "--lint" => frontend.enable_lints(),
+            "--profile" => frontend.enable_profiling(),
+            "--gdb" => {
+                let port = args.next().expect(USAGE).parse().expect(USAGE);
+                if let Err(e) = frontend.listen_gdb(port) {
+                    panic!("Could not listen for GDB on port {}: {}", port, e);
+                }
+            }
+            "--dap" => {
+                let port = args.next().expect(USAGE).parse().expect(USAGE);
+                if let Err(e) = frontend.listen_dap(port) {
+                    panic!("Could not listen for an IDE on port {}: {}", port, e);
+                }
+            }
+            "--disassemble" => listing = Some(Listing::Disassembly),
+            "--dot" => listing = Some(Listing::Dot),
+            _ => rom_path = Some(arg),
         }
     }
-    println!("Rom opened: len {}", program.len());
-    emulator.load(program);
-    emulator.start();
+    if let Some(listing) = listing {
+        print_listing(&rom_path.expect(USAGE), listing, platform, program_address);
+        return;
+    }
+
+    frontend.set_font(font, font_address);
+    frontend.set_platform(platform);
+    frontend.set_program_address(program_address);
+    Window::new("Rusted Chip8", run(frontend, rom_path));
+}
+
+async fn run(mut frontend: Frontend, rom_path: Option<String>) {
+    // Load the ROM given on the command line, or let the user pick one.
+    match rom_path {
+        Some(rom_path) => {
+            if let Err(e) = frontend.load_rom(Path::new(&rom_path)) {
+                panic!("Could not open rom {}", e);
+            }
+        }
+        None => {
+            if let Err(e) = frontend.open_browser(Path::new(ROMS_DIRECTORY)) {
+                panic!("Could not open {}: {}", ROMS_DIRECTORY, e);
+            }
+        }
+    }
+
     loop {
-        emulator.run();
+        frontend.update();
+        frontend.draw();
         next_frame().await;
     }
 }
+
+// Prints the disassembly or control-flow graph of a ROM, seen as the frontend would load it.
+fn print_listing(rom_path: &str, listing: Listing, platform: Option<Platform>, program_address: Option<u16>) {
+    let rom = rom_loader::load_file(Path::new(rom_path)).unwrap_or_else(|e| panic!("Could not open rom {}", e));
+    let metadata_platform = RomDatabase::bundled().lookup(&rom.program).map(|metadata| metadata.platform);
+    let platform = platform.or(metadata_platform).or(rom.platform).unwrap_or_default();
+    let program_address = program_address.or(rom.start_address);
+    match listing {
+        Listing::Disassembly => print!("{}", disassembler::disassemble(&rom.program, platform, program_address)),
+        Listing::Dot => print!("{}", Analysis::new(&rom.program, platform, program_address).to_dot()),
+    }
+}
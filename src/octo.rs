@@ -0,0 +1,959 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+// Octo programs are assembled to run from 0x200, where `jump main` goes unless main comes first.
+const ORIGIN: u32 = 0x200;
+
+/**
+Why Octo source could not be assembled, with the line it happened on.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/**
+Assembles Octo source, as found in Octo cartridges, into a program loaded at 0x200.
+
+Supported are the CHIP-8, SUPER-CHIP and XO-CHIP instructions, labels and forward references,
+`loop`/`while`/`again`, `if ... then` and `if ... begin ... else ... end` with every comparison,
+and the `:const`, `:alias`, `:unpack`, `:next`, `:org`, `:byte`, `:pointer`, `:call`, `:macro`
+and `:calc` directives. Expressions follow Octo: no precedence, evaluated right to left unless
+parenthesized. `:breakpoint` and `:monitor` only matter to Octo's debugger and are skipped.
+`:string`, `:stringmode` and `:assert` are not supported.
+ */
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler::new(source);
+    assembler.run()?;
+    Ok(assembler.rom)
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+    calls: u32,
+}
+
+// How a reference to a name not defined yet is filled in once it is.
+#[derive(Clone, Copy)]
+enum Patch {
+    // The low 12 bits of the instruction at the address.
+    Address,
+    // The 16 bits at the address, after F000 or for `:pointer`.
+    Long,
+    // The NN byte of 6XNN for `:unpack`: the nibble, then the high nibble of the 12-bit address.
+    UnpackHigh(u8),
+    // The NN byte of 6XNN for `:unpack long`: the high byte of the address.
+    UnpackLongHigh,
+    // The NN byte of 6XNN for `:unpack`: the low byte of the address.
+    UnpackLow,
+}
+
+// A conditional skip, before it is turned into instructions.
+#[derive(Clone, Copy)]
+struct Condition {
+    register: u8,
+    comparison: Comparison,
+    operand: Operand,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Key,
+    NotKey,
+}
+
+impl Comparison {
+    fn negated(self) -> Comparison {
+        match self {
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::Less => Comparison::GreaterOrEqual,
+            Comparison::GreaterOrEqual => Comparison::Less,
+            Comparison::Greater => Comparison::LessOrEqual,
+            Comparison::LessOrEqual => Comparison::Greater,
+            Comparison::Key => Comparison::NotKey,
+            Comparison::NotKey => Comparison::Key,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    None,
+    Register(u8),
+    Byte(u8),
+}
+
+// Open `if ... begin` blocks and loops, innermost last.
+enum Block {
+    // The address of the jump to patch at `else` or `end`, and whether `else` was seen.
+    If { jump: u32, has_else: bool },
+    // Where `again` jumps back to, and the jumps out of the loop of its `while`s.
+    Loop { start: u32, exits: Vec<u32> },
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    // The line of the last token read, for errors.
+    line: usize,
+    rom: Vec<u8>,
+    here: u32,
+    // Labels and constants. Octo keeps both in one namespace.
+    values: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    patches: Vec<(u32, String, Patch, usize)>,
+    blocks: Vec<Block>,
+    // The name of a `:next` waiting for the next instruction.
+    next: Option<String>,
+    // 0x200 holds a jump to main until main turns out to be the first label.
+    main_jump: bool,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Assembler {
+        let tokens = source
+            .lines()
+            .enumerate()
+            .flat_map(|(index, line)| {
+                let code = line.split('#').next().unwrap_or_default();
+                code.split_whitespace().map(move |text| Token { text: text.to_string(), line: index + 1 })
+            })
+            .collect();
+        Assembler {
+            tokens,
+            line: 1,
+            rom: Vec::new(),
+            here: ORIGIN,
+            values: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            patches: Vec::new(),
+            blocks: Vec::new(),
+            next: None,
+            main_jump: true,
+        }
+    }
+
+    fn run(&mut self) -> Result<(), AssembleError> {
+        self.emit(0x1000);
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if !self.blocks.is_empty() {
+            return Err(self.error("a loop or an if ... begin has no end"));
+        }
+        let Some(main) = self.values.get("main").copied() else {
+            return Err(self.error("the program has no main label"));
+        };
+        if self.main_jump {
+            self.write_word(ORIGIN, 0x1000 | main as u16);
+        }
+        for (address, name, patch, line) in std::mem::take(&mut self.patches) {
+            let Some(value) = self.values.get(&name).copied() else {
+                return Err(AssembleError { line, message: format!("undefined name {}", name) });
+            };
+            let value = value as u32;
+            match patch {
+                Patch::Address if value > 0xFFF => {
+                    return Err(AssembleError { line, message: format!("{} is past 0xFFF", name) });
+                }
+                Patch::Address => self.write_word(address, (self.word(address) & 0xF000) | value as u16),
+                Patch::Long => self.write_word(address, value as u16),
+                Patch::UnpackHigh(nibble) => self.write_byte(address + 1, (nibble << 4) | ((value >> 8) & 0xF) as u8),
+                Patch::UnpackLongHigh => self.write_byte(address + 1, (value >> 8) as u8),
+                Patch::UnpackLow => self.write_byte(address + 1, value as u8),
+            }
+        }
+        Ok(())
+    }
+
+    fn error(&self, message: &str) -> AssembleError {
+        AssembleError { line: self.line, message: message.to_string() }
+    }
+
+    fn next_token(&mut self) -> Result<String, AssembleError> {
+        let token = self.tokens.pop_front().ok_or_else(|| self.error("unexpected end of the source"))?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AssembleError> {
+        let token = self.next_token()?;
+        if token != expected {
+            return Err(self.error(&format!("expected {} instead of {}", expected, token)));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next_token()?;
+        match token.as_str() {
+            ":" => {
+                let name = self.next_token()?;
+                // A main coming first needs no jump to it.
+                if name == "main" && self.main_jump && self.here == ORIGIN + 2 && self.rom.len() == 2 {
+                    self.main_jump = false;
+                    self.rom.clear();
+                    self.here = ORIGIN;
+                }
+                self.define(name, self.here as f64)?;
+            }
+            ":const" => {
+                let name = self.next_token()?;
+                let value = self.number()?;
+                self.define(name, value)?;
+            }
+            ":alias" => {
+                let name = self.next_token()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":unpack" => {
+                let long = self.peek() == Some("long");
+                let high = if long {
+                    self.next_token()?;
+                    Patch::UnpackLongHigh
+                } else {
+                    Patch::UnpackHigh(self.number()? as u8 & 0xF)
+                };
+                let name = self.next_token()?;
+                self.reference(self.here, &name, high)?;
+                self.emit(0x6000);
+                self.reference(self.here, &name, Patch::UnpackLow)?;
+                self.emit(0x6100);
+            }
+            ":next" => self.next = Some(self.next_token()?),
+            ":org" => {
+                let address = self.number()? as u32;
+                if address < ORIGIN {
+                    return Err(self.error("programs cannot start below 0x200"));
+                }
+                self.here = address;
+            }
+            ":byte" => {
+                let value = self.expression_or_number()?;
+                self.write_byte(self.here, value as i64 as u8);
+                self.here += 1;
+            }
+            ":pointer" => {
+                let name = self.next_token()?;
+                self.reference(self.here, &name, Patch::Long)?;
+                self.here += 2;
+            }
+            ":call" => {
+                let name = self.next_token()?;
+                self.jump(0x2000, &name)?;
+            }
+            ":macro" => self.define_macro()?,
+            ":calc" => {
+                let name = self.next_token()?;
+                self.expect("{")?;
+                let value = self.expression_until_brace()?;
+                self.define(name, value)?;
+            }
+            ":breakpoint" => {
+                self.next_token()?;
+            }
+            ":monitor" => {
+                self.next_token()?;
+                self.next_token()?;
+            }
+            ":string" | ":stringmode" | ":assert" => return Err(self.error(&format!("{} is not supported", token))),
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-left" => self.emit(0x00FC),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n);
+            }
+            "audio" => self.emit(0xF002),
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8);
+            }
+            "jump" => {
+                let name = self.next_token()?;
+                self.jump(0x1000, &name)?;
+            }
+            "jump0" => {
+                let name = self.next_token()?;
+                self.jump(0xB000, &name)?;
+            }
+            "native" => {
+                let name = self.next_token()?;
+                self.jump(0x0000, &name)?;
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            }
+            "bcd" => self.register_instruction(0xF033)?,
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next_token()?;
+                    let y = self.register()? as u16;
+                    self.emit(if token == "save" { 0x5002 } else { 0x5003 } | x << 8 | y << 4);
+                } else {
+                    self.emit(if token == "save" { 0xF055 } else { 0xF065 } | x << 8);
+                }
+            }
+            "saveflags" => self.register_instruction(0xF075)?,
+            "loadflags" => self.register_instruction(0xF085)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let opcode = match token.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_instruction(opcode)?;
+            }
+            "i" => self.index()?,
+            "loop" => self.blocks.push(Block::Loop { start: self.here, exits: Vec::new() }),
+            "while" => {
+                let condition = self.condition()?;
+                if !self.blocks.iter().any(|block| matches!(block, Block::Loop { .. })) {
+                    return Err(self.error("while outside of a loop"));
+                }
+                self.skip_when(condition)?;
+                let exit = self.here;
+                self.emit(0x1000);
+                if let Some(Block::Loop { exits, .. }) = self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    exits.push(exit);
+                }
+            }
+            "again" => {
+                let Some(Block::Loop { start, exits }) = self.blocks.pop() else {
+                    return Err(self.error("again without a loop"));
+                };
+                self.emit(0x1000 | start as u16);
+                for exit in exits {
+                    self.write_word(exit, 0x1000 | self.here as u16);
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                match self.next_token()?.as_str() {
+                    "then" => self.skip_when(Condition { comparison: condition.comparison.negated(), ..condition })?,
+                    "begin" => {
+                        self.skip_when(condition)?;
+                        self.blocks.push(Block::If { jump: self.here, has_else: false });
+                        self.emit(0x1000);
+                    }
+                    other => return Err(self.error(&format!("expected then or begin instead of {}", other))),
+                }
+            }
+            "else" => {
+                let Some(Block::If { jump, has_else: false }) = self.blocks.pop() else {
+                    return Err(self.error("else without an if ... begin"));
+                };
+                self.blocks.push(Block::If { jump: self.here, has_else: true });
+                self.emit(0x1000);
+                self.write_word(jump, 0x1000 | self.here as u16);
+            }
+            "end" => {
+                let Some(Block::If { jump, .. }) = self.blocks.pop() else {
+                    return Err(self.error("end without an if ... begin"));
+                };
+                self.write_word(jump, 0x1000 | self.here as u16);
+            }
+            _ if self.is_register(&token) => self.register_statement(&token)?,
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            // Numbers on their own are data.
+            _ if parse_number(&token).is_some() => {
+                self.tokens.push_front(Token { text: token, line: self.line });
+                let value = self.byte()?;
+                self.write_byte(self.here, value as u8);
+                self.here += 1;
+            }
+            // Anything else names a subroutine to call.
+            _ => self.jump(0x2000, &token)?,
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: String, value: f64) -> Result<(), AssembleError> {
+        if self.values.contains_key(&name) {
+            return Err(self.error(&format!("{} is already defined", name)));
+        }
+        self.values.insert(name, value);
+        Ok(())
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        register_number(token).is_some() || self.aliases.contains_key(token)
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next_token()?;
+        register_number(&token)
+            .or_else(|| self.aliases.get(&token).copied())
+            .ok_or_else(|| self.error(&format!("expected a register instead of {}", token)))
+    }
+
+    // A number or the name of a constant or of a label already defined.
+    fn number(&mut self) -> Result<f64, AssembleError> {
+        let token = self.next_token()?;
+        parse_number(&token)
+            .or_else(|| self.values.get(&token).copied())
+            .ok_or_else(|| self.error(&format!("expected a number instead of {}", token)))
+    }
+
+    fn byte(&mut self) -> Result<u16, AssembleError> {
+        let value = self.number()?;
+        if !(-128.0..=255.0).contains(&value) {
+            return Err(self.error(&format!("{} does not fit in a byte", value)));
+        }
+        Ok(value as i64 as u8 as u16)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AssembleError> {
+        let value = self.number()?;
+        if !(0.0..=15.0).contains(&value) {
+            return Err(self.error(&format!("{} does not fit in a nibble", value)));
+        }
+        Ok(value as u16)
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        if let Some(name) = self.next.take() {
+            self.values.insert(name, (self.here + 1) as f64);
+        }
+        self.write_word(self.here, opcode);
+        self.here += 2;
+    }
+
+    fn register_instruction(&mut self, opcode: u16) -> Result<(), AssembleError> {
+        let x = self.register()? as u16;
+        self.emit(opcode | x << 8);
+        Ok(())
+    }
+
+    // Emits an instruction taking an address, which is filled in later if it is not known yet.
+    fn jump(&mut self, opcode: u16, name: &str) -> Result<(), AssembleError> {
+        match parse_number(name).or_else(|| self.values.get(name).copied()) {
+            Some(address) if !(0.0..=4095.0).contains(&address) => Err(self.error(&format!("{} is past 0xFFF", name))),
+            Some(address) => {
+                self.emit(opcode | address as u16);
+                Ok(())
+            }
+            None => {
+                self.reference(self.here, name, Patch::Address)?;
+                self.emit(opcode);
+                Ok(())
+            }
+        }
+    }
+
+    fn reference(&mut self, address: u32, name: &str, patch: Patch) -> Result<(), AssembleError> {
+        if let Some(value) = parse_number(name) {
+            self.values.insert(name.to_string(), value);
+        }
+        self.patches.push((address, name.to_string(), patch, self.line));
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<(), AssembleError> {
+        match self.next_token()?.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next_token()?;
+                    self.register_instruction(0xF029)
+                }
+                Some("bighex") => {
+                    self.next_token()?;
+                    self.register_instruction(0xF030)
+                }
+                Some("long") => {
+                    self.next_token()?;
+                    let name = self.next_token()?;
+                    self.emit(0xF000);
+                    self.reference(self.here, &name, Patch::Long)?;
+                    self.here += 2;
+                    Ok(())
+                }
+                _ => {
+                    let name = self.next_token()?;
+                    self.jump(0xA000, &name)
+                }
+            },
+            "+=" => self.register_instruction(0xF01E),
+            other => Err(self.error(&format!("expected := or += after i instead of {}", other))),
+        }
+    }
+
+    fn register_statement(&mut self, token: &str) -> Result<(), AssembleError> {
+        let x = register_number(token).or_else(|| self.aliases.get(token).copied()).unwrap() as u16;
+        let operator = self.next_token()?;
+        let source = self.peek().map(|token| token.to_string()).unwrap_or_default();
+        if self.is_register(&source) {
+            let y = self.register()? as u16;
+            let n = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(self.error(&format!("unknown operator {}", operator))),
+            };
+            self.emit(0x8000 | x << 8 | y << 4 | n);
+            return Ok(());
+        }
+        match (operator.as_str(), source.as_str()) {
+            (":=", "random") => {
+                self.next_token()?;
+                let nn = self.byte()?;
+                self.emit(0xC000 | x << 8 | nn);
+            }
+            (":=", "key") => {
+                self.next_token()?;
+                self.emit(0xF00A | x << 8);
+            }
+            (":=", "delay") => {
+                self.next_token()?;
+                self.emit(0xF007 | x << 8);
+            }
+            (":=", _) => {
+                let nn = self.byte()?;
+                self.emit(0x6000 | x << 8 | nn);
+            }
+            ("+=", _) => {
+                let nn = self.byte()?;
+                self.emit(0x7000 | x << 8 | nn);
+            }
+            ("-=", _) => {
+                let nn = self.byte()?;
+                self.emit(0x7000 | x << 8 | (nn as u8).wrapping_neg() as u16);
+            }
+            _ => return Err(self.error(&format!("unknown operator {} for a constant", operator))),
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let register = self.register()?;
+        let token = self.next_token()?;
+        let comparison = match token.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            "key" => Comparison::Key,
+            "-key" => Comparison::NotKey,
+            _ => return Err(self.error(&format!("unknown comparison {}", token))),
+        };
+        let operand = match comparison {
+            Comparison::Key | Comparison::NotKey => Operand::None,
+            _ if self.peek().is_some_and(|token| self.is_register(token)) => Operand::Register(self.register()?),
+            _ => Operand::Byte(self.byte()? as u8),
+        };
+        Ok(Condition { register, comparison, operand })
+    }
+
+    // Emits the instructions skipping the next one when the condition holds. Ordering comparisons
+    // subtract into VF and skip on its borrow flag, like Octo does.
+    fn skip_when(&mut self, condition: Condition) -> Result<(), AssembleError> {
+        let x = condition.register as u16;
+        match (condition.comparison, condition.operand) {
+            (Comparison::Equal, Operand::Byte(nn)) => self.emit(0x3000 | x << 8 | nn as u16),
+            (Comparison::NotEqual, Operand::Byte(nn)) => self.emit(0x4000 | x << 8 | nn as u16),
+            (Comparison::Equal, Operand::Register(y)) => self.emit(0x5000 | x << 8 | (y as u16) << 4),
+            (Comparison::NotEqual, Operand::Register(y)) => self.emit(0x9000 | x << 8 | (y as u16) << 4),
+            (Comparison::Key, _) => self.emit(0xE09E | x << 8),
+            (Comparison::NotKey, _) => self.emit(0xE0A1 | x << 8),
+            (comparison, operand) => {
+                // VF ends up 1 when left >= right. X < Y and X >= Y compare X with Y, X > Y and X <= Y compare Y with X.
+                let swapped = matches!(comparison, Comparison::Greater | Comparison::LessOrEqual);
+                let register = Operand::Register(condition.register);
+                let (left, right) = if swapped { (operand, register) } else { (register, operand) };
+                match (left, right) {
+                    (Operand::Register(l), Operand::Register(r)) => {
+                        self.emit(0x8F00 | (l as u16) << 4);
+                        self.emit(0x8F05 | (r as u16) << 4);
+                    }
+                    (Operand::Register(l), Operand::Byte(r)) => {
+                        self.emit(0x6F00 | r as u16);
+                        self.emit(0x8F07 | (l as u16) << 4);
+                    }
+                    (Operand::Byte(l), Operand::Register(r)) => {
+                        self.emit(0x6F00 | l as u16);
+                        self.emit(0x8F05 | (r as u16) << 4);
+                    }
+                    _ => return Err(self.error("nothing to compare with")),
+                }
+                let holds_when_no_borrow = matches!(comparison, Comparison::GreaterOrEqual | Comparison::LessOrEqual);
+                self.emit(if holds_when_no_borrow { 0x3F01 } else { 0x3F00 });
+            }
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.next_token()?;
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            if token == "{" {
+                break;
+            }
+            parameters.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.tokens.pop_front().ok_or_else(|| self.error("a macro has no closing brace"))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { parameters, body, calls: 0 });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), AssembleError> {
+        let count = self.macros[name].parameters.len();
+        let mut arguments = HashMap::new();
+        for index in 0..count {
+            let argument = self.next_token()?;
+            arguments.insert(self.macros[name].parameters[index].clone(), argument);
+        }
+        let line = self.line;
+        let definition = self.macros.get_mut(name).unwrap();
+        arguments.insert("CALLS".to_string(), definition.calls.to_string());
+        definition.calls += 1;
+        for token in definition.body.iter().rev() {
+            let text = arguments.get(&token.text).cloned().unwrap_or_else(|| token.text.clone());
+            self.tokens.push_front(Token { text, line });
+        }
+        Ok(())
+    }
+
+    fn expression_or_number(&mut self) -> Result<f64, AssembleError> {
+        if self.peek() == Some("{") {
+            self.next_token()?;
+            self.expression_until_brace()
+        } else {
+            self.number()
+        }
+    }
+
+    fn expression_until_brace(&mut self) -> Result<f64, AssembleError> {
+        let value = self.expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    // Octo expressions have no precedence: a binary operator takes everything on its right.
+    fn expression(&mut self) -> Result<f64, AssembleError> {
+        let left = self.term()?;
+        let operator = match self.peek() {
+            Some(operator) if BINARY_OPERATORS.contains(&operator) => operator.to_string(),
+            _ => return Ok(left),
+        };
+        self.next_token()?;
+        let right = self.expression()?;
+        let (l, r) = (left as i64, right as i64);
+        Ok(match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (l & r) as f64,
+            "|" => (l | r) as f64,
+            "^" => (l ^ r) as f64,
+            "<<" => (l << r) as f64,
+            ">>" => (l >> r) as f64,
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            _ => (left != right) as i64 as f64,
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, AssembleError> {
+        let token = self.next_token()?;
+        let value = match token.as_str() {
+            "(" => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => -self.term()?,
+            "~" => !(self.term()? as i64) as f64,
+            "!" => (self.term()? == 0.0) as i64 as f64,
+            "sin" => self.term()?.sin(),
+            "cos" => self.term()?.cos(),
+            "tan" => self.term()?.tan(),
+            "exp" => self.term()?.exp(),
+            "log" => self.term()?.ln(),
+            "abs" => self.term()?.abs(),
+            "sqrt" => self.term()?.sqrt(),
+            "sign" => self.term()?.signum(),
+            "ceil" => self.term()?.ceil(),
+            "floor" => self.term()?.floor(),
+            "@" => {
+                let address = self.term()? as u32;
+                self.byte_at(address) as f64
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => parse_number(&token)
+                .or_else(|| self.values.get(&token).copied())
+                .ok_or_else(|| self.error(&format!("{} is not defined yet", token)))?,
+        };
+        Ok(value)
+    }
+
+    fn byte_at(&self, address: u32) -> u8 {
+        address.checked_sub(ORIGIN).and_then(|index| self.rom.get(index as usize)).copied().unwrap_or(0)
+    }
+
+    fn word(&self, address: u32) -> u16 {
+        u16::from_be_bytes([self.byte_at(address), self.byte_at(address + 1)])
+    }
+
+    fn write_byte(&mut self, address: u32, value: u8) {
+        let index = (address - ORIGIN) as usize;
+        if self.rom.len() <= index {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = value;
+    }
+
+    fn write_word(&mut self, address: u32, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.write_byte(address, high);
+        self.write_byte(address + 1, low);
+    }
+}
+
+const BINARY_OPERATORS: [&str; 19] = ["+", "-", "*", "/", "%", "pow", "min", "max", "&", "|", "^", "<<", ">>", "<", ">", "<=", ">=", "==", "!="];
+
+fn register_number(token: &str) -> Option<u8> {
+    let digit = token.strip_prefix('v').or_else(|| token.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+// Decimal, 0x hexadecimal or 0b binary, optionally negative.
+fn parse_number(token: &str) -> Option<f64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{Chip8, Register};
+
+    fn opcodes(source: &str) -> Vec<u16> {
+        let program = assemble(source).unwrap();
+        program.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect()
+    }
+
+    // Runs the program until it settles in its final `loop again`.
+    fn run(source: &str) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load(assemble(source).unwrap());
+        chip8.start();
+        chip8.run_frame(200);
+        chip8
+    }
+
+    #[test]
+    fn test_main_first_should_start_at_0x200() {
+        assert_eq!(opcodes(": main clear loop again"), vec![0x00E0, 0x1202]);
+    }
+
+    #[test]
+    fn test_main_after_subroutines_should_be_jumped_to() {
+        assert_eq!(opcodes(": blank clear ; : main blank loop again"), vec![0x1206, 0x00E0, 0x00EE, 0x2202, 0x1208]);
+    }
+
+    #[test]
+    fn test_forward_references_should_be_patched() {
+        assert_eq!(opcodes(": main i := data jump done : done ; : data 0x3C 0b10000001"), vec![0xA206, 0x1204, 0x00EE, 0x3C81]);
+    }
+
+    #[test]
+    fn test_register_operations_should_pick_the_instruction_by_operand() {
+        let source = ": main v0 := v1 v0 := 5 v0 += 1 v0 -= 1 v0 += v1 v0 -= v1 v0 =- v1 v0 >>= v1 v0 <<= v1 v0 := random 0xFF v0 := key v0 := delay";
+
+        assert_eq!(
+            opcodes(source),
+            vec![0x8010, 0x6005, 0x7001, 0x70FF, 0x8014, 0x8015, 0x8017, 0x8016, 0x801E, 0xC0FF, 0xF00A, 0xF007],
+        );
+    }
+
+    #[test]
+    fn test_index_and_memory_instructions_should_be_assembled() {
+        let source = ": main i := 0x300 i += v2 i := hex v2 i := bighex v2 i := long 0x1234 bcd v2 save v3 load v3 save v1 - v4 load v1 - v4";
+
+        assert_eq!(
+            opcodes(source),
+            vec![0xA300, 0xF21E, 0xF229, 0xF230, 0xF000, 0x1234, 0xF233, 0xF355, 0xF365, 0x5142, 0x5143],
+        );
+    }
+
+    #[test]
+    fn test_loops_should_jump_back_and_exit_past_again() {
+        assert_eq!(opcodes(": main loop v0 += 1 while v0 != 5 again clear"), vec![0x7001, 0x4005, 0x1208, 0x1200, 0x00E0]);
+    }
+
+    #[test]
+    fn test_if_then_should_skip_when_the_condition_does_not_hold() {
+        assert_eq!(opcodes(": main if v0 == 3 then clear"), vec![0x4003, 0x00E0]);
+    }
+
+    #[test]
+    fn test_if_begin_else_end_should_jump_around_the_branches() {
+        assert_eq!(
+            opcodes(": main if v0 key begin v1 := 1 else v1 := 2 end clear"),
+            vec![0xE09E, 0x1208, 0x6101, 0x120A, 0x6102, 0x00E0],
+        );
+    }
+
+    #[test]
+    fn test_comparisons_should_hold_exactly_when_true() {
+        type Holds = fn(u8, u8) -> bool;
+        let comparisons: [(&str, Holds); 6] = [
+            ("==", |a, b| a == b),
+            ("!=", |a, b| a != b),
+            ("<", |a, b| a < b),
+            (">", |a, b| a > b),
+            ("<=", |a, b| a <= b),
+            (">=", |a, b| a >= b),
+        ];
+        for (operator, holds) in comparisons {
+            for (a, b) in [(0, 0), (3, 7), (7, 3), (255, 0), (0, 255), (128, 128)] {
+                let source = format!(
+                    ": main v0 := {a} v1 := {b}
+                     if v0 {operator} v1 then v2 := 1
+                     if v0 {operator} {b} then v3 := 1
+                     if v0 {operator} v1 begin v4 := 1 else v4 := 2 end
+                     loop again"
+                );
+
+                let chip8 = run(&source);
+
+                let expected = holds(a, b) as u16;
+                let context = format!("{} {} {}", a, operator, b);
+                assert_eq!(chip8.read_register(Register::V(2)), expected, "{}", context);
+                assert_eq!(chip8.read_register(Register::V(3)), expected, "{}", context);
+                assert_eq!(chip8.read_register(Register::V(4)), 2 - expected, "{}", context);
+            }
+        }
+    }
+
+    #[test]
+    fn test_constants_and_calc_should_be_evaluated_right_to_left() {
+        let source = ": main :const WIDTH 8 :calc AREA { WIDTH * 2 + 2 } :calc MASKED { ( 0xF3 & ~ 0x0F ) >> 4 } v0 := AREA v1 := MASKED :byte { WIDTH - 1 }";
+
+        assert_eq!(assemble(source).unwrap(), vec![0x60, 0x20, 0x61, 0x0F, 0x07]);
+    }
+
+    #[test]
+    fn test_macros_should_substitute_their_arguments() {
+        let source = ": main :macro numbered A { v0 := A v1 := CALLS } numbered 7 numbered 9";
+
+        assert_eq!(opcodes(source), vec![0x6007, 0x6100, 0x6009, 0x6101]);
+    }
+
+    #[test]
+    fn test_unpack_should_load_an_address_into_v0_and_v1() {
+        assert_eq!(opcodes(": main :unpack 0xA data :unpack long data : data"), vec![0x60A2, 0x6108, 0x6002, 0x6108]);
+    }
+
+    #[test]
+    fn test_next_should_name_the_byte_of_the_following_instruction() {
+        assert_eq!(opcodes(": main :next target v0 := 0 i := target"), vec![0x6000, 0xA201]);
+    }
+
+    #[test]
+    fn test_missing_main_should_be_an_error() {
+        assert_eq!(assemble(": start clear").unwrap_err().message, "the program has no main label");
+    }
+
+    #[test]
+    fn test_unsupported_directives_should_be_errors() {
+        let error = assemble(": main\n  :assert \"never\" { 0 }\n").unwrap_err();
+
+        assert_eq!(error, AssembleError { line: 2, message: ":assert is not supported".to_string() });
+    }
+
+    #[test]
+    fn test_undefined_names_should_be_reported_at_their_line() {
+        let error = assemble(": main\n  clear\n  jump nowhere\n").unwrap_err();
+
+        assert_eq!(error, AssembleError { line: 3, message: "undefined name nowhere".to_string() });
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use macroquad::color::{Color, BLACK, WHITE, YELLOW};
+use macroquad::input::{is_key_pressed, KeyCode};
+use macroquad::shapes::draw_rectangle;
+use macroquad::text::draw_text;
+use macroquad::window::{screen_height, screen_width};
+use rusted_chip8::rom_database::RomDatabase;
+use rusted_chip8::rom_loader;
+
+const FONT_SIZE: f32 = 20.0;
+const LINE_HEIGHT: f32 = 22.0;
+const MARGIN: f32 = 20.0;
+
+struct RomEntry {
+    path: PathBuf,
+    label: String,
+}
+
+/**
+Lists the ROMs of a directory so one can be picked with the arrow keys and Enter.
+Entries known by the ROM database are labelled with their title, release and authors.
+ */
+pub struct RomBrowser {
+    directory: PathBuf,
+    entries: Vec<RomEntry>,
+    selected: usize,
+}
+
+impl RomBrowser {
+    pub fn open(directory: &Path, database: &RomDatabase) -> io::Result<RomBrowser> {
+        let mut entries = Vec::new();
+        for dir_entry in fs::read_dir(directory)? {
+            let path = dir_entry?.path();
+            if !path.is_file() || !is_rom_file(&path) {
+                continue;
+            }
+
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let rom = rom_loader::load_file(&path).ok();
+            let label = match rom.and_then(|rom| database.lookup(&rom.program)) {
+                Some(metadata) => {
+                    let release = metadata.release.unwrap_or("?".to_string());
+                    format!("{} ({}, {})", metadata.title, metadata.authors.join(", "), release)
+                }
+                None => file_name,
+            };
+            entries.push(RomEntry { path, label });
+        }
+        entries.sort_by_key(|entry| entry.label.to_lowercase());
+
+        Ok(RomBrowser {
+            directory: directory.to_path_buf(),
+            entries,
+            selected: 0,
+        })
+    }
+
+    // Handles navigation and returns the ROM to load once the selection is confirmed.
+    pub fn update(&mut self) -> Option<PathBuf> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        if is_key_pressed(KeyCode::Down) {
+            self.selected = (self.selected + 1).min(self.entries.len() - 1);
+        }
+
+        if is_key_pressed(KeyCode::Up) {
+            self.selected = self.selected.saturating_sub(1);
+        }
+
+        if is_key_pressed(KeyCode::Enter) {
+            return Some(self.entries[self.selected].path.clone());
+        }
+
+        None
+    }
+
+    pub fn draw(&self) {
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.9));
+
+        let title = format!("ROMs in {}  (Enter: load, Esc: close)", self.directory.display());
+        draw_text(&title, MARGIN, MARGIN + FONT_SIZE, FONT_SIZE, YELLOW);
+
+        if self.entries.is_empty() {
+            draw_text("No ROMs found", MARGIN, MARGIN + 2.0 * LINE_HEIGHT + FONT_SIZE, FONT_SIZE, WHITE);
+            return;
+        }
+
+        let first_row_y = MARGIN + 2.0 * LINE_HEIGHT;
+        let visible_rows = (((screen_height() - first_row_y - MARGIN) / LINE_HEIGHT) as usize).max(1);
+        let first_visible = (self.selected + 1).saturating_sub(visible_rows);
+
+        for (row, entry) in self.entries.iter().enumerate().skip(first_visible).take(visible_rows) {
+            let y = first_row_y + (row - first_visible) as f32 * LINE_HEIGHT;
+            if row == self.selected {
+                draw_rectangle(MARGIN - 4.0, y, screen_width() - 2.0 * MARGIN, LINE_HEIGHT, WHITE);
+                draw_text(&entry.label, MARGIN, y + FONT_SIZE - 4.0, FONT_SIZE, BLACK);
+            } else {
+                draw_text(&entry.label, MARGIN, y + FONT_SIZE - 4.0, FONT_SIZE, WHITE);
+            }
+        }
+    }
+}
+
+fn is_rom_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(extension) => {
+            let extension = extension.to_string_lossy().to_lowercase();
+            rom_loader::EXTENSIONS.contains(&extension.as_str())
+        }
+        None => false,
+    }
+}
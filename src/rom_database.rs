@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use crate::chip8::{Platform, Quirks};
+
+const BUNDLED_PROGRAMS: &str = include_str!("../assets/chip-8-database/programs.json");
+const BUNDLED_HASHES: &str = include_str!("../assets/chip-8-database/sha1-hashes.json");
+
+/**
+ROM metadata from the community chip-8-database (https://github.com/chip-8/chip-8-database).
+ROMs are identified by the SHA-1 of their contents.
+ */
+pub struct RomDatabase {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    description: Option<String>,
+    release: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+impl QuirkOverrides {
+    fn apply(&self, quirks: &mut Quirks) {
+        quirks.shift = self.shift.unwrap_or(quirks.shift);
+        quirks.memory_increment_by_x = self.memory_increment_by_x.unwrap_or(quirks.memory_increment_by_x);
+        quirks.memory_leave_i_unchanged = self.memory_leave_i_unchanged.unwrap_or(quirks.memory_leave_i_unchanged);
+        quirks.wrap = self.wrap.unwrap_or(quirks.wrap);
+        quirks.jump = self.jump.unwrap_or(quirks.jump);
+        quirks.vblank = self.vblank.unwrap_or(quirks.vblank);
+        quirks.logic = self.logic.unwrap_or(quirks.logic);
+    }
+}
+
+/**
+Everything known about a ROM, with the recommended settings already resolved:
+the first platform the database lists for it, that platform's quirks with the ROM's
+overrides applied, and the ROM's tickrate or the platform default.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomMetadata {
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub authors: Vec<String>,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub tickrate: u32,
+    // CHIP-8 key for each logical input ("up", "down", "left", "right", "a", "b").
+    pub keys: HashMap<String, u8>,
+}
+
+impl RomDatabase {
+    pub fn bundled() -> RomDatabase {
+        RomDatabase::from_json(BUNDLED_PROGRAMS, BUNDLED_HASHES)
+            .expect("Bundled chip-8-database should be valid")
+    }
+
+    // Builds a database from the contents of `programs.json` and `sha1-hashes.json`.
+    pub fn from_json(programs: &str, hashes: &str) -> Result<RomDatabase, serde_json::Error> {
+        Ok(RomDatabase {
+            programs: serde_json::from_str(programs)?,
+            hashes: serde_json::from_str(hashes)?,
+        })
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomMetadata> {
+        let hash = sha1_hex(rom);
+        let program = self.programs.get(*self.hashes.get(&hash)?)?;
+        let rom = program.roms.get(&hash)?;
+
+        let platform_id = rom.platforms.iter().find(|id| Platform::from_id(id).is_some());
+        let platform = platform_id.and_then(|id| Platform::from_id(id)).unwrap_or_default();
+
+        let mut quirks = platform.quirks();
+        if let Some(overrides) = platform_id.and_then(|id| rom.quirky_platforms.get(id)) {
+            overrides.apply(&mut quirks);
+        }
+
+        Some(RomMetadata {
+            title: program.title.clone(),
+            description: program.description.clone(),
+            release: program.release.clone(),
+            authors: program.authors.clone(),
+            platform,
+            quirks,
+            tickrate: rom.tickrate.unwrap_or(platform.default_tickrate()),
+            keys: rom.keys.clone(),
+        })
+    }
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r#"[
+        {
+            "title": "Some Game",
+            "description": "A game.",
+            "release": "1991",
+            "authors": ["Someone"],
+            "roms": {
+                "a9993e364706816aba3e25717850c26c9cd0d89d": {
+                    "platforms": ["superchip", "xochip"],
+                    "tickrate": 50,
+                    "keys": {"up": 5, "a": 6},
+                    "quirkyPlatforms": {"superchip": {"jump": false}}
+                }
+            }
+        },
+        {
+            "title": "Unknown Platform",
+            "roms": {
+                "356a192b7913b04c54574d18c28d46e6395428ab": {
+                    "platforms": ["somethingElse"]
+                }
+            }
+        }
+    ]"#;
+    const HASHES: &str = r#"{
+        "a9993e364706816aba3e25717850c26c9cd0d89d": 0,
+        "356a192b7913b04c54574d18c28d46e6395428ab": 1
+    }"#;
+
+    #[test]
+    fn test_sha1_hex() {
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn test_lookup_should_resolve_platform_quirks_and_tickrate() {
+        let database = RomDatabase::from_json(PROGRAMS, HASHES).unwrap();
+
+        let metadata = database.lookup(b"abc").unwrap();
+
+        assert_eq!(metadata.title, "Some Game");
+        assert_eq!(metadata.release.as_deref(), Some("1991"));
+        assert_eq!(metadata.authors, vec!["Someone"]);
+        assert_eq!(metadata.platform, Platform::SuperChip);
+        assert_eq!(metadata.quirks, Quirks { jump: false, ..Platform::SuperChip.quirks() });
+        assert_eq!(metadata.tickrate, 50);
+        assert_eq!(metadata.keys.get("up"), Some(&5));
+    }
+
+    #[test]
+    fn test_lookup_should_fall_back_to_default_platform() {
+        let database = RomDatabase::from_json(PROGRAMS, HASHES).unwrap();
+
+        let metadata = database.lookup(b"1").unwrap();
+
+        assert_eq!(metadata.platform, Platform::default());
+        assert_eq!(metadata.tickrate, Platform::default().default_tickrate());
+    }
+
+    #[test]
+    fn test_lookup_should_not_find_unknown_roms() {
+        let database = RomDatabase::from_json(PROGRAMS, HASHES).unwrap();
+
+        assert_eq!(database.lookup(b"unknown"), None);
+    }
+
+    #[test]
+    fn test_bundled_database_should_identify_bundled_roms() {
+        let rom = std::fs::read("roms/Particle Demo [zeroZshadow, 2008].ch8").unwrap();
+
+        let metadata = RomDatabase::bundled().lookup(&rom).unwrap();
+
+        assert_eq!(metadata.title, "Particle Demo");
+        assert_eq!(metadata.platform, Platform::OriginalChip8);
+    }
+
+    #[test]
+    fn test_bundled_hashes_should_point_at_programs_listing_them() {
+        let database = RomDatabase::bundled();
+
+        for (hash, index) in &database.hashes {
+            let program = &database.programs[*index];
+            assert!(program.roms.contains_key(hash), "{} is not a ROM of {}", hash, program.title);
+        }
+        for (index, program) in database.programs.iter().enumerate() {
+            for hash in program.roms.keys() {
+                assert_eq!(database.hashes.get(hash), Some(&index), "{} of {} is not indexed", hash, program.title);
+            }
+        }
+    }
+}
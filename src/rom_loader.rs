@@ -0,0 +1,435 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::Deserialize;
+use crate::chip8::{AddressOutOfRange, Platform, Quirks};
+use crate::octo::{self, AssembleError};
+
+// File extensions of the ROMs `load_file` can run.
+pub const EXTENSIONS: [&str; 9] = ["ch8", "c8", "c8h", "c8e", "c8x", "sc8", "xo8", "hex", "gif"];
+
+const GIF_SIGNATURES: [&[u8]; 2] = [b"GIF87a", b"GIF89a"];
+
+// Hi-res CHIP-8 programs start by jumping to the machine code that enables the 64x64 mode.
+const HIRES_SIGNATURE: [u8; 2] = [0x12, 0x60];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RomFormat {
+    Binary,
+    HexListing,
+    OctoCartridge,
+}
+
+/**
+A program ready to be loaded with `Chip8::load`, plus whatever settings its container specifies.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct Rom {
+    pub format: RomFormat,
+    pub program: Vec<u8>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub tickrate: Option<u32>,
+    // Where the program has to be loaded and started, when it is not the platform default.
+    pub start_address: Option<u16>,
+}
+
+/**
+The contents of an Octo cartridge: the Octo assembly source of the program and
+the settings it was saved with.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct OctoCartridge {
+    pub source: String,
+    pub platform: Option<Platform>,
+    pub quirks: Quirks,
+    pub tickrate: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Unrecognised,
+    InvalidHexListing { line: usize, token: String },
+    InvalidCartridge(String),
+    // The Octo source of a cartridge uses something `octo::assemble` does not support, or is wrong.
+    InvalidOctoSource(AssembleError),
+    // The ROM starts past the end of the memory of the platform it runs on.
+    AddressOutOfRange(AddressOutOfRange),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Unrecognised => write!(f, "unrecognised ROM format"),
+            LoadError::InvalidHexListing { line, token } => {
+                write!(f, "invalid byte '{}' in hex listing at line {}", token, line)
+            }
+            LoadError::InvalidCartridge(reason) => write!(f, "invalid Octo cartridge: {}", reason),
+            LoadError::InvalidOctoSource(e) => write!(f, "Octo cartridge source cannot be assembled: {}", e),
+            LoadError::AddressOutOfRange(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+pub fn load_file(path: &Path) -> Result<Rom, LoadError> {
+    let data = fs::read(path)?;
+    let extension = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+
+    load(&extension, &data)
+}
+
+/**
+Identifies a ROM by its GIF header, its file extension, or by looking like a hex listing:
+
+```text
+.ch8 .c8     CHIP-8 binary, or hi-res CHIP-8 if it starts with 1260
+.c8h         Hi-res CHIP-8 binary
+.c8e         CHIP-8E binary
+.c8x         CHIP-8X binary
+.sc8         SUPER-CHIP binary
+.xo8         XO-CHIP binary
+.hex         Hex listing, see `parse_hex_listing`
+.gif         Octo cartridge
+```
+
+GIF files are Octo cartridges whatever their extension. They are decoded with `decode_cartridge`
+and their source is assembled with `octo::assemble`.
+ */
+pub fn load(extension: &str, data: &[u8]) -> Result<Rom, LoadError> {
+    if GIF_SIGNATURES.iter().any(|signature| data.starts_with(signature)) {
+        let cartridge = decode_cartridge(data)?;
+        return Ok(Rom {
+            format: RomFormat::OctoCartridge,
+            program: octo::assemble(&cartridge.source).map_err(LoadError::InvalidOctoSource)?,
+            platform: cartridge.platform,
+            quirks: Some(cartridge.quirks),
+            tickrate: cartridge.tickrate,
+            start_address: None,
+        });
+    }
+
+    let platform = match extension {
+        "ch8" | "c8" if data.starts_with(&HIRES_SIGNATURE) => Some(Platform::HiResChip8),
+        "ch8" | "c8" => None,
+        "c8h" => Some(Platform::HiResChip8),
+        "c8e" => Some(Platform::Chip8E),
+        "c8x" => Some(Platform::Chip8X),
+        "sc8" => Some(Platform::SuperChip),
+        "xo8" => Some(Platform::XoChip),
+        "hex" => return parse_hex_listing(data),
+        _ => return parse_hex_listing(data).map_err(|_| LoadError::Unrecognised),
+    };
+
+    Ok(Rom {
+        format: RomFormat::Binary,
+        program: data.to_vec(),
+        platform,
+        quirks: None,
+        tickrate: None,
+        start_address: None,
+    })
+}
+
+/**
+Parses a program written as hex bytes separated by whitespace, like:
+
+```text
+# Clear the screen and loop forever
+0200: 00E0
+0202: 12 02
+```
+
+Tokens may contain several bytes and an optional `0x` prefix. Tokens ending in `:` are
+addresses. The first one, when it comes before any byte, is where the program is loaded and
+started; the others are ignored, as is everything after a `#` or `;`.
+ */
+pub fn parse_hex_listing(data: &[u8]) -> Result<Rom, LoadError> {
+    let text = std::str::from_utf8(data).map_err(|_| LoadError::Unrecognised)?;
+
+    let mut program = Vec::new();
+    let mut start_address = None;
+    for (line_index, line) in text.lines().enumerate() {
+        let code = line.split(['#', ';']).next().unwrap_or_default();
+        for token in code.split_whitespace() {
+            let invalid = || LoadError::InvalidHexListing { line: line_index + 1, token: token.to_string() };
+
+            if let Some(address) = token.strip_suffix(':') {
+                if program.is_empty() && start_address.is_none() {
+                    let digits = address.strip_prefix("0x").unwrap_or(address);
+                    start_address = Some(u16::from_str_radix(digits, 16).map_err(|_| invalid())?);
+                }
+                continue;
+            }
+
+            let digits = token.strip_prefix("0x").unwrap_or(token);
+            if digits.is_empty() || digits.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            for i in (0..digits.len()).step_by(2) {
+                let byte = digits.get(i..i + 2).ok_or_else(invalid)?;
+                program.push(u8::from_str_radix(byte, 16).map_err(|_| invalid())?);
+            }
+        }
+    }
+
+    if program.is_empty() {
+        return Err(LoadError::Unrecognised);
+    }
+
+    Ok(Rom {
+        format: RomFormat::HexListing,
+        program,
+        platform: None,
+        quirks: None,
+        tickrate: None,
+        start_address,
+    })
+}
+
+#[derive(Deserialize)]
+struct CartridgePayload {
+    program: String,
+    #[serde(default)]
+    options: CartridgeOptions,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct CartridgeOptions {
+    tickrate: Option<u32>,
+    max_size: Option<u32>,
+    #[serde(default)]
+    shift_quirks: bool,
+    #[serde(default)]
+    load_store_quirks: bool,
+    #[serde(default)]
+    clip_quirks: bool,
+    #[serde(default)]
+    jump_quirks: bool,
+    #[serde(default)]
+    logic_quirks: bool,
+    #[serde(default)]
+    v_blank_quirks: bool,
+}
+
+/**
+Decodes an Octo cartridge: a GIF whose pixels carry a payload in the low two bits of
+their colour indices, most significant bits first, across all frames in order.
+The payload is a 32 bit big endian length followed by that many bytes of JSON:
+
+```text
+{ "program": "<octo source>", "options": { "tickrate": 20, "shiftQuirks": false, ... } }
+```
+ */
+pub fn decode_cartridge(data: &[u8]) -> Result<OctoCartridge, LoadError> {
+    let invalid = |reason: &str| LoadError::InvalidCartridge(reason.to_string());
+
+    let mut decoder = gif::DecodeOptions::new()
+        .read_info(data)
+        .map_err(|e| LoadError::InvalidCartridge(e.to_string()))?;
+
+    let mut pairs: Vec<u8> = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| LoadError::InvalidCartridge(e.to_string()))? {
+        pairs.extend(frame.buffer.iter().map(|index| index & 0b11));
+    }
+
+    let bytes: Vec<u8> = pairs
+        .chunks_exact(4)
+        .map(|pair| (pair[0] << 6) | (pair[1] << 4) | (pair[2] << 2) | pair[3])
+        .collect();
+    if bytes.len() < 4 {
+        return Err(invalid("missing payload length"));
+    }
+
+    let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let json = bytes.get(4..4 + length).ok_or_else(|| invalid("truncated payload"))?;
+    let payload: CartridgePayload =
+        serde_json::from_slice(json).map_err(|e| LoadError::InvalidCartridge(e.to_string()))?;
+
+    let options = payload.options;
+    let platform = match options.max_size {
+        Some(3216) => Some(Platform::OriginalChip8),
+        Some(3583) => Some(Platform::SuperChip),
+        Some(65024) => Some(Platform::XoChip),
+        _ => None,
+    };
+
+    Ok(OctoCartridge {
+        source: payload.program,
+        platform,
+        quirks: Quirks {
+            shift: options.shift_quirks,
+            memory_leave_i_unchanged: options.load_store_quirks,
+            wrap: !options.clip_quirks,
+            jump: options.jump_quirks,
+            logic: options.logic_quirks,
+            vblank: options.v_blank_quirks,
+            ..Quirks::default()
+        },
+        tickrate: options.tickrate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encodes a payload the way Octo does, into a single frame GIF.
+    fn a_cartridge(json: &str) -> Vec<u8> {
+        let mut bytes = (json.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(json.as_bytes());
+
+        let mut pixels = Vec::new();
+        for byte in bytes {
+            for shift in [6, 4, 2, 0] {
+                // Any colour in the high bits, the payload is in the low two bits.
+                pixels.push(0b1000_0000 | ((byte >> shift) & 0b11));
+            }
+        }
+        let width = 64u16;
+        pixels.resize(pixels.len().div_ceil(width as usize) * width as usize, 0);
+        let height = (pixels.len() / width as usize) as u16;
+
+        let palette = vec![0u8; 256 * 3];
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, width, height, &palette).unwrap();
+            let frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+
+        gif
+    }
+
+    #[test]
+    fn test_binary_rom_should_keep_program_as_is() {
+        let rom = load("ch8", &[0x00, 0xE0]).unwrap();
+
+        assert_eq!(rom.format, RomFormat::Binary);
+        assert_eq!(rom.program, vec![0x00, 0xE0]);
+        assert_eq!(rom.platform, None);
+    }
+
+    #[test]
+    fn test_extension_should_select_platform() {
+        assert_eq!(load("c8x", &[0x00]).unwrap().platform, Some(Platform::Chip8X));
+        assert_eq!(load("sc8", &[0x00]).unwrap().platform, Some(Platform::SuperChip));
+        assert_eq!(load("xo8", &[0x00]).unwrap().platform, Some(Platform::XoChip));
+        assert_eq!(load("c8e", &[0x00]).unwrap().platform, Some(Platform::Chip8E));
+        assert_eq!(load("c8h", &[0x00]).unwrap().platform, Some(Platform::HiResChip8));
+    }
+
+    #[test]
+    fn test_hires_program_should_be_recognised_by_its_first_jump() {
+        assert_eq!(load("ch8", &[0x12, 0x60, 0x00]).unwrap().platform, Some(Platform::HiResChip8));
+        assert_eq!(load("ch8", &[0x12, 0x62, 0x00]).unwrap().platform, None);
+    }
+
+    #[test]
+    fn test_hex_listing_should_be_parsed() {
+        let listing = b"# Clear and loop\n0200: 00E0 ; cls\n0202: 0x12 02\n";
+
+        let rom = load("hex", listing).unwrap();
+
+        assert_eq!(rom.format, RomFormat::HexListing);
+        assert_eq!(rom.program, vec![0x00, 0xE0, 0x12, 0x02]);
+        assert_eq!(rom.start_address, Some(0x200));
+    }
+
+    #[test]
+    fn test_hex_listing_should_be_recognised_without_extension() {
+        let rom = load("txt", b"00E0 1202").unwrap();
+
+        assert_eq!(rom.format, RomFormat::HexListing);
+        assert_eq!(rom.program, vec![0x00, 0xE0, 0x12, 0x02]);
+        assert_eq!(rom.start_address, None);
+    }
+
+    #[test]
+    fn test_hex_listing_should_start_at_its_first_address() {
+        let rom = load("hex", b"0x600: 00E0\n0602: 1602\n").unwrap();
+
+        assert_eq!(rom.start_address, Some(0x600));
+    }
+
+    #[test]
+    fn test_hex_listing_should_report_invalid_tokens() {
+        match load("hex", b"00E0\n12G2") {
+            Err(LoadError::InvalidHexListing { line, token }) => {
+                assert_eq!(line, 2);
+                assert_eq!(token, "12G2");
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_binary_should_be_unrecognised() {
+        assert!(matches!(load("bin", &[0x00, 0xE0]), Err(LoadError::Unrecognised)));
+    }
+
+    #[test]
+    fn test_octo_cartridge_should_be_decoded() {
+        let gif = a_cartridge(
+            r#"{"program": ": main\n  loop again", "options": {"tickrate": 20, "maxSize": 3583, "shiftQuirks": true, "clipQuirks": true}}"#,
+        );
+
+        let cartridge = decode_cartridge(&gif).unwrap();
+
+        assert_eq!(cartridge.source, ": main\n  loop again");
+        assert_eq!(cartridge.platform, Some(Platform::SuperChip));
+        assert_eq!(cartridge.tickrate, Some(20));
+        assert_eq!(cartridge.quirks, Quirks { shift: true, ..Quirks::default() });
+    }
+
+    #[test]
+    fn test_octo_cartridge_should_be_detected_by_header() {
+        let gif = a_cartridge(r#"{"program": ": main loop again"}"#);
+
+        let rom = load("ch8", &gif).unwrap();
+
+        assert_eq!(rom.format, RomFormat::OctoCartridge);
+        assert_eq!(rom.program, vec![0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_octo_cartridge_should_be_assembled_with_its_settings() {
+        let gif = a_cartridge(
+            r#"{"program": ": main\n  v0 := 7\n  i := digit\n  loop again\n: digit 0xF0", "options": {"tickrate": 20, "maxSize": 3583, "shiftQuirks": true}}"#,
+        );
+
+        let rom = load("gif", &gif).unwrap();
+
+        assert_eq!(rom.program, vec![0x60, 0x07, 0xA2, 0x06, 0x12, 0x04, 0xF0]);
+        assert_eq!(rom.platform, Some(Platform::SuperChip));
+        assert_eq!(rom.quirks, Some(Quirks { shift: true, wrap: true, ..Quirks::default() }));
+        assert_eq!(rom.tickrate, Some(20));
+    }
+
+    #[test]
+    fn test_octo_cartridge_with_invalid_source_should_report_its_line() {
+        let gif = a_cartridge(r#"{"program": ": main\n  v0 := 300"}"#);
+
+        match load("gif", &gif) {
+            Err(LoadError::InvalidOctoSource(e)) => assert_eq!(e.line, 2),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_truncated_cartridge_should_be_invalid() {
+        let mut gif = a_cartridge(r#"{"program": ": main"}"#);
+        gif.truncate(16);
+
+        assert!(matches!(decode_cartridge(&gif), Err(LoadError::InvalidCartridge(_))));
+    }
+}